@@ -2,20 +2,21 @@
 // AUDIO INTERFACING
 // -------------------------------------------------------------------------------------------------
 
-use std::error::Error;
 use log::info;
 use portaudio as pa;
-use portaudio::{InputStreamSettings, OutputStreamSettings, PortAudio};
+use portaudio::{DeviceIndex, DuplexStreamSettings, InputStreamSettings, OutputStreamSettings, PortAudio};
 use portaudio::stream::Parameters;
+use crate::libs::error::error::QdxError;
 
 // PortAudio constants
 const INTERLEAVED: bool = true;
 const LATENCY: pa::Time = 0.0; // Ignored by PortAudio::is_*_format_supported.
+const CHANNELS: i32 = 2; // The duplex callback expects interleaved stereo.
 pub(crate) const FRAMES_PER_BUFFER: u32 = 64; // May have to increase this to 1024
 pub(crate) const SAMPLE_RATE: f64 = 48000.0;
 
 
-pub fn list_audio_devices(pa: &PortAudio) -> Result<i32, Box<dyn Error>> {
+pub fn list_audio_devices(pa: &PortAudio) -> Result<i32, QdxError> {
     let num_devices = pa.device_count()?;
     info!("Number of audio devices = {}", num_devices);
 
@@ -36,43 +37,124 @@ pub fn list_audio_devices(pa: &PortAudio) -> Result<i32, Box<dyn Error>> {
     Ok(0)
 }
 
-pub fn get_qdx_input_device(pa: &PortAudio) -> Result<(InputStreamSettings<f32>, Parameters<f32>), Box<dyn Error>> {
+fn is_usable_input(pa: &PortAudio, idx: DeviceIndex, in_channels: i32) -> bool {
+    let input_params = pa::StreamParameters::<f32>::new(idx, CHANNELS, INTERLEAVED, LATENCY);
+    in_channels >= CHANNELS && pa.is_input_format_supported(input_params, SAMPLE_RATE).is_ok()
+}
+
+fn is_usable_output(pa: &PortAudio, idx: DeviceIndex, out_channels: i32) -> bool {
+    let output_params = pa::StreamParameters::<f32>::new(idx, CHANNELS, INTERLEAVED, LATENCY);
+    out_channels >= CHANNELS && pa.is_output_format_supported(output_params, SAMPLE_RATE).is_ok()
+}
+
+// Finds a device given a specification from the command line, which may be a PortAudio device
+// index (as shown by ListAudioDevices), an exact device name, or an unambiguous part of a device
+// name. Names are compared case-insensitively. Only devices that can handle stereo at 48kHz in the
+// required direction are candidates.
+fn find_device(pa: &PortAudio, device_spec: &str, is_input: bool) -> Result<DeviceIndex, QdxError> {
+    let direction = if is_input { "input" } else { "output" };
+    let mut candidates: Vec<(DeviceIndex, String)> = vec![];
     for device in pa.devices()? {
         let (idx, info) = device?;
+        let usable = if is_input {
+            is_usable_input(pa, idx, info.max_input_channels)
+        } else {
+            is_usable_output(pa, idx, info.max_output_channels)
+        };
+        if usable {
+            candidates.push((idx, info.name.to_string()));
+        }
+    }
 
-        let in_channels = info.max_input_channels;
-        let input_params = pa::StreamParameters::<f32>::new(idx, in_channels, INTERLEAVED, LATENCY);
-        let in_48k_supported = pa.is_input_format_supported(input_params, SAMPLE_RATE).is_ok();
-        let is_qdx_input = in_channels == 2 && in_48k_supported && info.name.contains("QDX");
-        if is_qdx_input {
-            info!("Using {:?} as QDX input device", info);
-            let settings = InputStreamSettings::new(input_params, SAMPLE_RATE, FRAMES_PER_BUFFER);
-            return Ok((settings, input_params));
+    if let Ok(index) = device_spec.parse::<u32>() {
+        if let Some((idx, name)) = candidates.iter().find(|(idx, _)| idx.0 == index) {
+            info!("Audio {} device index {} is {:?}", direction, index, name);
+            return Ok(*idx);
         }
     }
-    Err(Box::<dyn Error + Send + Sync>::from("Can't find QDX input device"))
+
+    let lower_spec = device_spec.to_lowercase();
+    if let Some((idx, _)) = candidates.iter().find(|(_, name)| name.to_lowercase() == lower_spec) {
+        return Ok(*idx);
+    }
+
+    let partial_matches: Vec<&(DeviceIndex, String)> = candidates.iter()
+        .filter(|(_, name)| name.to_lowercase().contains(&lower_spec))
+        .collect();
+    match partial_matches.len() {
+        1 => Ok(partial_matches[0].0),
+        0 => Err(QdxError::DeviceNotFound(format!("Can't find audio {} device matching '{}'; candidates are:\n{}",
+            direction, device_spec, describe_devices(candidates.iter())))),
+        _ => Err(QdxError::DeviceAmbiguous(format!("Audio {} device '{}' is ambiguous; it matches:\n{}",
+            direction, device_spec, describe_devices(partial_matches.into_iter())))),
+    }
 }
 
-pub fn is_speaker_name(x: &str) -> bool {
-    x.eq_ignore_ascii_case("built-in output") || x.eq_ignore_ascii_case("macbook pro speakers") ||
-        x.eq_ignore_ascii_case("speakers (realtek high definition audio")
-    // a poor heuristic since there are several "realtek" devices, and the second one in the list
-    // works - need to assess the DeviceInfo better on windows
+fn describe_devices<'a>(devices: impl Iterator<Item = &'a (DeviceIndex, String)>) -> String {
+    let descriptions: Vec<String> = devices.map(|(idx, name)| format!("  {}: {:?}", idx.0, name)).collect();
+    if descriptions.is_empty() {
+        "  (none)".to_string()
+    } else {
+        descriptions.join("\n")
+    }
 }
 
-pub fn get_speaker_output_device(pa: &PortAudio) -> Result<(OutputStreamSettings<f32>, Parameters<f32>), Box<dyn Error>> {
+fn find_qdx_input_device(pa: &PortAudio) -> Result<DeviceIndex, QdxError> {
     for device in pa.devices()? {
         let (idx, info) = device?;
 
-        let out_channels = info.max_output_channels;
-        let output_params =
-            pa::StreamParameters::<f32>::new(idx, out_channels, INTERLEAVED, LATENCY);
-        let out_48k_supported = pa.is_output_format_supported(output_params, SAMPLE_RATE).is_ok();
-        if is_speaker_name(info.name) && out_channels == 2 && out_48k_supported {
-            info!("Using {:?} as audio output device", info);
-            let settings = OutputStreamSettings::new(output_params, SAMPLE_RATE, FRAMES_PER_BUFFER);
-            return Ok((settings, output_params));
+        if info.name.contains("QDX") && is_usable_input(pa, idx, info.max_input_channels) {
+            return Ok(idx);
         }
     }
-    Err(Box::<dyn Error + Send + Sync>::from("Can't find speaker output device"))
+    Err(QdxError::DeviceNotFound("Can't find QDX input device".to_string()))
+}
+
+// If a device_spec is given, it is used to find the input device; otherwise the first suitable
+// device with QDX in its name is used.
+pub fn get_qdx_input_device(pa: &PortAudio, device_spec: Option<&str>) -> Result<(InputStreamSettings<f32>, Parameters<f32>), QdxError> {
+    let idx = match device_spec {
+        Some(spec) => find_device(pa, spec, true)?,
+        None => find_qdx_input_device(pa)?,
+    };
+    info!("Using {:?} as QDX input device", pa.device_info(idx)?);
+    let input_params = pa::StreamParameters::<f32>::new(idx, CHANNELS, INTERLEAVED, LATENCY);
+    let settings = InputStreamSettings::new(input_params, SAMPLE_RATE, FRAMES_PER_BUFFER);
+    Ok((settings, input_params))
+}
+
+// If a device_spec is given, it is used to find the output device; otherwise the host's default
+// output device is used.
+pub fn get_speaker_output_device(pa: &PortAudio, device_spec: Option<&str>) -> Result<(OutputStreamSettings<f32>, Parameters<f32>), QdxError> {
+    let idx = match device_spec {
+        Some(spec) => find_device(pa, spec, false)?,
+        None => {
+            let idx = match pa.default_output_device() {
+                Ok(idx) => idx,
+                Err(e) => {
+                    return Err(QdxError::DeviceNotFound(format!("Can't find default speaker output device: {}", e)));
+                }
+            };
+            let info = pa.device_info(idx)?;
+            if !is_usable_output(pa, idx, info.max_output_channels) {
+                return Err(QdxError::AudioFormatUnsupported(format!("Default output device {:?} can't play stereo at {}Hz; choose another with --audioout",
+                    info.name, SAMPLE_RATE)));
+            }
+            idx
+        }
+    };
+    info!("Using {:?} as audio output device", pa.device_info(idx)?);
+    let output_params = pa::StreamParameters::<f32>::new(idx, CHANNELS, INTERLEAVED, LATENCY);
+    let settings = OutputStreamSettings::new(output_params, SAMPLE_RATE, FRAMES_PER_BUFFER);
+    Ok((settings, output_params))
+}
+
+// The settings for the Receiver's duplex stream, from the QDX to the speaker/headphones.
+pub fn get_duplex_stream_settings(pa: &PortAudio, rig_in_device_spec: Option<&str>, audio_out_device_spec: Option<&str>) -> Result<DuplexStreamSettings<f32, f32>, QdxError> {
+    info!("Initialising QDX input device...");
+    let (_qdx_input, qdx_params) = get_qdx_input_device(pa, rig_in_device_spec)?;
+    info!("Initialising speaker output device...");
+    let (_speaker_output, speaker_params) = get_speaker_output_device(pa, audio_out_device_spec)?;
+    pa.is_duplex_format_supported(qdx_params, speaker_params, SAMPLE_RATE)?;
+    Ok(DuplexStreamSettings::new(qdx_params, speaker_params, SAMPLE_RATE, FRAMES_PER_BUFFER))
 }
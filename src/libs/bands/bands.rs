@@ -0,0 +1,42 @@
+// -------------------------------------------------------------------------------------------------
+// AMATEUR RADIO BANDS
+// -------------------------------------------------------------------------------------------------
+
+// (metres, lower edge Hz, upper edge Hz) - the edges are the widest of the IARU region allocations,
+// so that a frequency can be associated with a band wherever you are.
+const BAND_EDGES: [(u8, u32, u32); 10] = [
+    (80,  3_500_000,  4_000_000),
+    (60,  5_250_000,  5_450_000),
+    (40,  7_000_000,  7_300_000),
+    (30, 10_100_000, 10_150_000),
+    (20, 14_000_000, 14_350_000),
+    (17, 18_068_000, 18_168_000),
+    (15, 21_000_000, 21_450_000),
+    (12, 24_890_000, 24_990_000),
+    (11, 26_965_000, 27_405_000),
+    (10, 28_000_000, 29_700_000),
+];
+
+// Which band (in metres) is this frequency in, if any?
+pub fn band_for_frequency(frequency_hz: u32) -> Option<u8> {
+    BAND_EDGES.iter()
+        .find(|(_, lower, upper)| frequency_hz >= *lower && frequency_hz <= *upper)
+        .map(|(metres, _, _)| *metres)
+}
+
+// The FT8 frequency of each band, used when there's no better idea of where to tune on it.
+pub fn default_band_frequency(metres: u8) -> u32 {
+    match metres {
+        80 =>  3_573_000,
+        60 =>  5_357_000,
+        40 =>  7_074_000,
+        30 => 10_136_000,
+        20 => 14_074_000,
+        17 => 18_100_000,
+        15 => 21_074_000,
+        12 => 24_915_000,
+        11 => 27_255_000, // Maybe?
+        10 => 28_180_000,
+        _ => 14_074_000, // default to 20m
+    }
+}
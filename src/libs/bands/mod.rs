@@ -0,0 +1 @@
+pub mod bands;
\ No newline at end of file
@@ -2,69 +2,183 @@
 // CAT - COMPUTER AIDED TRANSCEIVER
 // -------------------------------------------------------------------------------------------------
 
-use std::error::Error;
 use std::time::Duration;
-use log::{debug, info};
+use log::{debug, info, warn};
 use regex::Regex;
-use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits};
+use crate::libs::error::error::{is_timeout, QdxError};
+use crate::libs::transport::transport::Transport;
+
+// The TS-480 commands, with the length of their read request. Most are read with just the command
+// (e.g. FA;), but some take a parameter saying what to read (e.g. SM0; for the main S-meter).
+// Requests of any other length are sets, which aren't answered; None marks those that never are.
+const TS480_READ_LENGTHS: [(&str, Option<usize>); 60] = [
+    ("AC", Some(3)), ("AG", Some(4)), ("AI", Some(3)), ("AN", Some(3)), ("BC", Some(3)), ("BD", None),
+    ("BU", None), ("BY", Some(3)), ("CA", Some(3)), ("CH", None), ("CN", Some(3)), ("CT", Some(3)),
+    ("DN", None), ("EX", Some(10)), ("FA", Some(3)), ("FB", Some(3)), ("FR", Some(3)), ("FS", Some(3)),
+    ("FT", Some(3)), ("FW", Some(3)), ("GT", Some(3)), ("ID", Some(3)), ("IF", Some(3)), ("IS", Some(3)),
+    ("KS", Some(3)), ("KY", Some(3)), ("LK", Some(3)), ("LM", Some(3)), ("MC", Some(3)), ("MD", Some(3)),
+    ("MF", Some(3)), ("MG", Some(3)), ("ML", Some(3)), ("MR", Some(7)), ("MW", None), ("MX", Some(3)),
+    ("NB", Some(3)), ("NL", Some(3)), ("NR", Some(3)), ("NT", Some(3)), ("PA", Some(3)), ("PC", Some(3)),
+    ("PR", Some(3)), ("PS", Some(3)), ("RA", Some(3)), ("RC", None), ("RD", None), ("RG", Some(3)),
+    ("RM", Some(3)), ("RT", Some(3)), ("RU", None), ("RX", None), ("SH", Some(3)), ("SL", Some(3)),
+    ("SM", Some(4)), ("SQ", Some(4)), ("TX", None), ("VX", Some(3)), ("XI", Some(3)), ("XT", Some(3)),
+];
+
+// Sets aren't answered, unless the QDX doesn't implement them, when it replies ?; soon after.
+const SET_REJECTION_TIMEOUT: Duration = Duration::from_millis(50);
+
+// Operating modes, as used by the TS-480's MD and IF commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RigMode {
+    Lsb,
+    Usb,
+    Cw,
+    Fm,
+    Am,
+    Fsk,
+    CwReverse,
+    FskReverse,
+}
+
+impl RigMode {
+    pub fn from_code(code: char) -> Option<RigMode> {
+        match code {
+            '1' => Some(RigMode::Lsb),
+            '2' => Some(RigMode::Usb),
+            '3' => Some(RigMode::Cw),
+            '4' => Some(RigMode::Fm),
+            '5' => Some(RigMode::Am),
+            '6' => Some(RigMode::Fsk),
+            '7' => Some(RigMode::CwReverse),
+            '9' => Some(RigMode::FskReverse),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> char {
+        match self {
+            RigMode::Lsb => '1',
+            RigMode::Usb => '2',
+            RigMode::Cw => '3',
+            RigMode::Fm => '4',
+            RigMode::Am => '5',
+            RigMode::Fsk => '6',
+            RigMode::CwReverse => '7',
+            RigMode::FskReverse => '9',
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RigMode::Lsb => "LSB",
+            RigMode::Usb => "USB",
+            RigMode::Cw => "CW",
+            RigMode::Fm => "FM",
+            RigMode::Am => "AM",
+            RigMode::Fsk => "FSK",
+            RigMode::CwReverse => "CW-R",
+            RigMode::FskReverse => "FSK-R",
+        }
+    }
+}
+
+// VFOs, as used by the TS-480's FR, FT and IF commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vfo {
+    A,
+    B,
+    Memory,
+}
+
+impl Vfo {
+    pub fn from_code(code: char) -> Option<Vfo> {
+        match code {
+            '0' => Some(Vfo::A),
+            '1' => Some(Vfo::B),
+            '2' => Some(Vfo::Memory),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> char {
+        match self {
+            Vfo::A => '0',
+            Vfo::B => '1',
+            Vfo::Memory => '2',
+        }
+    }
+}
+
+// The parts of the IF (information) response that the QDX reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfStatus {
+    pub frequency_hz: u32,
+    pub rit_xit_offset_hz: i32,
+    pub rit_on: bool,
+    pub xit_on: bool,
+    pub transmitting: bool,
+    pub mode: RigMode,
+    pub vfo: Vfo,
+    pub split: bool,
+}
 
 pub struct Cat {
-    serial_port: Box<dyn SerialPort>,
+    transport: Box<dyn Transport>,
 }
 
 impl Cat {
-    pub fn new(port_name: String) -> Result<Cat, Box<dyn Error>> {
-        info!("Opening serial port {}", port_name);
-        let settings = SerialPortSettings {
-            baud_rate: 38400, // it's irrelevant over USB
-            data_bits: DataBits::Eight,
-            flow_control: FlowControl::Hardware,
-            parity: Parity::None,
-            stop_bits: StopBits::One,
-            timeout: Duration::from_millis(250)
-        };
-        return match serialport::open_with_settings(&port_name, &settings) {
-            Ok(serial_port) => {
-                info!("Port open");
-                let cat = Self {
-                    serial_port,
-                };
-                Ok(cat)
-            }
-            Err(e) => {
-                Err(Box::<dyn Error + Send + Sync>::from(format!("Failed to open serial port {}: {}", port_name, e)))
-            }
-        };
+    pub fn new(transport: Box<dyn Transport>) -> Cat {
+        Self {
+            transport,
+        }
     }
 
     // Synchronous.. Expects request to be a valid ;-terminated CAT command; an Ok response contains
     // a valid ;-terminated CAT response.
-    fn transact(&mut self, request: &str) -> Result<String, Box<dyn Error>> {
+    fn transact(&mut self, request: &str) -> Result<String, QdxError> {
         self.send_request(request)?;
         self.receive_response(request)
     }
 
-    fn send_request(&mut self, request: &str) -> Result<(), Box<dyn Error>> {
+    fn send_request(&mut self, request: &str) -> Result<(), QdxError> {
         let request_bytes = request.as_bytes();
         if request.len() < 3 {
-            return Err(Box::<dyn Error + Send + Sync>::from(format!("A CAT request must be at least 3 characters long, this is {}", request_bytes.len())));
+            return Err(QdxError::InvalidRequest(format!("A CAT request must be at least 3 characters long, this is {}", request_bytes.len())));
         }
         debug!("Sending CAT request '{}'", request);
-        let result = self.serial_port.write(request_bytes)?;
+        let result = self.transport.write(request_bytes)?;
         if result != request_bytes.len() {
-            return Err(Box::<dyn Error + Send + Sync>::from(format!("Expected to write {} bytes to QDX; wrote {}", request_bytes.len(), result)));
+            return Err(QdxError::Disconnected(format!("Expected to write {} bytes to QDX; wrote {}", request_bytes.len(), result)));
         }
         Ok(())
     }
 
     // Precondition: send_request has been used to validate and send the request.
-    fn receive_response(&mut self, request: &str) -> Result<String, Box<dyn Error>> {
+    fn receive_response(&mut self, request: &str) -> Result<String, QdxError> {
         let request_bytes = request.as_bytes();
+        let response = self.receive_reply()?;
+        if response.len() < 2 {
+            Err(QdxError::MalformedResponse(format!("QDX response was too short: '{}'", response)))
+        } else {
+            let response_bytes = response.as_bytes();
+            if response_bytes[0] != request_bytes[0] || response_bytes[1] != request_bytes[1] {
+                Err(QdxError::MismatchedResponse { request: request.to_string(), response })
+            } else {
+                debug!("Received CAT response '{}'", response);
+                Ok(response)
+            }
+        }
+    }
 
+    // Whatever the QDX sends, up to and including the next ;.
+    fn receive_reply(&mut self) -> Result<String, QdxError> {
         let mut received: Vec<u8> = vec![];
         loop {
             let mut byte = [0u8; 1];
-            match self.serial_port.read(&mut byte) {
+            match self.transport.read(&mut byte) {
+                Ok(0) => {
+                    return Err(QdxError::Disconnected("Connection to QDX closed".to_string()));
+                }
                 Ok(n) => {
                     assert!(n == 1);
                     debug!("Received CAT response byte '{}'", byte[0]);
@@ -74,56 +188,477 @@ impl Cat {
                         break;
                     }
                 }
+                Err(err) if is_timeout(&err) => {
+                    return Err(QdxError::Timeout(format!("No response from QDX: {}", err)));
+                }
                 Err(err) => {
-                    return Err(Box::<dyn Error + Send + Sync>::from(format!("No response from QDX: {}", err)));
+                    return Err(QdxError::Disconnected(format!("Lost connection to QDX: {}", err)));
                 }
             }
         }
         match std::str::from_utf8(received.as_slice()) {
-            Ok(response) => {
-                if response.len() < 2 {
-                    Err(Box::<dyn Error + Send + Sync>::from(format!("QDX response was too short: '{}'", response)))
-                } else {
-                    let response_bytes = response.as_bytes();
-                    if response_bytes[0] != request_bytes[0] && response_bytes[1] != request_bytes[1] {
-                        Err(Box::<dyn Error + Send + Sync>::from(format!("QDX response did not match request: '{}'", response)))
-                    } else {
-                        let response_string = response.to_string();
-                        debug!("Received CAT response '{}'", response_string);
-                        Ok(response_string)
-                    }
-                }
-            }
+            Ok(response) => Ok(response.to_string()),
             Err(err) => {
-                Err(Box::<dyn Error + Send + Sync>::from(format!("Could not convert QDX response to String: {}", err)))
+                Err(QdxError::MalformedResponse(format!("Could not convert QDX response to String: {}", err)))
             }
         }
     }
 
-    pub fn get_frequency(&mut self) -> Result<u32, Box<dyn Error>> {
-        let fa_response = self.transact("FA;")?;
-        let fa_response_regex = Regex::new(r"FA(\d{11});").unwrap();
-        match fa_response_regex.captures(fa_response.as_str()) {
+    // For sets that the QDX may not implement: the ?; it would reply with is collected here, rather
+    // than being taken as the response to the next request.
+    fn send_checked_request(&mut self, request: &str) -> Result<(), QdxError> {
+        self.send_request(request)?;
+        let timeout = self.transport.timeout();
+        self.transport.set_timeout(SET_REJECTION_TIMEOUT)?;
+        let reply = self.receive_reply();
+        self.transport.set_timeout(timeout)?;
+        match reply {
+            Err(QdxError::Timeout(_)) => Ok(()),
+            Ok(reply) if reply == "?;" => Err(QdxError::InvalidRequest(format!("QDX rejected '{}'", request))),
+            Ok(reply) => Err(QdxError::MismatchedResponse { request: request.to_string(), response: reply }),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Sends the request, and checks that the response matches the pattern, returning the pattern's
+    // capture groups.
+    fn query(&mut self, request: &str, pattern: &str, description: &str) -> Result<Vec<String>, QdxError> {
+        let response = self.transact(request)?;
+        let response_regex = Regex::new(pattern).unwrap();
+        match response_regex.captures(response.as_str()) {
             Some(captures) => {
-                let freq_string = captures.get(1).unwrap().as_str();
-                let freq = str::parse::<u32>(freq_string)?;
-                debug!("get_frequency returning {}", freq);
-                Ok(freq)
+                Ok(captures.iter().skip(1).map(|c| c.map(|m| m.as_str().to_string()).unwrap_or_default()).collect())
             },
-            None => Err(Box::<dyn Error + Send + Sync>::from(format!("Unexpected 'frequency' response: '{}'", fa_response)))
+            None => Err(QdxError::MalformedResponse(format!("Unexpected '{}' response: '{}'", description, response)))
+        }
+    }
+
+    fn parse_field<T: std::str::FromStr>(field: &str, description: &str) -> Result<T, QdxError> {
+        field.trim().parse::<T>()
+            .map_err(|_| QdxError::MalformedResponse(format!("Unexpected '{}' value: '{}'", description, field)))
+    }
+
+    fn frequency_command(vfo: Vfo) -> Result<&'static str, QdxError> {
+        match vfo {
+            Vfo::A => Ok("FA"),
+            Vfo::B => Ok("FB"),
+            Vfo::Memory => Err(QdxError::InvalidRequest("Memory channels have no frequency command".to_string())),
         }
     }
 
-    pub fn set_frequency(&mut self, frequency_hz: u32) -> Result<(), Box<dyn Error>> {
-        self.send_request(format!("FA{};", frequency_hz).as_str())?;
+    // The frequency of VFO A.
+    pub fn get_frequency(&mut self) -> Result<u32, QdxError> {
+        self.get_vfo_frequency(Vfo::A)
+    }
+
+    pub fn set_frequency(&mut self, frequency_hz: u32) -> Result<(), QdxError> {
+        self.set_vfo_frequency(Vfo::A, frequency_hz)
+    }
+
+    pub fn get_vfo_frequency(&mut self, vfo: Vfo) -> Result<u32, QdxError> {
+        let command = Self::frequency_command(vfo)?;
+        let fields = self.query(format!("{};", command).as_str(), format!(r"^{}(\d{{11}});$", command).as_str(), "frequency")?;
+        let freq = Self::parse_field::<u32>(&fields[0], "frequency")?;
+        debug!("get_vfo_frequency {:?} returning {}", vfo, freq);
+        Ok(freq)
+    }
+
+    pub fn set_vfo_frequency(&mut self, vfo: Vfo, frequency_hz: u32) -> Result<(), QdxError> {
+        let command = Self::frequency_command(vfo)?;
+        self.send_request(format!("{}{:011};", command, frequency_hz).as_str())?;
         Ok(())
     }
+
+    pub fn get_if_status(&mut self) -> Result<IfStatus, QdxError> {
+        // P1 frequency, P2 (unused), P3 RIT/XIT offset, P4 RIT, P5 XIT, P6/P7 memory channel, P8 RX/TX,
+        // P9 mode, P10 VFO, P11 scan, P12 split, P13/P14 tone, P15 (unused). Unused or unreported
+        // fields may be spaces.
+        let fields = self.query("IF;", r"^IF(\d{11}).{5}([+-]\d{4}|\s{5})([01 ])([01 ]).{3}([01])(\d)([012])(.)([01 ]).{4};$", "IF status")?;
+        let mode_code = fields[5].chars().next().unwrap();
+        let vfo_code = fields[6].chars().next().unwrap();
+        let rit_xit_offset = fields[1].trim();
+        let status = IfStatus {
+            frequency_hz: Self::parse_field::<u32>(&fields[0], "IF frequency")?,
+            rit_xit_offset_hz: if rit_xit_offset.is_empty() { 0 } else { Self::parse_field::<i32>(rit_xit_offset, "IF RIT/XIT offset")? },
+            rit_on: fields[2] == "1",
+            xit_on: fields[3] == "1",
+            transmitting: fields[4] == "1",
+            mode: RigMode::from_code(mode_code)
+                .ok_or_else(|| QdxError::MalformedResponse(format!("Unexpected 'IF mode' value: '{}'", mode_code)))?,
+            vfo: Vfo::from_code(vfo_code)
+                .ok_or_else(|| QdxError::MalformedResponse(format!("Unexpected 'IF VFO' value: '{}'", vfo_code)))?,
+            split: fields[8] == "1",
+        };
+        debug!("get_if_status returning {:?}", status);
+        Ok(status)
+    }
+
+    pub fn get_mode(&mut self) -> Result<RigMode, QdxError> {
+        let fields = self.query("MD;", r"^MD(\d);$", "mode")?;
+        let code = fields[0].chars().next().unwrap();
+        RigMode::from_code(code).ok_or_else(|| QdxError::MalformedResponse(format!("Unexpected 'mode' value: '{}'", code)))
+    }
+
+    pub fn set_mode(&mut self, mode: RigMode) -> Result<(), QdxError> {
+        self.send_request(format!("MD{};", mode.code()).as_str())
+    }
+
+    fn get_vfo_selection(&mut self, command: &str) -> Result<Vfo, QdxError> {
+        let fields = self.query(format!("{};", command).as_str(), format!(r"^{}([012]);$", command).as_str(), "VFO")?;
+        let code = fields[0].chars().next().unwrap();
+        Ok(Vfo::from_code(code).unwrap())
+    }
+
+    // The VFO used for receiving (FR).
+    pub fn get_receive_vfo(&mut self) -> Result<Vfo, QdxError> {
+        self.get_vfo_selection("FR")
+    }
+
+    pub fn set_receive_vfo(&mut self, vfo: Vfo) -> Result<(), QdxError> {
+        self.send_request(format!("FR{};", vfo.code()).as_str())
+    }
+
+    // The VFO used for transmitting (FT).
+    pub fn get_transmit_vfo(&mut self) -> Result<Vfo, QdxError> {
+        self.get_vfo_selection("FT")
+    }
+
+    pub fn set_transmit_vfo(&mut self, vfo: Vfo) -> Result<(), QdxError> {
+        self.send_request(format!("FT{};", vfo.code()).as_str())
+    }
+
+    // The radio's model number; the TS-480 is 020, which the QDX emulates.
+    pub fn get_identification(&mut self) -> Result<u32, QdxError> {
+        let fields = self.query("ID;", r"^ID(\d{3});$", "identification")?;
+        Self::parse_field::<u32>(&fields[0], "identification")
+    }
+
+    pub fn is_transmitting(&mut self) -> Result<bool, QdxError> {
+        Ok(self.get_if_status()?.transmitting)
+    }
+
+    pub fn get_rit(&mut self) -> Result<bool, QdxError> {
+        let fields = self.query("RT;", r"^RT([01]);$", "RIT")?;
+        Ok(fields[0] == "1")
+    }
+
+    pub fn set_rit(&mut self, on: bool) -> Result<(), QdxError> {
+        self.send_request(if on { "RT1;" } else { "RT0;" })
+    }
+
+    // The TS-480's main receiver AF gain (AG0), 0 to 255. Not all QDX firmware implements it.
+    pub fn get_audio_gain(&mut self) -> Result<u8, QdxError> {
+        let fields = self.query("AG0;", r"^AG0(\d{3});$", "audio gain")?;
+        let gain = Self::parse_field::<u8>(&fields[0], "audio gain")?;
+        debug!("get_audio_gain returning {}", gain);
+        Ok(gain)
+    }
+
+    pub fn set_audio_gain(&mut self, gain: u8) -> Result<(), QdxError> {
+        self.send_checked_request(format!("AG0{:03};", gain).as_str())
+    }
+
+    // Sends a request from another program unchanged, returning the reply if it's one that
+    // should have one: reads are answered, sets aren't. The reply is passed on as it is, so a QDX
+    // that doesn't implement a read can say so with ?;. Commands that aren't in the TS-480 table
+    // get whatever the QDX sends back before the timeout, if anything.
+    pub fn pass_through(&mut self, request: &str) -> Result<Option<String>, QdxError> {
+        let request_bytes = request.as_bytes();
+        if request_bytes.len() < 3 || !request_bytes[0].is_ascii_alphabetic() || !request_bytes[1].is_ascii_alphabetic() || !request.ends_with(';') {
+            return Err(QdxError::InvalidRequest(format!("Not a CAT request: '{}'", request)));
+        }
+        let command = &request[0..2];
+        self.send_request(request)?;
+        match TS480_READ_LENGTHS.iter().find(|(read_command, _)| *read_command == command) {
+            Some((_, read_length)) if *read_length == Some(request.len()) => self.receive_reply().map(Some),
+            Some(_) => Ok(None),
+            None => match self.receive_reply() {
+                Ok(reply) => Ok(Some(reply)),
+                Err(QdxError::Timeout(_)) => Ok(None),
+                Err(err) => Err(err),
+            }
+        }
+    }
 }
 
 impl Drop for Cat {
     fn drop(&mut self) {
-        info!("Flushing serial port");
-        self.serial_port.flush().expect("Could not flush");
+        info!("Flushing CAT transport");
+        // The QDX may have been unplugged, so this can't be relied upon.
+        if let Err(err) = self.transport.flush() {
+            warn!("Could not flush CAT transport: {}", err);
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::cat::cat::{Cat, IfStatus, RigMode, Vfo};
+    use crate::libs::error::error::QdxError;
+    use crate::libs::transport::transport::MockTransport;
+
+    fn cat_with(transport: &MockTransport) -> Cat {
+        Cat::new(Box::new(transport.clone()))
+    }
+
+    #[test]
+    fn transact_returns_response() {
+        let transport = MockTransport::new();
+        transport.expect("ID;", "ID020;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.transact("ID;").unwrap(), "ID020;");
+        assert_eq!(transport.written(), "ID;");
+    }
+
+    #[test]
+    fn transact_rejects_short_request() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        assert!(matches!(cat.transact("F;"), Err(QdxError::InvalidRequest(_))));
+        assert_eq!(transport.written(), "");
+    }
+
+    #[test]
+    fn receive_response_times_out_with_no_response() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        let err = cat.receive_response("FA;").unwrap_err();
+        assert!(matches!(err, QdxError::Timeout(_)), "{}", err);
+    }
+
+    #[test]
+    fn receive_response_times_out_with_unterminated_response() {
+        let transport = MockTransport::new();
+        transport.respond("FA000140");
+        let mut cat = cat_with(&transport);
+        let err = cat.receive_response("FA;").unwrap_err();
+        assert!(matches!(err, QdxError::Timeout(_)), "{}", err);
+    }
+
+    #[test]
+    fn receive_response_rejects_short_response() {
+        let transport = MockTransport::new();
+        transport.respond(";");
+        let mut cat = cat_with(&transport);
+        let err = cat.receive_response("FA;").unwrap_err();
+        assert!(matches!(err, QdxError::MalformedResponse(_)), "{}", err);
+    }
+
+    #[test]
+    fn receive_response_rejects_mismatched_prefix() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        for response in ["FB00014074000;", "IA00014074000;", "ID020;"] {
+            transport.respond(response);
+            let err = cat.receive_response("FA;").unwrap_err();
+            assert!(matches!(err, QdxError::MismatchedResponse { .. }), "{}", err);
+        }
+    }
+
+    #[test]
+    fn receive_response_reports_lost_connection() {
+        let transport = MockTransport::new();
+        transport.close();
+        let mut cat = cat_with(&transport);
+        let err = cat.receive_response("FA;").unwrap_err();
+        assert!(matches!(err, QdxError::Disconnected(_)), "{}", err);
+        assert!(err.is_connection_lost());
+        assert!(cat.get_frequency().unwrap_err().is_connection_lost());
+    }
+
+    #[test]
+    fn get_frequency_parses_response() {
+        let transport = MockTransport::new();
+        transport.expect("FA;", "FA00014074000;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_frequency().unwrap(), 14074000);
+        assert!(transport.is_script_complete());
+    }
+
+    #[test]
+    fn get_frequency_rejects_malformed_response() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        for response in ["FA14074000;", "FA0001407400X;", "FA000140740000;"] {
+            transport.expect("FA;", response);
+            let err = cat.get_frequency().unwrap_err();
+            assert!(matches!(err, QdxError::MalformedResponse(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn get_frequency_times_out() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        assert!(matches!(cat.get_frequency(), Err(QdxError::Timeout(_))));
+        assert_eq!(transport.written(), "FA;");
+    }
+
+    #[test]
+    fn set_frequency_sends_request_without_waiting() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        cat.set_frequency(7074000).unwrap();
+        assert_eq!(transport.written(), "FA00007074000;");
+    }
+
+    #[test]
+    fn vfo_b_frequency() {
+        let transport = MockTransport::new();
+        transport.expect("FB;", "FB00010136000;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_vfo_frequency(Vfo::B).unwrap(), 10136000);
+        cat.set_vfo_frequency(Vfo::B, 10138000).unwrap();
+        assert_eq!(transport.written(), "FB;FB00010138000;");
+        assert!(matches!(cat.get_vfo_frequency(Vfo::Memory), Err(QdxError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn get_if_status_parses_response() {
+        let transport = MockTransport::new();
+        transport.expect("IF;", "IF00007074000     +00000000002000000 ;");
+        transport.expect("IF;", "IF00014074000     -01501001012101000 ;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_if_status().unwrap(), IfStatus {
+            frequency_hz: 7074000,
+            rit_xit_offset_hz: 0,
+            rit_on: false,
+            xit_on: false,
+            transmitting: false,
+            mode: RigMode::Usb,
+            vfo: Vfo::A,
+            split: false,
+        });
+        assert_eq!(cat.get_if_status().unwrap(), IfStatus {
+            frequency_hz: 14074000,
+            rit_xit_offset_hz: -150,
+            rit_on: true,
+            xit_on: false,
+            transmitting: true,
+            mode: RigMode::Usb,
+            vfo: Vfo::B,
+            split: true,
+        });
+    }
+
+    #[test]
+    fn get_if_status_rejects_malformed_response() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        for response in ["IF00007074000;", "IF00007074000     +00000000008000000 ;"] {
+            transport.expect("IF;", response);
+            let err = cat.get_if_status().unwrap_err();
+            assert!(matches!(err, QdxError::MalformedResponse(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn mode() {
+        let transport = MockTransport::new();
+        transport.expect("MD;", "MD2;");
+        transport.expect("MD;", "MD8;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_mode().unwrap(), RigMode::Usb);
+        assert!(matches!(cat.get_mode(), Err(QdxError::MalformedResponse(_))));
+        cat.set_mode(RigMode::Cw).unwrap();
+        assert_eq!(transport.written(), "MD;MD;MD3;");
+    }
+
+    #[test]
+    fn vfo_selection() {
+        let transport = MockTransport::new();
+        transport.expect("FR;", "FR0;");
+        transport.expect("FT;", "FT1;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_receive_vfo().unwrap(), Vfo::A);
+        assert_eq!(cat.get_transmit_vfo().unwrap(), Vfo::B);
+        cat.set_receive_vfo(Vfo::B).unwrap();
+        cat.set_transmit_vfo(Vfo::A).unwrap();
+        assert_eq!(transport.written(), "FR;FT;FR1;FT0;");
+    }
+
+    #[test]
+    fn identification() {
+        let transport = MockTransport::new();
+        transport.expect("ID;", "ID020;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_identification().unwrap(), 20);
+    }
+
+    #[test]
+    fn get_audio_gain_parses_response() {
+        let transport = MockTransport::new();
+        transport.expect("AG0;", "AG0128;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.get_audio_gain().unwrap(), 128);
+        assert!(transport.is_script_complete());
+    }
+
+    #[test]
+    fn get_audio_gain_rejects_malformed_response() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        for response in ["AG0256;", "AG012;", "AG1128;", "AG0;"] {
+            transport.expect("AG0;", response);
+            let err = cat.get_audio_gain().unwrap_err();
+            assert!(matches!(err, QdxError::MalformedResponse(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn set_audio_gain_sends_request() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        cat.set_audio_gain(7).unwrap();
+        cat.set_audio_gain(255).unwrap();
+        assert_eq!(transport.written(), "AG0007;AG0255;");
+    }
+
+    #[test]
+    fn set_audio_gain_collects_rejection() {
+        let transport = MockTransport::new();
+        transport.expect("AG0128;", "?;");
+        transport.expect("FA;", "FA00014074000;");
+        let mut cat = cat_with(&transport);
+        assert!(matches!(cat.set_audio_gain(128), Err(QdxError::InvalidRequest(_))));
+        // The rejection isn't taken as the next response.
+        assert_eq!(cat.get_frequency().unwrap(), 14074000);
+    }
+
+    #[test]
+    fn pass_through() {
+        let transport = MockTransport::new();
+        transport.expect("IF;", "IF00014074000     -01501001012101000 ;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.pass_through("IF;").unwrap(), Some("IF00014074000     -01501001012101000 ;".to_string()));
+        assert_eq!(cat.pass_through("FA00007074000;").unwrap(), None);
+        assert_eq!(cat.pass_through("TX;").unwrap(), None);
+        assert!(matches!(cat.pass_through("12;"), Err(QdxError::InvalidRequest(_))));
+        assert!(matches!(cat.pass_through("FA"), Err(QdxError::InvalidRequest(_))));
+        assert_eq!(transport.written(), "IF;FA00007074000;TX;");
+    }
+
+    #[test]
+    fn pass_through_reads_with_parameters() {
+        let transport = MockTransport::new();
+        transport.expect("SM0;", "SM00005;");
+        transport.expect("AG0;", "?;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.pass_through("SM0;").unwrap(), Some("SM00005;".to_string()));
+        // Firmware that doesn't implement a read says so.
+        assert_eq!(cat.pass_through("AG0;").unwrap(), Some("?;".to_string()));
+        assert_eq!(cat.pass_through("AG0128;").unwrap(), None);
+        assert!(matches!(cat.pass_through("SM0;"), Err(QdxError::Timeout(_))));
+    }
+
+    #[test]
+    fn pass_through_unknown_commands() {
+        let transport = MockTransport::new();
+        transport.expect("ZZ1;", "ZZ1;");
+        let mut cat = cat_with(&transport);
+        assert_eq!(cat.pass_through("ZZ1;").unwrap(), Some("ZZ1;".to_string()));
+        assert_eq!(cat.pass_through("ZZ2;").unwrap(), None);
+        assert_eq!(transport.written(), "ZZ1;ZZ2;");
+    }
+}
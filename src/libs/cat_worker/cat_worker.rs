@@ -0,0 +1,456 @@
+// -------------------------------------------------------------------------------------------------
+// CAT WORKER
+// -------------------------------------------------------------------------------------------------
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use crate::libs::cat::cat::Cat;
+use crate::libs::error::error::QdxError;
+use crate::libs::gui_api::gui_api::GUIInputMessage;
+use crate::libs::transport::transport::DisconnectedTransport;
+
+// How often the QDX is asked for its frequency, to notice it being changed by other CAT clients or
+// the front panel.
+const FREQUENCY_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+// A QDX that has been power-cycled may leave its port open but silent (or garbled), so this many
+// retryable failures in a row are treated as losing it.
+const RETRIES_BEFORE_CONNECTION_LOST: u32 = 3;
+
+// Commands waiting to be sent to the QDX. Each holds only the latest value requested, so a burst of
+// changes (e.g. spinning the mouse wheel) results in a single command once the QDX is free.
+#[derive(Default)]
+struct PendingCommands {
+    frequency: Option<u32>,
+    audio_gain: Option<u8>,
+}
+
+impl PendingCommands {
+    fn is_empty(&self) -> bool {
+        self.frequency.is_none() && self.audio_gain.is_none()
+    }
+}
+
+// The frequency the QDX should be on, restored on reconnection: the latest requested, or seen when
+// polling. The servers that set the QDX's frequency directly, rather than through the CatWorker,
+// record it here too.
+#[derive(Clone)]
+pub struct RememberedFrequency(Arc<AtomicU32>);
+
+impl RememberedFrequency {
+    pub fn new(frequency_hz: u32) -> Self {
+        Self(Arc::new(AtomicU32::new(frequency_hz)))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, frequency_hz: u32) {
+        self.0.store(frequency_hz, Ordering::SeqCst);
+    }
+}
+
+// Performs all CAT I/O on its own thread, so that callers (ultimately the GUI) never block on the
+// serial port. Failures are reported to the GUI rather than returned.
+pub struct CatWorker {
+    cat: Arc<Mutex<Cat>>,
+    pending: Arc<(Mutex<PendingCommands>, Condvar)>,
+    frequency: RememberedFrequency,
+    audio_gain: Mutex<Option<u8>>, // The latest audio gain requested, if any.
+    connection_lost: Arc<AtomicBool>,
+    thread_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl CatWorker {
+    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: u32, gui_input: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>) -> Self {
+        let pending = Arc::new((Mutex::new(PendingCommands::default()), Condvar::new()));
+        let thread_pending = pending.clone();
+        let remembered_frequency = RememberedFrequency::new(frequency);
+        let thread_frequency = remembered_frequency.clone();
+        let connection_lost = Arc::new(AtomicBool::new(false));
+        let thread_connection_lost = connection_lost.clone();
+        let thread_cat = cat.clone();
+        let thread_handle = thread::spawn(move || {
+            let mut known_frequency = frequency;
+            let mut last_error: Option<String> = None;
+            let mut consecutive_retries = 0;
+            let mut next_poll = Instant::now() + FREQUENCY_POLL_INTERVAL;
+            let (lock, condvar) = &*thread_pending;
+            loop {
+                // Nothing is sent while the connection is lost; commands stay pending until the
+                // Supervisor reconnects.
+                let mut waiting = lock.lock().unwrap();
+                while !terminate.load(Ordering::SeqCst) {
+                    let now = Instant::now();
+                    let lost = thread_connection_lost.load(Ordering::SeqCst);
+                    if !lost && (!waiting.is_empty() || now >= next_poll) {
+                        break;
+                    }
+                    let timeout = if lost { FREQUENCY_POLL_INTERVAL } else { next_poll - now };
+                    waiting = condvar.wait_timeout(waiting, timeout).unwrap().0;
+                }
+                drop(waiting);
+                if terminate.load(Ordering::SeqCst) {
+                    info!("Terminating CAT worker thread");
+                    break;
+                }
+
+                // The CAT lock is taken before the pending commands, so that any requested while
+                // waiting for it are coalesced too.
+                let mut cat = thread_cat.lock().unwrap();
+                let commands = std::mem::take(&mut *lock.lock().unwrap());
+                let mut outcome: Result<(), QdxError> = Ok(());
+                let mut changed_frequency: Option<u32> = None;
+                if let Some(frequency_hz) = commands.frequency {
+                    debug!("Setting QDX frequency to {}", frequency_hz);
+                    outcome = cat.set_frequency(frequency_hz);
+                    if outcome.is_ok() {
+                        known_frequency = frequency_hz;
+                    } else {
+                        // Otherwise the GUI would show it, but the QDX would never be set to it.
+                        // A newer one, requested meanwhile, replaces it.
+                        lock.lock().unwrap().frequency.get_or_insert(frequency_hz);
+                    }
+                }
+                if let Some(gain) = commands.audio_gain {
+                    if outcome.is_ok() {
+                        debug!("Setting QDX audio gain to {}", gain);
+                        outcome = cat.set_audio_gain(gain);
+                    }
+                    // One the QDX has rejected would only be rejected again.
+                    if matches!(&outcome, Err(err) if err.is_retryable() || err.is_connection_lost()) {
+                        lock.lock().unwrap().audio_gain.get_or_insert(gain);
+                    }
+                }
+                if outcome.is_ok() && Instant::now() >= next_poll {
+                    next_poll = Instant::now() + FREQUENCY_POLL_INTERVAL;
+                    match cat.get_frequency() {
+                        Ok(polled_frequency) => {
+                            // If the GUI has asked for another frequency meanwhile, that's about
+                            // to be sent, so there's no point showing this one. The pending lock
+                            // is held until it's stored, so it can't replace one asked for now.
+                            let waiting = lock.lock().unwrap();
+                            if polled_frequency != known_frequency && waiting.frequency.is_none() {
+                                info!("QDX frequency changed to {}", polled_frequency);
+                                known_frequency = polled_frequency;
+                                thread_frequency.set(polled_frequency);
+                                changed_frequency = Some(polled_frequency);
+                            }
+                        }
+                        Err(err) => {
+                            outcome = Err(err);
+                        }
+                    }
+                }
+                drop(cat);
+
+                // Sent once the CAT is released, so that the servers sharing it aren't held up if
+                // the GUI's channel is full.
+                if let Some(frequency_hz) = changed_frequency {
+                    Self::send(&gui_input, GUIInputMessage::Frequency(frequency_hz));
+                }
+                match outcome {
+                    Ok(()) => {
+                        consecutive_retries = 0;
+                        if last_error.take().is_some() {
+                            info!("CAT communication with QDX restored");
+                            Self::send(&gui_input, GUIInputMessage::CatRecovered);
+                        }
+                    }
+                    Err(err) => {
+                        if err.is_retryable() {
+                            consecutive_retries += 1;
+                        }
+                        if err.is_connection_lost() || consecutive_retries >= RETRIES_BEFORE_CONNECTION_LOST {
+                            warn!("CAT connection to QDX lost");
+                            consecutive_retries = 0;
+                            thread_connection_lost.store(true, Ordering::SeqCst);
+                        }
+                        // Only report each distinct failure once, rather than on every poll.
+                        let message = err.to_string();
+                        if last_error.as_ref() != Some(&message) {
+                            warn!("CAT failure: {}", message);
+                            Self::send(&gui_input, GUIInputMessage::CatError(message.clone(), err.advice()));
+                            last_error = Some(message);
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            cat,
+            pending,
+            frequency: remembered_frequency,
+            audio_gain: Mutex::new(None),
+            connection_lost,
+            thread_handle: Mutex::new(Some(thread_handle)),
+        }
+    }
+
+    fn send(gui_input: &Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>, message: GUIInputMessage) {
+        if let Some(gui_input) = gui_input.lock().unwrap().as_deref() {
+            let _ = gui_input.send(message);
+        }
+    }
+
+    fn submit<F: FnOnce(&mut PendingCommands)>(&self, update: F) {
+        let (lock, condvar) = &*self.pending;
+        update(&mut lock.lock().unwrap());
+        condvar.notify_one();
+    }
+
+    // The frequency is stored under the pending lock, so a poll can't overwrite it with what the
+    // QDX was on before it's sent.
+    pub fn set_frequency(&self, frequency_hz: u32) {
+        self.submit(|pending| {
+            self.frequency.set(frequency_hz);
+            pending.frequency = Some(frequency_hz);
+        });
+    }
+
+    pub fn remembered_frequency(&self) -> RememberedFrequency {
+        self.frequency.clone()
+    }
+
+    pub fn set_audio_gain(&self, gain: u8) {
+        *self.audio_gain.lock().unwrap() = Some(gain);
+        self.submit(|pending| pending.audio_gain = Some(gain));
+    }
+
+    pub fn is_connection_lost(&self) -> bool {
+        self.connection_lost.load(Ordering::SeqCst)
+    }
+
+    // Closes the connection, e.g. so that its port can be reopened. Until reconnected, nothing is
+    // sent, and anything else sharing the Cat finds it disconnected.
+    pub fn disconnect(&self) {
+        self.connection_lost.store(true, Ordering::SeqCst);
+        *self.cat.lock().unwrap() = Cat::new(Box::new(DisconnectedTransport));
+    }
+
+    // Replaces the lost connection with a newly-opened one, and returns the QDX to the frequency
+    // (and audio gain, if it's been set) it should be on.
+    pub fn reconnect(&self, cat: Cat) {
+        *self.cat.lock().unwrap() = cat;
+        self.connection_lost.store(false, Ordering::SeqCst);
+        self.set_frequency(self.frequency.get());
+        let audio_gain = *self.audio_gain.lock().unwrap();
+        if let Some(gain) = audio_gain {
+            self.set_audio_gain(gain);
+        }
+    }
+}
+
+impl Drop for CatWorker {
+    fn drop(&mut self) {
+        // Wake the thread, so it notices termination without waiting for the next poll.
+        self.pending.1.notify_one();
+        debug!("CatWorker joining thread handle...");
+        let mut thread_handle = self.thread_handle.lock().unwrap();
+        thread_handle.take().map(JoinHandle::join);
+        debug!("...CatWorker joined thread handle");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{Receiver, sync_channel};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::libs::cat::cat::Cat;
+    use crate::libs::cat_worker::cat_worker::{CatWorker, FREQUENCY_POLL_INTERVAL};
+    use crate::libs::gui_api::gui_api::GUIInputMessage;
+    use crate::libs::transport::transport::MockTransport;
+
+    // Long enough for a few polls, even on a busy machine.
+    const DEADLINE: Duration = Duration::from_secs(10);
+
+    // Terminates the worker when dropped, as the worker's own Drop waits for that.
+    struct TestWorker {
+        worker: Option<CatWorker>,
+        terminate: Arc<AtomicBool>,
+        receiver: Receiver<GUIInputMessage>,
+    }
+
+    impl TestWorker {
+        fn new(transport: &MockTransport) -> Self {
+            let (sender, receiver) = sync_channel(16);
+            let cat = Arc::new(Mutex::new(Cat::new(Box::new(transport.clone()))));
+            let gui_input = Arc::new(Mutex::new(Some(Arc::new(sender))));
+            let terminate = Arc::new(AtomicBool::new(false));
+            let worker = CatWorker::new(terminate.clone(), cat, 14074000, gui_input);
+            Self { worker: Some(worker), terminate, receiver }
+        }
+
+        fn worker(&self) -> &CatWorker {
+            self.worker.as_ref().unwrap()
+        }
+
+        fn next_message(&self) -> GUIInputMessage {
+            self.receiver.recv_timeout(DEADLINE).unwrap_or_else(|_| panic!("No message for the GUI"))
+        }
+
+        fn stop(&mut self) {
+            self.terminate.store(true, Ordering::SeqCst);
+            self.worker.take();
+        }
+    }
+
+    impl Drop for TestWorker {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        let deadline = Instant::now() + DEADLINE;
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn external_frequency_changes_are_shown_but_not_echoed() {
+        let transport = MockTransport::new();
+        transport.expect("FA;", "FA00007074000;");
+        let mut test_worker = TestWorker::new(&transport);
+        assert!(test_worker.next_message() == GUIInputMessage::Frequency(7074000));
+
+        // Still there at the next poll, so nothing more is shown, or sent back to the QDX.
+        transport.expect("FA;", "FA00007074000;");
+        assert!(wait_until(|| transport.is_script_complete()));
+        thread::sleep(FREQUENCY_POLL_INTERVAL / 2);
+        test_worker.stop();
+        assert!(test_worker.receiver.try_recv().is_err());
+        assert_eq!(transport.written(), "FA;FA;");
+    }
+
+    #[test]
+    fn failures_are_reported_once_then_recovery() {
+        let transport = MockTransport::new();
+        let test_worker = TestWorker::new(&transport);
+        assert!(matches!(test_worker.next_message(), GUIInputMessage::CatError(..)));
+
+        transport.expect("FA;", "FA00014074000;");
+        assert!(test_worker.next_message() == GUIInputMessage::CatRecovered);
+        assert!(!test_worker.worker().is_connection_lost());
+    }
+
+    #[test]
+    fn repeated_timeouts_lose_the_connection() {
+        let transport = MockTransport::new();
+        let test_worker = TestWorker::new(&transport);
+        assert!(matches!(test_worker.next_message(), GUIInputMessage::CatError(..)));
+        assert!(!test_worker.worker().is_connection_lost());
+        assert!(wait_until(|| test_worker.worker().is_connection_lost()));
+        // Each poll timed out, and the same failure was only reported once.
+        assert_eq!(transport.written(), "FA;FA;FA;");
+        assert!(test_worker.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn reconnecting_restores_the_frequency() {
+        let transport = MockTransport::new();
+        transport.close();
+        let test_worker = TestWorker::new(&transport);
+        assert!(matches!(test_worker.next_message(), GUIInputMessage::CatError(..)));
+        assert!(test_worker.worker().is_connection_lost());
+
+        // Changes made while disconnected wait for the new connection.
+        test_worker.worker().set_frequency(7074000);
+        let new_transport = MockTransport::new();
+        test_worker.worker().reconnect(Cat::new(Box::new(new_transport.clone())));
+        assert!(!test_worker.worker().is_connection_lost());
+        assert!(test_worker.next_message() == GUIInputMessage::CatRecovered);
+        assert_eq!(new_transport.written().replace("FA;", ""), "FA00007074000;");
+        assert_eq!(transport.written(), "");
+    }
+
+    #[test]
+    fn reconnecting_restores_a_frequency_set_elsewhere() {
+        let transport = MockTransport::new();
+        let test_worker = TestWorker::new(&transport);
+        // As by the rigctld server, directly through the shared Cat.
+        test_worker.worker().remembered_frequency().set(3573000);
+
+        let new_transport = MockTransport::new();
+        test_worker.worker().disconnect();
+        test_worker.worker().reconnect(Cat::new(Box::new(new_transport.clone())));
+        assert!(wait_until(|| new_transport.written().contains("FA00003573000;")));
+    }
+
+    #[test]
+    fn failed_frequency_changes_are_retried() {
+        let transport = MockTransport::new();
+        transport.time_out_next_write();
+        let test_worker = TestWorker::new(&transport);
+        test_worker.worker().set_frequency(7074000);
+        assert!(matches!(test_worker.next_message(), GUIInputMessage::CatError(..)));
+        assert!(test_worker.next_message() == GUIInputMessage::CatRecovered);
+        assert!(!test_worker.worker().is_connection_lost());
+        assert_eq!(transport.written().replace("FA;", ""), "FA00007074000;");
+    }
+
+    #[test]
+    fn audio_gain_changes_are_sent_and_restored_on_reconnection() {
+        let transport = MockTransport::new();
+        let test_worker = TestWorker::new(&transport);
+        test_worker.worker().set_audio_gain(64);
+        assert!(wait_until(|| transport.written().contains("AG0064;")));
+
+        let new_transport = MockTransport::new();
+        test_worker.worker().disconnect();
+        test_worker.worker().reconnect(Cat::new(Box::new(new_transport.clone())));
+        assert!(wait_until(|| new_transport.written().contains("AG0064;")));
+        assert_eq!(new_transport.written().replace("FA;", ""), "FA00014074000;AG0064;");
+    }
+
+    #[test]
+    fn disconnecting_closes_the_port() {
+        let transport = MockTransport::new();
+        let test_worker = TestWorker::new(&transport);
+        assert_eq!(transport.handle_count(), 2);
+        test_worker.worker().disconnect();
+        assert!(test_worker.worker().is_connection_lost());
+        assert_eq!(transport.handle_count(), 1);
+
+        // The same port can now be opened again.
+        test_worker.worker().reconnect(Cat::new(Box::new(transport.clone())));
+        assert!(wait_until(|| !transport.written().is_empty()));
+        assert_eq!(transport.written().replace("FA;", ""), "FA00014074000;");
+    }
+
+    #[test]
+    fn rapid_frequency_changes_are_coalesced() {
+        let transport = MockTransport::new();
+        let cat = Arc::new(Mutex::new(Cat::new(Box::new(transport.clone()))));
+        let terminate = Arc::new(AtomicBool::new(false));
+        let worker = CatWorker::new(terminate.clone(), cat.clone(), 14074000, Arc::new(Mutex::new(None)));
+
+        // Keep the worker waiting for the QDX while several changes are requested.
+        let busy = cat.lock().unwrap();
+        worker.set_frequency(14074010);
+        worker.set_frequency(14074020);
+        worker.set_frequency(14074030);
+        thread::sleep(Duration::from_millis(50));
+        drop(busy);
+        assert!(wait_until(|| !transport.written().is_empty()));
+
+        terminate.store(true, Ordering::SeqCst);
+        drop(worker);
+        // On a slow machine, the first poll may have been due by now too.
+        assert_eq!(transport.written().replace("FA;", ""), "FA00014074030;");
+    }
+}
@@ -0,0 +1 @@
+pub mod cat_worker;
\ No newline at end of file
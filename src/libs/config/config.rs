@@ -0,0 +1,483 @@
+// -------------------------------------------------------------------------------------------------
+// CONFIGURATION STORE
+// -------------------------------------------------------------------------------------------------
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use crate::libs::bands::bands::band_for_frequency;
+use crate::libs::dsp::agc::AgcSettings;
+use crate::libs::dsp::bandpass::BandpassSettings;
+use crate::libs::dsp::noise_blanker::NoiseBlankerSettings;
+use crate::libs::dsp::noise_reduction::NoiseReductionSettings;
+use crate::libs::dsp::notch::NotchSettings;
+use crate::libs::dsp::tune_assist::TuneAssistSettings;
+use crate::libs::gui_api::gui_api::{MeterMode, WaterfallPalette};
+use crate::libs::smeter::ballistics::MeterBallistics;
+
+const CONFIG_FILE_NAME: &str = "qdx-receiver.toml";
+// A configuration file that can't be read is renamed to this, so it can be looked at, and isn't
+// overwritten when the defaults used instead are saved.
+const BAD_CONFIG_FILE_NAME: &str = "qdx-receiver.toml.bad";
+
+// What's stored in the configuration file. Anything missing from the file takes its default, so
+// older files can still be read as settings are added.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct Configuration {
+    frequency: Option<u32>,
+    amplitude: f32,
+    muted: bool,
+    audio_out_device: Option<String>,
+    rig_in_device: Option<String>,
+    cat_port_device: Option<String>,
+    window_position: Option<(i32, i32)>,
+    // Keyed by band in metres; TOML table keys have to be strings.
+    band_frequencies: BTreeMap<String, u32>,
+    spectrum_reference_level: f32,
+    spectrum_span_hz: u32,
+    spectrum_averaging: u32,
+    waterfall_palette: WaterfallPalette,
+    waterfall_speed: u32,
+    waterfall_contrast: f32,
+    tuning_pitch_hz: u32,
+    notch: NotchSettings,
+    auto_notch: bool,
+    noise_reduction: NoiseReductionSettings,
+    noise_blanker: NoiseBlankerSettings,
+    agc: AgcSettings,
+    meter_mode: MeterMode,
+    meter_ballistics: MeterBallistics,
+    tune_assist: TuneAssistSettings,
+    // These are keyed by band in metres, as band_frequencies.
+    band_bandpasses: BTreeMap<String, BandpassSettings>,
+    band_meter_calibrations: BTreeMap<String, f32>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            frequency: None,
+            amplitude: 1.0,
+            muted: false,
+            audio_out_device: None,
+            rig_in_device: None,
+            cat_port_device: None,
+            window_position: None,
+            band_frequencies: BTreeMap::new(),
+            spectrum_reference_level: -20.0,
+            spectrum_span_hz: 3000,
+            spectrum_averaging: 4,
+            waterfall_palette: WaterfallPalette::Classic,
+            waterfall_speed: 3,
+            waterfall_contrast: 1.0,
+            tuning_pitch_hz: 1500,
+            notch: NotchSettings::default(),
+            auto_notch: false,
+            noise_reduction: NoiseReductionSettings::default(),
+            noise_blanker: NoiseBlankerSettings::default(),
+            agc: AgcSettings::default(),
+            meter_mode: MeterMode::SignalLevel,
+            meter_ballistics: MeterBallistics::Fast,
+            tune_assist: TuneAssistSettings::default(),
+            band_bandpasses: BTreeMap::new(),
+            band_meter_calibrations: BTreeMap::new(),
+        }
+    }
+}
+
+// Settings are read when the store is created, changed in memory, and written by save().
+pub struct ConfigurationStore {
+    config_file_path: PathBuf,
+    configuration: Configuration,
+}
+
+// The configuration file within qdx-receiver's configuration directory, whether or not it exists.
+pub fn config_file_path(config_path: &Path) -> PathBuf {
+    config_path.join(CONFIG_FILE_NAME)
+}
+
+impl ConfigurationStore {
+    pub fn new(config_path: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let config_file_path = config_file_path(&config_path);
+        let configuration = if config_file_path.exists() {
+            info!("Reading configuration from {:?}", config_file_path);
+            let contents = fs::read(&config_file_path)?;
+            let parsed = String::from_utf8(contents).map_err(|err| err.to_string())
+                .and_then(|contents| Self::parse(contents.as_str()));
+            match parsed {
+                Ok(configuration) => configuration,
+                Err(err) => {
+                    // e.g. a hand-edited or truncated file; that shouldn't stop qdx-receiver starting.
+                    let bad_config_file_path = config_path.join(BAD_CONFIG_FILE_NAME);
+                    warn!("Could not read configuration file {:?}: {}; moving it to {:?} and using defaults", config_file_path, err, bad_config_file_path);
+                    if let Err(err) = fs::rename(&config_file_path, &bad_config_file_path) {
+                        warn!("Could not move configuration file {:?}: {}", config_file_path, err);
+                    }
+                    Configuration::default()
+                }
+            }
+        } else {
+            info!("No configuration file at {:?}; using defaults", config_file_path);
+            Configuration::default()
+        };
+        debug!("Configuration is {:?}", configuration);
+        Ok(Self {
+            config_file_path,
+            configuration,
+        })
+    }
+
+    // Takes each setting in the file that can be read, so that one that can't (e.g. mistyped when
+    // hand-editing) keeps its default without losing the rest. Only a file that isn't TOML at all
+    // is an error.
+    fn parse(contents: &str) -> Result<Configuration, String> {
+        let table = contents.parse::<toml::Table>().map_err(|err| err.to_string())?;
+        let mut readable = toml::Table::new();
+        for (key, value) in table {
+            let mut setting = toml::Table::new();
+            setting.insert(key.clone(), value.clone());
+            match toml::Value::Table(setting).try_into::<Configuration>() {
+                Ok(_) => {
+                    readable.insert(key, value);
+                }
+                Err(err) => {
+                    warn!("Could not read configuration setting '{}'; using its default: {}", key, err);
+                }
+            }
+        }
+        toml::Value::Table(readable).try_into::<Configuration>().map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        info!("Saving configuration to {:?}", self.config_file_path);
+        let contents = toml::to_string(&self.configuration)?;
+        fs::write(&self.config_file_path, contents)?;
+        Ok(())
+    }
+
+    pub fn get_frequency(&self) -> Option<u32> {
+        self.configuration.frequency
+    }
+
+    // Also remembers the frequency as the last one used on its band, if it's in one.
+    pub fn set_frequency(&mut self, frequency_hz: u32) {
+        self.configuration.frequency = Some(frequency_hz);
+        if let Some(metres) = band_for_frequency(frequency_hz) {
+            self.configuration.band_frequencies.insert(metres.to_string(), frequency_hz);
+        }
+    }
+
+    pub fn get_band_frequency(&self, metres: u8) -> Option<u32> {
+        self.configuration.band_frequencies.get(&metres.to_string()).copied()
+    }
+
+    pub fn get_amplitude(&self) -> f32 {
+        self.configuration.amplitude
+    }
+
+    pub fn set_amplitude(&mut self, amplitude: f32) {
+        self.configuration.amplitude = amplitude;
+    }
+
+    pub fn get_muted(&self) -> bool {
+        self.configuration.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.configuration.muted = muted;
+    }
+
+    pub fn get_audio_out_device(&self) -> Option<String> {
+        self.configuration.audio_out_device.clone()
+    }
+
+    pub fn set_audio_out_device(&mut self, device: Option<String>) {
+        self.configuration.audio_out_device = device;
+    }
+
+    pub fn get_rig_in_device(&self) -> Option<String> {
+        self.configuration.rig_in_device.clone()
+    }
+
+    pub fn set_rig_in_device(&mut self, device: Option<String>) {
+        self.configuration.rig_in_device = device;
+    }
+
+    pub fn get_cat_port_device(&self) -> Option<String> {
+        self.configuration.cat_port_device.clone()
+    }
+
+    pub fn set_cat_port_device(&mut self, device: Option<String>) {
+        self.configuration.cat_port_device = device;
+    }
+
+    pub fn get_window_position(&self) -> Option<(i32, i32)> {
+        self.configuration.window_position
+    }
+
+    pub fn set_window_position(&mut self, x: i32, y: i32) {
+        self.configuration.window_position = Some((x, y));
+    }
+
+    pub fn get_spectrum_reference_level(&self) -> f32 {
+        self.configuration.spectrum_reference_level
+    }
+
+    pub fn set_spectrum_reference_level(&mut self, reference_level: f32) {
+        self.configuration.spectrum_reference_level = reference_level;
+    }
+
+    pub fn get_spectrum_span_hz(&self) -> u32 {
+        self.configuration.spectrum_span_hz
+    }
+
+    pub fn set_spectrum_span_hz(&mut self, span_hz: u32) {
+        self.configuration.spectrum_span_hz = span_hz;
+    }
+
+    pub fn get_spectrum_averaging(&self) -> u32 {
+        self.configuration.spectrum_averaging
+    }
+
+    pub fn set_spectrum_averaging(&mut self, averaging: u32) {
+        self.configuration.spectrum_averaging = averaging;
+    }
+
+    pub fn get_waterfall_palette(&self) -> WaterfallPalette {
+        self.configuration.waterfall_palette
+    }
+
+    pub fn set_waterfall_palette(&mut self, palette: WaterfallPalette) {
+        self.configuration.waterfall_palette = palette;
+    }
+
+    pub fn get_waterfall_speed(&self) -> u32 {
+        self.configuration.waterfall_speed
+    }
+
+    pub fn set_waterfall_speed(&mut self, speed: u32) {
+        self.configuration.waterfall_speed = speed;
+    }
+
+    pub fn get_waterfall_contrast(&self) -> f32 {
+        self.configuration.waterfall_contrast
+    }
+
+    pub fn set_waterfall_contrast(&mut self, contrast: f32) {
+        self.configuration.waterfall_contrast = contrast;
+    }
+
+    pub fn get_tuning_pitch_hz(&self) -> u32 {
+        self.configuration.tuning_pitch_hz
+    }
+
+    pub fn set_tuning_pitch_hz(&mut self, pitch_hz: u32) {
+        self.configuration.tuning_pitch_hz = pitch_hz;
+    }
+
+    pub fn get_notch(&self) -> NotchSettings {
+        self.configuration.notch
+    }
+
+    pub fn set_notch(&mut self, settings: NotchSettings) {
+        self.configuration.notch = settings;
+    }
+
+    pub fn get_auto_notch(&self) -> bool {
+        self.configuration.auto_notch
+    }
+
+    pub fn set_auto_notch(&mut self, enabled: bool) {
+        self.configuration.auto_notch = enabled;
+    }
+
+    pub fn get_noise_reduction(&self) -> NoiseReductionSettings {
+        self.configuration.noise_reduction
+    }
+
+    pub fn set_noise_reduction(&mut self, settings: NoiseReductionSettings) {
+        self.configuration.noise_reduction = settings;
+    }
+
+    pub fn get_noise_blanker(&self) -> NoiseBlankerSettings {
+        self.configuration.noise_blanker
+    }
+
+    pub fn set_noise_blanker(&mut self, settings: NoiseBlankerSettings) {
+        self.configuration.noise_blanker = settings;
+    }
+
+    pub fn get_agc(&self) -> AgcSettings {
+        self.configuration.agc
+    }
+
+    pub fn set_agc(&mut self, settings: AgcSettings) {
+        self.configuration.agc = settings;
+    }
+
+    pub fn get_meter_mode(&self) -> MeterMode {
+        self.configuration.meter_mode
+    }
+
+    pub fn set_meter_mode(&mut self, meter_mode: MeterMode) {
+        self.configuration.meter_mode = meter_mode;
+    }
+
+    pub fn get_meter_ballistics(&self) -> MeterBallistics {
+        self.configuration.meter_ballistics
+    }
+
+    pub fn set_meter_ballistics(&mut self, ballistics: MeterBallistics) {
+        self.configuration.meter_ballistics = ballistics;
+    }
+
+    pub fn get_tune_assist(&self) -> TuneAssistSettings {
+        self.configuration.tune_assist
+    }
+
+    pub fn set_tune_assist(&mut self, settings: TuneAssistSettings) {
+        self.configuration.tune_assist = settings;
+    }
+
+    pub fn get_band_bandpass(&self, metres: u8) -> Option<BandpassSettings> {
+        self.configuration.band_bandpasses.get(&metres.to_string()).copied()
+    }
+
+    pub fn set_band_bandpass(&mut self, metres: u8, settings: BandpassSettings) {
+        self.configuration.band_bandpasses.insert(metres.to_string(), settings);
+    }
+
+    pub fn get_band_meter_calibration(&self, metres: u8) -> Option<f32> {
+        self.configuration.band_meter_calibrations.get(&metres.to_string()).copied()
+    }
+
+    pub fn set_band_meter_calibration(&mut self, metres: u8, calibration_db: f32) {
+        self.configuration.band_meter_calibrations.insert(metres.to_string(), calibration_db);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::libs::config::config::{BAD_CONFIG_FILE_NAME, CONFIG_FILE_NAME, Configuration, ConfigurationStore};
+    use crate::libs::dsp::bandpass::{BandpassPreset, BandpassSettings};
+    use crate::libs::dsp::notch::NotchSettings;
+    use crate::libs::dsp::tune_assist::TuneAssistSettings;
+    use crate::libs::gui_api::gui_api::MeterMode;
+    use crate::libs::smeter::ballistics::MeterBallistics;
+
+    // A fresh, empty directory for each test, removed when done.
+    struct ConfigDir {
+        path: PathBuf,
+    }
+
+    impl ConfigDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("qdx-receiver-test-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self { path }
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn settings_survive_save_and_load() {
+        let dir = ConfigDir::new("round-trip");
+        let mut config = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(config.configuration, Configuration::default());
+        config.set_frequency(7074000);
+        config.set_frequency(14074000);
+        config.set_amplitude(0.25);
+        config.set_muted(true);
+        config.set_rig_in_device(Some("QDX Transceiver".to_string()));
+        config.set_window_position(100, -20);
+        config.set_meter_ballistics(MeterBallistics::Rms(3));
+        config.set_tune_assist(TuneAssistSettings { enabled: true, tone: false });
+        config.save().unwrap();
+
+        let loaded = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(loaded.get_frequency(), Some(14074000));
+        assert_eq!(loaded.get_band_frequency(40), Some(7074000));
+        assert_eq!(loaded.get_amplitude(), 0.25);
+        assert!(loaded.get_muted());
+        assert_eq!(loaded.get_rig_in_device(), Some("QDX Transceiver".to_string()));
+        assert_eq!(loaded.get_audio_out_device(), None);
+        assert_eq!(loaded.get_window_position(), Some((100, -20)));
+        assert_eq!(loaded.get_meter_ballistics(), MeterBallistics::Rms(3));
+        // Tune assist is always off at startup.
+        assert_eq!(loaded.get_tune_assist(), TuneAssistSettings { enabled: false, tone: false });
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let dir = ConfigDir::new("missing");
+        fs::write(dir.path.join(CONFIG_FILE_NAME), "frequency = 3573000\nmuted = true\n").unwrap();
+        let config = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(config.get_frequency(), Some(3573000));
+        assert!(config.get_muted());
+        assert_eq!(config.configuration, Configuration { frequency: Some(3573000), muted: true, ..Configuration::default() });
+    }
+
+    #[test]
+    fn per_band_settings_are_keyed_by_band() {
+        let dir = ConfigDir::new("bands");
+        let mut config = ConfigurationStore::new(dir.path.clone()).unwrap();
+        let bandpass = BandpassSettings { preset: BandpassPreset::Cw250, centre_hz: 650.0 };
+        config.set_band_bandpass(20, bandpass);
+        config.set_band_meter_calibration(160, -37.5);
+        config.set_frequency(28074000);
+        config.save().unwrap();
+        let contents = fs::read_to_string(dir.path.join(CONFIG_FILE_NAME)).unwrap();
+        assert!(contents.contains("[band_bandpasses.20]"), "{}", contents);
+
+        let loaded = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(loaded.get_band_bandpass(20), Some(bandpass));
+        assert_eq!(loaded.get_band_bandpass(40), None);
+        assert_eq!(loaded.get_band_meter_calibration(160), Some(-37.5));
+        assert_eq!(loaded.get_band_frequency(10), Some(28074000));
+        assert_eq!(loaded.get_band_frequency(20), None);
+    }
+
+    #[test]
+    fn unparsable_files_are_set_aside_for_the_defaults() {
+        let dir = ConfigDir::new("unparsable");
+        let contents = "frequency = 14074000\nband_frequencies = { 20 = 140";
+        fs::write(dir.path.join(CONFIG_FILE_NAME), contents).unwrap();
+        let config = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(config.configuration, Configuration::default());
+        assert!(!dir.path.join(CONFIG_FILE_NAME).exists());
+        assert_eq!(fs::read_to_string(dir.path.join(BAD_CONFIG_FILE_NAME)).unwrap(), contents);
+
+        // Saving the defaults leaves it alone.
+        config.save().unwrap();
+        assert!(ConfigurationStore::new(dir.path.clone()).is_ok());
+        assert_eq!(fs::read_to_string(dir.path.join(BAD_CONFIG_FILE_NAME)).unwrap(), contents);
+    }
+
+    #[test]
+    fn mistyped_settings_take_their_defaults() {
+        let dir = ConfigDir::new("mistyped");
+        let contents = "frequency = \"fourteen\"\nmuted = true\nmeter_mode = \"Loudness\"\n[notch]\nenabled = 1\n[band_frequencies]\n40 = 7074000\n";
+        fs::write(dir.path.join(CONFIG_FILE_NAME), contents).unwrap();
+        let config = ConfigurationStore::new(dir.path.clone()).unwrap();
+        assert_eq!(config.get_frequency(), None);
+        assert_eq!(config.get_meter_mode(), MeterMode::SignalLevel);
+        assert_eq!(config.get_notch(), NotchSettings::default());
+        // The rest are still read, and the file is left where it is.
+        assert!(config.get_muted());
+        assert_eq!(config.get_band_frequency(40), Some(7074000));
+        assert!(dir.path.join(CONFIG_FILE_NAME).exists());
+        assert!(!dir.path.join(BAD_CONFIG_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,29 @@
+// -------------------------------------------------------------------------------------------------
+// CONFIGURATION DIRECTORY
+// -------------------------------------------------------------------------------------------------
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use log::info;
+
+const CONFIG_DIR_NAME: &str = "qdx-receiver";
+
+// Given the platform's configuration directory (e.g. $XDG_CONFIG_HOME or ~/.config on Linux,
+// ~/Library/Application Support on macOS), return qdx-receiver's directory within it, creating it
+// if necessary.
+pub fn configuration_directory(config_dir: Option<PathBuf>) -> Result<PathBuf, Box<dyn Error>> {
+    match config_dir {
+        Some(config_dir) => {
+            let config_path = config_dir.join(CONFIG_DIR_NAME);
+            if !config_path.exists() {
+                info!("Creating configuration directory {:?}", config_path);
+                fs::create_dir_all(&config_path)?;
+            }
+            Ok(config_path)
+        }
+        None => {
+            Err(Box::<dyn Error + Send + Sync>::from("Can't determine the configuration directory"))
+        }
+    }
+}
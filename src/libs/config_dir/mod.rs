@@ -0,0 +1 @@
+pub mod config_dir;
\ No newline at end of file
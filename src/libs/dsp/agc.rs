@@ -0,0 +1,206 @@
+// -------------------------------------------------------------------------------------------------
+// AUTOMATIC GAIN CONTROL
+// -------------------------------------------------------------------------------------------------
+
+use serde::{Deserialize, Serialize};
+use crate::libs::audio::audio::SAMPLE_RATE;
+
+// The QDX's audio is quiet; with the AGC off, this brings it up to a comfortable level at full
+// volume.
+const FIXED_GAIN: f32 = 90.0;
+
+// The peak level the AGC brings signals to, before the volume control.
+const TARGET_LEVEL: f32 = 0.5;
+
+pub const MIN_MAX_GAIN_DB: f32 = 0.0;
+pub const MAX_MAX_GAIN_DB: f32 = 80.0;
+
+// The audio is delayed by this (2ms), so that the gain has come down before the start of a
+// sudden loud signal is heard, rather than it being heard at full gain.
+const LOOKAHEAD: usize = 96;
+// The envelope's time constant when rising, which the lookahead is four times.
+const ATTACK_SECONDS: f64 = 0.0005;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AgcPreset {
+    Off,
+    Fast,
+    Medium,
+    Slow,
+}
+
+pub const AGC_PRESETS: [AgcPreset; 4] = [AgcPreset::Off, AgcPreset::Fast, AgcPreset::Medium, AgcPreset::Slow];
+
+impl AgcPreset {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AgcPreset::Off => "Off",
+            AgcPreset::Fast => "Fast",
+            AgcPreset::Medium => "Medium",
+            AgcPreset::Slow => "Slow",
+        }
+    }
+
+    // How long the gain is held after a peak, before it starts to recover.
+    fn hang_seconds(&self) -> f64 {
+        match self {
+            AgcPreset::Off => 0.0,
+            AgcPreset::Fast => 0.1,
+            AgcPreset::Medium => 0.25,
+            AgcPreset::Slow => 0.5,
+        }
+    }
+
+    // The envelope's time constant when falling, once the hang time has passed.
+    fn decay_seconds(&self) -> f64 {
+        match self {
+            AgcPreset::Off => 0.0,
+            AgcPreset::Fast => 0.1,
+            AgcPreset::Medium => 0.5,
+            AgcPreset::Slow => 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgcSettings {
+    pub preset: AgcPreset,
+    pub max_gain_db: f32, // The ceiling, reached on silence or the weakest signals.
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self { preset: AgcPreset::Off, max_gain_db: 50.0 }
+    }
+}
+
+// Brings strong and weak signals to a similar level, by following the peak envelope of the audio
+// and setting the gain to bring it to the target level, up to the ceiling. After a peak, the gain
+// is held for the hang time, so that it doesn't pump up the noise between words or characters.
+// When off, a fixed gain is applied instead.
+pub struct Agc {
+    settings: AgcSettings,
+    attack_rate: f32,
+    decay_rate: f32,
+    hang_samples: usize,
+    delay: [f32; LOOKAHEAD],
+    position: usize,
+    envelope: f32,
+    hang: usize, // Samples until the envelope may fall.
+    gain: f32,
+}
+
+impl Agc {
+    pub fn new(settings: AgcSettings) -> Self {
+        let mut agc = Self {
+            settings,
+            attack_rate: Self::rate(ATTACK_SECONDS),
+            decay_rate: 0.0,
+            hang_samples: 0,
+            delay: [0.0; LOOKAHEAD],
+            position: 0,
+            envelope: 0.0,
+            hang: 0,
+            gain: FIXED_GAIN,
+        };
+        agc.set_timing();
+        agc
+    }
+
+    pub fn set_settings(&mut self, settings: AgcSettings) {
+        if settings != self.settings {
+            self.settings = settings;
+            self.set_timing();
+        }
+    }
+
+    fn set_timing(&mut self) {
+        let preset = self.settings.preset;
+        self.decay_rate = if preset == AgcPreset::Off { 0.0 } else { Self::rate(preset.decay_seconds()) };
+        self.hang_samples = (preset.hang_seconds() * SAMPLE_RATE) as usize;
+    }
+
+    // The smoothing factor for a time constant of the given length.
+    fn rate(seconds: f64) -> f32 {
+        (1.0 - (-1.0 / (seconds * SAMPLE_RATE)).exp()) as f32
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let delayed = self.delay[self.position];
+        self.delay[self.position] = sample;
+        self.position = (self.position + 1) % LOOKAHEAD;
+
+        if self.settings.preset == AgcPreset::Off {
+            self.gain = FIXED_GAIN;
+            return delayed * FIXED_GAIN;
+        }
+
+        let magnitude = sample.abs();
+        if magnitude > self.envelope {
+            self.envelope += (magnitude - self.envelope) * self.attack_rate;
+            self.hang = self.hang_samples;
+        } else if self.hang > 0 {
+            self.hang -= 1;
+        } else {
+            self.envelope += (magnitude - self.envelope) * self.decay_rate;
+        }
+        // On silence, the envelope is zero, and the gain is the ceiling.
+        self.gain = (TARGET_LEVEL / self.envelope).min(db_to_gain(self.settings.max_gain_db));
+        delayed * self.gain
+    }
+
+    // How far the gain is below the ceiling; always zero when off.
+    pub fn gain_reduction_db(&self) -> f32 {
+        if self.settings.preset == AgcPreset::Off {
+            return 0.0;
+        }
+        (self.settings.max_gain_db - 20.0 * self.gain.log10()).max(0.0)
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::agc::{Agc, AgcPreset, AgcSettings, TARGET_LEVEL};
+    use crate::libs::dsp::test_signals::{ONE_SECOND, peak_output};
+
+    fn peak_of_tone(agc: &mut Agc, amplitude: f32) -> f32 {
+        peak_output(700.0, amplitude, |sample| agc.process(sample))
+    }
+
+    #[test]
+    fn strong_and_weak_signals_are_brought_to_the_same_level() {
+        let settings = AgcSettings { preset: AgcPreset::Medium, max_gain_db: 60.0 };
+        for amplitude in [0.003, 0.03, 0.3] {
+            let mut agc = Agc::new(settings);
+            let peak = peak_of_tone(&mut agc, amplitude);
+            assert!((peak - TARGET_LEVEL).abs() < 0.05, "{} {}", amplitude, peak);
+        }
+        // Too weak to reach the target at the ceiling.
+        let mut agc = Agc::new(settings);
+        let peak = peak_of_tone(&mut agc, 0.0001);
+        assert!((peak - 0.1).abs() < 0.01, "{}", peak);
+        assert!(agc.gain_reduction_db() < 0.1);
+    }
+
+    #[test]
+    fn gain_is_held_after_a_signal_then_recovers() {
+        let mut agc = Agc::new(AgcSettings { preset: AgcPreset::Fast, max_gain_db: 60.0 });
+        peak_of_tone(&mut agc, 0.1);
+        let reduction = agc.gain_reduction_db();
+        assert!((reduction - 46.0).abs() < 1.0, "{}", reduction);
+        // Silence: within the hang time, the gain is held...
+        for _ in 0..2400 {
+            agc.process(0.0);
+        }
+        assert_eq!(agc.gain_reduction_db(), reduction);
+        // ... then recovers.
+        for _ in 0..ONE_SECOND {
+            agc.process(0.0);
+        }
+        assert!(agc.gain_reduction_db() < 1.0, "{}", agc.gain_reduction_db());
+    }
+}
@@ -0,0 +1,91 @@
+// -------------------------------------------------------------------------------------------------
+// AUTOMATIC NOTCH FILTER
+// -------------------------------------------------------------------------------------------------
+
+// An adaptive line enhancer: a normalised LMS filter predicts each sample from earlier ones, far
+// enough back that noise and speech have decorrelated, but steady tones haven't. The prediction is
+// the tones, so subtracting it leaves everything else. As tones come and go, it follows them.
+const TAPS: usize = 64;
+const DELAY: usize = 48; // 1ms at 48kHz.
+const STEP_SIZE: f32 = 0.01;
+// Slowly forgets, so the weights don't drift when there's nothing to track.
+const LEAKAGE: f32 = 0.9999;
+
+pub struct AutoNotch {
+    enabled: bool,
+    history: [f32; TAPS + DELAY], // A circular buffer of past input.
+    position: usize, // Where the next input goes.
+    weights: [f32; TAPS],
+}
+
+impl Default for AutoNotch {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            history: [0.0; TAPS + DELAY],
+            position: 0,
+            weights: [0.0; TAPS],
+        }
+    }
+}
+
+impl AutoNotch {
+    // On enabling, it starts adapting afresh.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            *self = Self::default();
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        if !self.enabled {
+            return input;
+        }
+        let length = self.history.len();
+        // The taps start DELAY samples back.
+        let oldest_delayed = (self.position + length - DELAY) % length;
+        let mut prediction = 0.0;
+        let mut power = 0.0;
+        for (tap, weight) in self.weights.iter().enumerate() {
+            let past = self.history[(oldest_delayed + length - 1 - tap) % length];
+            prediction += weight * past;
+            power += past * past;
+        }
+        let error = input - prediction;
+        let step = STEP_SIZE * error / (power + 1e-6);
+        for (tap, weight) in self.weights.iter_mut().enumerate() {
+            let past = self.history[(oldest_delayed + length - 1 - tap) % length];
+            *weight = *weight * LEAKAGE + step * past;
+        }
+        self.history[self.position] = input;
+        self.position = (self.position + 1) % length;
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::auto_notch::AutoNotch;
+    use crate::libs::dsp::test_signals::{ONE_SECOND, tone};
+    use crate::libs::random::random::XorShift;
+
+    #[test]
+    fn steady_tone_is_removed() {
+        let mut auto_notch = AutoNotch::default();
+        auto_notch.set_enabled(true);
+        let mut random = XorShift::new(7);
+        let mut tone_power = 0.0;
+        let mut noise_power = 0.0;
+        for n in 0..2 * ONE_SECOND {
+            let noise = 0.05 * (random.next_f64() as f32 - 0.5);
+            let tone_output = auto_notch.process(tone(1000.0, 0.5, n) + noise) - noise;
+            if n > ONE_SECOND {
+                tone_power += tone_output * tone_output;
+                noise_power += noise * noise;
+            }
+        }
+        // What's left of the tone is well below the noise, which started 20dB below it.
+        assert!(tone_power < noise_power, "{} {}", tone_power, noise_power);
+    }
+}
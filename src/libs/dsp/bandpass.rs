@@ -0,0 +1,186 @@
+// -------------------------------------------------------------------------------------------------
+// BANDPASS FILTER
+// -------------------------------------------------------------------------------------------------
+
+use std::f64::consts::PI;
+use rustfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use crate::libs::audio::audio::SAMPLE_RATE;
+use crate::libs::dsp::biquad::Biquad;
+
+// The order of the lowpass prototype; the bandpass has twice as many poles, in this many pairs of
+// second-order sections.
+const PROTOTYPE_ORDER: usize = 4;
+
+// The edges are kept within what the sections can be designed for.
+const MIN_EDGE_HZ: f32 = 50.0;
+const MAX_EDGE_HZ: f32 = 20000.0;
+
+pub const MIN_CENTRE_HZ: f32 = 300.0;
+pub const MAX_CENTRE_HZ: f32 = 3000.0;
+
+const SSB_CENTRE_HZ: f32 = 1500.0;
+const CW_CENTRE_HZ: f32 = 700.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BandpassPreset {
+    Ssb2400,
+    Cw500,
+    Cw250,
+    Cw100,
+}
+
+pub const BANDPASS_PRESETS: [BandpassPreset; 4] = [BandpassPreset::Ssb2400, BandpassPreset::Cw500, BandpassPreset::Cw250, BandpassPreset::Cw100];
+
+impl BandpassPreset {
+    pub fn width_hz(&self) -> f32 {
+        match self {
+            BandpassPreset::Ssb2400 => 2400.0,
+            BandpassPreset::Cw500 => 500.0,
+            BandpassPreset::Cw250 => 250.0,
+            BandpassPreset::Cw100 => 100.0,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BandpassPreset::Ssb2400 => "SSB 2.4k",
+            BandpassPreset::Cw500 => "CW 500",
+            BandpassPreset::Cw250 => "CW 250",
+            BandpassPreset::Cw100 => "CW 100",
+        }
+    }
+
+    fn is_cw(&self) -> bool {
+        *self != BandpassPreset::Ssb2400
+    }
+
+    fn default_centre_hz(&self) -> f32 {
+        if self.is_cw() { CW_CENTRE_HZ } else { SSB_CENTRE_HZ }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BandpassSettings {
+    pub preset: BandpassPreset,
+    pub centre_hz: f32,
+}
+
+impl Default for BandpassSettings {
+    fn default() -> Self {
+        Self { preset: BandpassPreset::Ssb2400, centre_hz: SSB_CENTRE_HZ }
+    }
+}
+
+impl BandpassSettings {
+    // Changing between the CW widths keeps the centre, which is the CW pitch; changing between CW
+    // and SSB takes the new preset's default centre.
+    pub fn with_preset(&self, preset: BandpassPreset) -> Self {
+        let centre_hz = if preset.is_cw() == self.preset.is_cw() { self.centre_hz } else { preset.default_centre_hz() };
+        Self { preset, centre_hz }
+    }
+
+    fn edges_hz(&self) -> (f32, f32) {
+        let half_width = self.preset.width_hz() / 2.0;
+        ((self.centre_hz - half_width).clamp(MIN_EDGE_HZ, MAX_EDGE_HZ),
+         (self.centre_hz + half_width).clamp(MIN_EDGE_HZ, MAX_EDGE_HZ))
+    }
+}
+
+// An 8th-order Butterworth bandpass, made by transforming a 4th-order lowpass prototype. It's
+// -3dB at the edges, with unity gain at their geometric centre.
+pub struct Bandpass {
+    settings: BandpassSettings,
+    sections: [Biquad; PROTOTYPE_ORDER],
+}
+
+impl Bandpass {
+    pub fn new(settings: BandpassSettings) -> Self {
+        Self {
+            settings,
+            sections: Self::design(settings),
+        }
+    }
+
+    fn design(settings: BandpassSettings) -> [Biquad; PROTOTYPE_ORDER] {
+        let (low_hz, high_hz) = settings.edges_hz();
+        let sample_rate = SAMPLE_RATE;
+        // The analogue edges, prewarped so that the bilinear transform puts them where they should
+        // be.
+        let c = 2.0 * sample_rate;
+        let low = c * (PI * low_hz as f64 / sample_rate).tan();
+        let high = c * (PI * high_hz as f64 / sample_rate).tan();
+        let centre = (low * high).sqrt();
+        let bandwidth = high - low;
+        let centre_hz = (centre / c).atan() * sample_rate / PI;
+
+        let mut sections = [Biquad::default(); PROTOTYPE_ORDER];
+        let mut section_index = 0;
+        // Each prototype pole in the upper half-plane, p, becomes the two roots of
+        // s^2 - pBs + w0^2 = 0; their conjugates come from p's conjugate. Each root and its
+        // conjugate make a section, with a zero at 0 and at infinity.
+        for k in 0..PROTOTYPE_ORDER / 2 {
+            let angle = PI * (2 * k + PROTOTYPE_ORDER + 1) as f64 / (2 * PROTOTYPE_ORDER) as f64;
+            let prototype_pole = Complex::from_polar(1.0, angle);
+            let pb = prototype_pole * bandwidth;
+            let discriminant = (pb * pb - 4.0 * centre * centre).sqrt();
+            for pole in [(pb + discriminant) / 2.0, (pb - discriminant) / 2.0] {
+                // H(s) = s / (s^2 + a1 s + a0), by the bilinear transform.
+                let a1 = -2.0 * pole.re;
+                let a0 = pole.norm_sqr();
+                let d0 = c * c + a1 * c + a0;
+                let mut section = Biquad::new(c / d0, 0.0, -c / d0, (2.0 * a0 - 2.0 * c * c) / d0, (c * c - a1 * c + a0) / d0);
+                section.scale(1.0 / section.gain_at(sample_rate, centre_hz));
+                sections[section_index] = section;
+                section_index += 1;
+            }
+        }
+        sections
+    }
+
+    // Cheap if the settings are unchanged, so it can be called on every callback.
+    pub fn set_settings(&mut self, settings: BandpassSettings) {
+        if settings == self.settings {
+            return;
+        }
+        self.settings = settings;
+        for (section, designed) in self.sections.iter_mut().zip(Self::design(settings).iter()) {
+            section.set_response(designed);
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.sections.iter_mut().fold(input, |sample, section| section.process(sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::bandpass::{Bandpass, BandpassPreset, BandpassSettings};
+    use crate::libs::dsp::test_signals::peak_output;
+
+    fn gain(bandpass: &mut Bandpass, frequency_hz: f32) -> f32 {
+        peak_output(frequency_hz, 1.0, |sample| bandpass.process(sample))
+    }
+
+    #[test]
+    fn passes_the_centre_and_rejects_elsewhere() {
+        let settings = BandpassSettings { preset: BandpassPreset::Cw250, centre_hz: 700.0 };
+        assert!((gain(&mut Bandpass::new(settings), 700.0) - 1.0).abs() < 0.05);
+        assert!(gain(&mut Bandpass::new(settings), 1200.0) < 0.01);
+        assert!(gain(&mut Bandpass::new(settings), 300.0) < 0.01);
+
+        let ssb = BandpassSettings::default();
+        assert!(gain(&mut Bandpass::new(ssb), 1000.0) > 0.95);
+        assert!(gain(&mut Bandpass::new(ssb), 8000.0) < 0.01);
+    }
+
+    #[test]
+    fn presets_keep_the_cw_pitch() {
+        let cw = BandpassSettings::default().with_preset(BandpassPreset::Cw500);
+        assert_eq!(cw.centre_hz, 700.0);
+        let moved = BandpassSettings { centre_hz: 600.0, ..cw };
+        assert_eq!(moved.with_preset(BandpassPreset::Cw100).centre_hz, 600.0);
+        assert_eq!(moved.with_preset(BandpassPreset::Ssb2400).centre_hz, 1500.0);
+    }
+}
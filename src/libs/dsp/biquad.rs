@@ -0,0 +1,93 @@
+// -------------------------------------------------------------------------------------------------
+// BIQUAD FILTER SECTION
+// -------------------------------------------------------------------------------------------------
+
+use std::f64::consts::PI;
+use rustfft::num_complex::Complex;
+
+// One second-order IIR section, run in transposed direct form II. The coefficients are normalised
+// so that a0 is 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 as f32,
+            b1: b1 as f32,
+            b2: b2 as f32,
+            a1: a1 as f32,
+            a2: a2 as f32,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    // A notch, from the formulae in Robert Bristow-Johnson's "Audio EQ Cookbook". The higher the
+    // Q, the narrower it is.
+    pub fn notch(sample_rate: f64, frequency_hz: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * frequency_hz / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Self::new(1.0 / a0, -2.0 * w0.cos() / a0, 1.0 / a0, -2.0 * w0.cos() / a0, (1.0 - alpha) / a0)
+    }
+
+    // The magnitude of the response at a frequency.
+    pub fn gain_at(&self, sample_rate: f64, frequency_hz: f64) -> f64 {
+        let z1 = Complex::from_polar(1.0, -2.0 * PI * frequency_hz / sample_rate);
+        let z2 = z1 * z1;
+        let numerator = self.b0 as f64 + z1 * self.b1 as f64 + z2 * self.b2 as f64;
+        let denominator = 1.0 + z1 * self.a1 as f64 + z2 * self.a2 as f64;
+        (numerator / denominator).norm()
+    }
+
+    // Scales the response, e.g. to normalise its gain.
+    pub fn scale(&mut self, factor: f64) {
+        self.b0 *= factor as f32;
+        self.b1 *= factor as f32;
+        self.b2 *= factor as f32;
+    }
+
+    // Takes another section's response, keeping this one's state, so that redesigning a filter
+    // while it's running doesn't click.
+    pub fn set_response(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::audio::audio::SAMPLE_RATE;
+    use crate::libs::dsp::biquad::Biquad;
+    use crate::libs::dsp::test_signals::peak_output;
+
+    #[test]
+    fn measured_gain_matches_response() {
+        let section = Biquad::new(0.2, 0.3, 0.1, -0.5, 0.2);
+        for frequency_hz in [100.0, 1000.0, 10000.0] {
+            let mut biquad = section;
+            let peak = peak_output(frequency_hz, 1.0, |sample| biquad.process(sample));
+            let expected = section.gain_at(SAMPLE_RATE, frequency_hz as f64) as f32;
+            assert!((peak - expected).abs() < 0.01, "{} {} {}", frequency_hz, peak, expected);
+        }
+    }
+}
@@ -0,0 +1,10 @@
+pub mod agc;
+pub mod auto_notch;
+pub mod bandpass;
+pub mod biquad;
+pub mod noise_blanker;
+pub mod noise_reduction;
+pub mod notch;
+#[cfg(test)]
+pub mod test_signals;
+pub mod tune_assist;
\ No newline at end of file
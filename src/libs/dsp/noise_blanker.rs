@@ -0,0 +1,130 @@
+// -------------------------------------------------------------------------------------------------
+// NOISE BLANKER
+// -------------------------------------------------------------------------------------------------
+
+use serde::{Deserialize, Serialize};
+
+pub const MIN_BLANKER_THRESHOLD: f32 = 2.0;
+pub const MAX_BLANKER_THRESHOLD: f32 = 20.0;
+
+// The audio is delayed by this, so that the rising edge of an impulse, before it crosses the
+// threshold, is blanked too.
+const LOOKAHEAD: usize = 8;
+// How long blanking continues after the impulse (0.5ms).
+const HOLD: usize = 24;
+// The running average's time constant is about 20ms.
+const AVERAGE_RATE: f32 = 0.001;
+// Nothing is blanked until the average has had time to settle.
+const SETTLING: usize = 1000;
+// So that after silence, the average can still rise.
+const MIN_AVERAGE: f32 = 1e-6;
+// While blanking, the last good sample fades away, rather than the audio stopping with a click.
+const FADE: f32 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseBlankerSettings {
+    pub enabled: bool,
+    pub threshold: f32, // Impulses are this many times the running average amplitude.
+}
+
+impl Default for NoiseBlankerSettings {
+    fn default() -> Self {
+        Self { enabled: false, threshold: 8.0 }
+    }
+}
+
+// Blanks impulses, such as clicks from switch-mode power supplies and electric fences, that are
+// much louder than the running average of the audio. It has to come before any filtering, which
+// would spread them out.
+pub struct NoiseBlanker {
+    settings: NoiseBlankerSettings,
+    delay: [f32; LOOKAHEAD],
+    position: usize,
+    average: f32,
+    settling: usize, // Samples until the average has settled.
+    blanking: usize, // The number of delayed samples still to blank.
+    last_good: f32,
+}
+
+impl NoiseBlanker {
+    pub fn new(settings: NoiseBlankerSettings) -> Self {
+        Self {
+            settings,
+            delay: [0.0; LOOKAHEAD],
+            position: 0,
+            average: 0.0,
+            settling: SETTLING,
+            blanking: 0,
+            last_good: 0.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: NoiseBlankerSettings) {
+        self.settings = settings;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        if !self.settings.enabled {
+            return input;
+        }
+        let magnitude = input.abs();
+        if self.settling > 0 {
+            self.settling -= 1;
+            self.average += (magnitude - self.average) * AVERAGE_RATE;
+        } else {
+            let limit = self.settings.threshold * self.average.max(MIN_AVERAGE);
+            if magnitude > limit {
+                self.blanking = LOOKAHEAD + HOLD;
+            }
+            // Impulses are limited, so they don't raise the average much; a signal that's
+            // suddenly much louder still raises it, so it's only blanked briefly.
+            self.average += (magnitude.min(limit) - self.average) * AVERAGE_RATE;
+        }
+
+        let delayed = self.delay[self.position];
+        self.delay[self.position] = input;
+        self.position = (self.position + 1) % LOOKAHEAD;
+        if self.blanking > 0 {
+            self.blanking -= 1;
+            self.last_good *= FADE;
+        } else {
+            self.last_good = delayed;
+        }
+        self.last_good
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::noise_blanker::{LOOKAHEAD, NoiseBlanker, NoiseBlankerSettings};
+    use crate::libs::dsp::test_signals::{ONE_SECOND, tone};
+
+    fn cw_tone(n: usize) -> f32 {
+        tone(700.0, 0.1, n)
+    }
+
+    #[test]
+    fn impulses_are_blanked() {
+        let mut noise_blanker = NoiseBlanker::new(NoiseBlankerSettings { enabled: true, threshold: 8.0 });
+        let mut peak: f32 = 0.0;
+        for n in 0..ONE_SECOND {
+            let impulse = if n % 4800 == 2400 { 1.0 } else { 0.0 };
+            let output = noise_blanker.process(cw_tone(n) + impulse);
+            if n > 4800 {
+                peak = peak.max(output.abs());
+            }
+        }
+        assert!(peak <= 0.1, "{}", peak);
+    }
+
+    #[test]
+    fn steady_signals_are_delayed_but_untouched() {
+        let mut noise_blanker = NoiseBlanker::new(NoiseBlankerSettings { enabled: true, threshold: 8.0 });
+        for n in 0..ONE_SECOND {
+            let output = noise_blanker.process(cw_tone(n));
+            if n >= LOOKAHEAD {
+                assert_eq!(output, cw_tone(n - LOOKAHEAD));
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// -------------------------------------------------------------------------------------------------
+// NOISE REDUCTION
+// -------------------------------------------------------------------------------------------------
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+// Frames of 256 samples (5.3ms at 48kHz), overlapping by half, so a frame is processed every other
+// 64-sample callback. All buffers are allocated up front, so the callback never allocates.
+const FRAME_SIZE: usize = 256;
+const HOP_SIZE: usize = FRAME_SIZE / 2;
+const BINS: usize = FRAME_SIZE / 2 + 1;
+
+// How much noise is subtracted, at full strength.
+const MAX_OVER_SUBTRACTION: f32 = 3.0;
+// The most a bin is reduced by (-20dB), limiting the 'musical noise' of spectral subtraction.
+const GAIN_FLOOR: f32 = 0.1;
+// Per frame: the noise estimate falls straight to a quieter level, but rises slowly (about
+// 8dB/second), so that signals aren't mistaken for noise.
+const NOISE_RISE: f32 = 1.005;
+const POWER_SMOOTHING: f32 = 0.2;
+// The noise is tracked from a more heavily smoothed power, so its dips are shallower; the minimum
+// of that is still below the average noise, by about this much.
+const NOISE_SMOOTHING: f32 = 0.05;
+const NOISE_MINIMUM_BIAS: f32 = 1.5;
+const GAIN_SMOOTHING: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoiseReductionSettings {
+    pub enabled: bool,
+    pub strength: f32, // 0.0 -> 1.0
+    // Hear the input without noise reduction, but with the same delay, to compare. Not saved.
+    #[serde(skip)]
+    pub bypass: bool,
+}
+
+impl Default for NoiseReductionSettings {
+    fn default() -> Self {
+        Self { enabled: false, strength: 0.5, bypass: false }
+    }
+}
+
+// Spectral subtraction: each frame's spectrum is reduced, bin by bin, by an estimate of the noise
+// in that bin, tracked from the quietest the bin has recently been. Frames are analysed and
+// resynthesised with square-root Hann windows, which overlap-add back to the input when nothing is
+// subtracted. It delays the audio by one frame.
+pub struct NoiseReduction {
+    settings: NoiseReductionSettings,
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    input: Vec<f32>, // The last frame of input; new samples go in the second half.
+    filled: usize, // How many new samples there are.
+    overlap: Vec<f32>, // The overlap-added output of the frames so far.
+    ready: Vec<f32>, // Finished output, being played during the next hop.
+    ready_bypassed: Vec<f32>, // The input that corresponds to it.
+    smoothed_power: Vec<f32>,
+    noise_power: Vec<f32>,
+    noise: Vec<f32>,
+    gains: Vec<f32>,
+    noise_initialised: bool,
+}
+
+impl NoiseReduction {
+    pub fn new(settings: NoiseReductionSettings) -> Self {
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(FRAME_SIZE);
+        let inverse = planner.plan_fft_inverse(FRAME_SIZE);
+        let scratch_length = forward.get_inplace_scratch_len().max(inverse.get_inplace_scratch_len());
+        Self {
+            settings,
+            forward,
+            inverse,
+            window: (0..FRAME_SIZE).map(|n| (PI * n as f32 / FRAME_SIZE as f32).sin()).collect(),
+            spectrum: vec![Complex::default(); FRAME_SIZE],
+            scratch: vec![Complex::default(); scratch_length],
+            input: vec![0.0; FRAME_SIZE],
+            filled: 0,
+            overlap: vec![0.0; FRAME_SIZE],
+            ready: vec![0.0; HOP_SIZE],
+            ready_bypassed: vec![0.0; HOP_SIZE],
+            smoothed_power: vec![0.0; BINS],
+            noise_power: vec![0.0; BINS],
+            noise: vec![0.0; BINS],
+            gains: vec![1.0; BINS],
+            noise_initialised: false,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: NoiseReductionSettings) {
+        self.settings = settings;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        if !self.settings.enabled {
+            return input;
+        }
+        let output = if self.settings.bypass { self.ready_bypassed[self.filled] } else { self.ready[self.filled] };
+        self.input[HOP_SIZE + self.filled] = input;
+        self.filled += 1;
+        if self.filled == HOP_SIZE {
+            self.process_frame();
+            self.filled = 0;
+        }
+        output
+    }
+
+    fn process_frame(&mut self) {
+        for ((bin, sample), window) in self.spectrum.iter_mut().zip(&self.input).zip(&self.window) {
+            *bin = Complex::new(sample * window, 0.0);
+        }
+        self.forward.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+
+        let over_subtraction = self.settings.strength.clamp(0.0, 1.0) * MAX_OVER_SUBTRACTION;
+        for k in 0..BINS {
+            let power = self.spectrum[k].norm_sqr();
+            if self.noise_initialised {
+                self.smoothed_power[k] += (power - self.smoothed_power[k]) * POWER_SMOOTHING;
+                self.noise_power[k] += (power - self.noise_power[k]) * NOISE_SMOOTHING;
+            } else {
+                self.smoothed_power[k] = power;
+                self.noise_power[k] = power;
+            }
+            self.noise[k] = if !self.noise_initialised || self.noise_power[k] < self.noise[k] {
+                self.noise_power[k]
+            } else {
+                self.noise[k] * NOISE_RISE
+            };
+            // Using the smoothed power, rather than this frame's, also reduces musical noise.
+            let gain = if self.smoothed_power[k] > 0.0 {
+                (1.0 - over_subtraction * NOISE_MINIMUM_BIAS * self.noise[k] / self.smoothed_power[k]).max(GAIN_FLOOR * GAIN_FLOOR).sqrt()
+            } else {
+                GAIN_FLOOR
+            };
+            self.gains[k] += (gain - self.gains[k]) * GAIN_SMOOTHING;
+            self.spectrum[k] *= self.gains[k];
+            // The negative frequencies mirror the positive ones.
+            if k > 0 && k < FRAME_SIZE / 2 {
+                self.spectrum[FRAME_SIZE - k] *= self.gains[k];
+            }
+        }
+        self.noise_initialised = true;
+
+        self.inverse.process_with_scratch(&mut self.spectrum, &mut self.scratch);
+        let scale = 1.0 / FRAME_SIZE as f32;
+        for ((overlap, bin), window) in self.overlap.iter_mut().zip(&self.spectrum).zip(&self.window) {
+            *overlap += bin.re * window * scale;
+        }
+
+        // The first half of the overlap now has all its contributions.
+        self.ready.copy_from_slice(&self.overlap[..HOP_SIZE]);
+        self.ready_bypassed.copy_from_slice(&self.input[..HOP_SIZE]);
+        self.overlap.copy_within(HOP_SIZE.., 0);
+        self.overlap[HOP_SIZE..].fill(0.0);
+        self.input.copy_within(HOP_SIZE.., 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::noise_reduction::{FRAME_SIZE, NoiseReduction, NoiseReductionSettings};
+    use crate::libs::dsp::test_signals::{ONE_SECOND, tone};
+    use crate::libs::random::random::XorShift;
+
+    #[test]
+    fn reconstructs_the_input_at_zero_strength() {
+        let mut noise_reduction = NoiseReduction::new(NoiseReductionSettings { enabled: true, strength: 0.0, bypass: false });
+        let input: Vec<f32> = (0..4096).map(|n| tone(440.0, 1.0, n)).collect();
+        let output: Vec<f32> = input.iter().map(|sample| noise_reduction.process(*sample)).collect();
+        for n in FRAME_SIZE * 2..input.len() {
+            assert!((output[n] - input[n - FRAME_SIZE]).abs() < 0.001, "{} {} {}", n, output[n], input[n - FRAME_SIZE]);
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced_and_a_tone_kept() {
+        let mut noise_reduction = NoiseReduction::new(NoiseReductionSettings { enabled: true, strength: 1.0, bypass: false });
+        let mut random = XorShift::new(3);
+        let mut noise_in = 0.0;
+        let mut noise_out = 0.0;
+        // A second of noise, then a second of a tone in the noise.
+        let mut tone_out = 0.0;
+        for n in 0..2 * ONE_SECOND {
+            let noise = 0.1 * (random.next_f64() as f32 - 0.5);
+            let signal = if n >= ONE_SECOND { tone(1000.0, 0.5, n) } else { 0.0 };
+            let output = noise_reduction.process(noise + signal);
+            if n > ONE_SECOND / 2 && n < ONE_SECOND {
+                noise_in += noise * noise;
+                noise_out += output * output;
+            } else if n > ONE_SECOND * 3 / 2 {
+                tone_out += output * output;
+            }
+        }
+        // The noise is down by more than 10dB; the tone (0.125 power per sample) mostly remains.
+        let tone_power = tone_out / (ONE_SECOND / 2) as f32;
+        assert!(noise_out < noise_in / 10.0, "{} {}", noise_out, noise_in);
+        assert!(tone_power > 0.1, "{}", tone_power);
+    }
+}
@@ -0,0 +1,77 @@
+// -------------------------------------------------------------------------------------------------
+// MANUAL NOTCH FILTER
+// -------------------------------------------------------------------------------------------------
+
+use serde::{Deserialize, Serialize};
+use crate::libs::audio::audio::SAMPLE_RATE;
+use crate::libs::dsp::biquad::Biquad;
+
+pub const MIN_NOTCH_HZ: f32 = 100.0;
+pub const MAX_NOTCH_HZ: f32 = 3000.0;
+pub const MIN_NOTCH_Q: f32 = 1.0;
+pub const MAX_NOTCH_Q: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NotchSettings {
+    pub enabled: bool,
+    pub frequency_hz: f32,
+    pub q: f32,
+}
+
+impl Default for NotchSettings {
+    fn default() -> Self {
+        Self { enabled: false, frequency_hz: 1000.0, q: 10.0 }
+    }
+}
+
+// Removes a single tone, e.g. a carrier or birdie, at a frequency chosen by the operator.
+pub struct Notch {
+    settings: NotchSettings,
+    section: Biquad,
+}
+
+impl Notch {
+    pub fn new(settings: NotchSettings) -> Self {
+        Self {
+            settings,
+            section: Self::design(settings),
+        }
+    }
+
+    fn design(settings: NotchSettings) -> Biquad {
+        Biquad::notch(SAMPLE_RATE,
+                      settings.frequency_hz.clamp(MIN_NOTCH_HZ, MAX_NOTCH_HZ) as f64,
+                      settings.q.clamp(MIN_NOTCH_Q, MAX_NOTCH_Q) as f64)
+    }
+
+    // Cheap if the settings are unchanged, so it can be called on every callback.
+    pub fn set_settings(&mut self, settings: NotchSettings) {
+        if settings == self.settings {
+            return;
+        }
+        self.settings = settings;
+        self.section.set_response(&Self::design(settings));
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        if self.settings.enabled {
+            self.section.process(input)
+        } else {
+            input
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::audio::audio::SAMPLE_RATE;
+    use crate::libs::dsp::biquad::Biquad;
+
+    #[test]
+    fn notch_removes_only_its_frequency() {
+        let notch = Biquad::notch(SAMPLE_RATE, 1000.0, 10.0);
+        assert!(notch.gain_at(SAMPLE_RATE, 1000.0) < 0.001);
+        assert!(notch.gain_at(SAMPLE_RATE, 700.0) > 0.95);
+        assert!(notch.gain_at(SAMPLE_RATE, 1500.0) > 0.95);
+    }
+}
@@ -0,0 +1,28 @@
+// -------------------------------------------------------------------------------------------------
+// TEST SIGNALS AND MEASUREMENTS
+// -------------------------------------------------------------------------------------------------
+
+use std::f32::consts::PI;
+use crate::libs::audio::audio::SAMPLE_RATE;
+
+pub const ONE_SECOND: usize = SAMPLE_RATE as usize;
+
+// Sample n of a sine at the frequency.
+pub fn tone(frequency_hz: f32, amplitude: f32, n: usize) -> f32 {
+    amplitude * (2.0 * PI * frequency_hz * n as f32 / SAMPLE_RATE as f32).sin()
+}
+
+// The peak of what process makes of a second of the tone, measured over the last half second, once
+// filters, envelopes and the like have settled.
+pub fn peak_output<F: FnMut(f32) -> f32>(frequency_hz: f32, amplitude: f32, mut process: F) -> f32 {
+    settled_output(frequency_hz, amplitude, &mut process).fold(0.0, |peak, output| peak.max(output.abs()))
+}
+
+// The same, but the mean, e.g. of a power.
+pub fn mean_output<F: FnMut(f32) -> f32>(frequency_hz: f32, amplitude: f32, mut process: F) -> f32 {
+    settled_output(frequency_hz, amplitude, &mut process).sum::<f32>() / (ONE_SECOND / 2) as f32
+}
+
+fn settled_output<F: FnMut(f32) -> f32>(frequency_hz: f32, amplitude: f32, process: &mut F) -> impl Iterator<Item = f32> + '_ {
+    (0..ONE_SECOND).map(move |n| process(tone(frequency_hz, amplitude, n))).skip(ONE_SECOND / 2)
+}
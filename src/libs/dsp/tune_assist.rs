@@ -0,0 +1,125 @@
+// -------------------------------------------------------------------------------------------------
+// TUNE ASSIST
+// -------------------------------------------------------------------------------------------------
+
+use std::f32::consts::PI;
+use serde::{Deserialize, Serialize};
+use crate::libs::audio::audio::SAMPLE_RATE;
+use crate::libs::dsp::bandpass::{Bandpass, BandpassPreset, BandpassSettings};
+
+// The noise is measured in a narrow window here, away from the edges of the QDX's passband, and
+// narrow enough to usually find a gap between signals.
+pub const TUNE_ASSIST_CENTRE_HZ: f32 = 1500.0;
+const TUNE_ASSIST_WINDOW: BandpassPreset = BandpassPreset::Cw100;
+
+// The level's time constant; the noise has to be smoothed a lot to show small changes.
+const SMOOTHING_SECONDS: f64 = 0.3;
+
+// Reported for silence, rather than -infinity.
+const FLOOR_DBFS: f32 = -150.0;
+
+// The tone's pitch rises by this much for each dB of noise, from its lowest at the quietest.
+const TONE_QUIETEST_DBFS: f32 = -130.0;
+const TONE_LOWEST_HZ: f32 = 300.0;
+const TONE_HIGHEST_HZ: f32 = 2000.0;
+const TONE_HZ_PER_DB: f32 = 20.0;
+const TONE_LEVEL: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TuneAssistSettings {
+    #[serde(skip)]
+    pub enabled: bool, // Always off at startup.
+    pub tone: bool, // Whether the receive audio is replaced by the tone.
+}
+
+impl Default for TuneAssistSettings {
+    fn default() -> Self {
+        Self { enabled: false, tone: true }
+    }
+}
+
+// For tuning an antenna (or ATU) without transmitting, by peaking the band noise it receives.
+// The noise power is measured in a narrow window of the input; optionally, the receive audio is
+// replaced by a tone whose pitch rises with it, so that the tuning can be done by ear.
+pub struct TuneAssist {
+    settings: TuneAssistSettings,
+    window: Bandpass,
+    smoothing: f32,
+    power: f32, // The mean square in the window.
+    phase: f32, // Of the tone, in radians.
+}
+
+impl TuneAssist {
+    pub fn new(settings: TuneAssistSettings) -> Self {
+        Self {
+            settings,
+            window: Bandpass::new(BandpassSettings { preset: TUNE_ASSIST_WINDOW, centre_hz: TUNE_ASSIST_CENTRE_HZ }),
+            smoothing: (1.0 - (-1.0 / (SMOOTHING_SECONDS * SAMPLE_RATE)).exp()) as f32,
+            power: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: TuneAssistSettings) {
+        self.settings = settings;
+    }
+
+    // Measures the input, and returns either the received audio, or the tone.
+    pub fn process(&mut self, input: f32, received: f32) -> f32 {
+        if !self.settings.enabled {
+            return received;
+        }
+        let windowed = self.window.process(input);
+        self.power += (windowed * windowed - self.power) * self.smoothing;
+        if !self.settings.tone {
+            return received;
+        }
+        self.phase += 2.0 * PI * tone_pitch_hz(self.level_dbfs()) / SAMPLE_RATE as f32;
+        if self.phase > 2.0 * PI {
+            self.phase -= 2.0 * PI;
+        }
+        TONE_LEVEL * self.phase.sin()
+    }
+
+    pub fn level_dbfs(&self) -> f32 {
+        if self.power > 0.0 { (10.0 * self.power.log10()).max(FLOOR_DBFS) } else { FLOOR_DBFS }
+    }
+}
+
+pub fn tone_pitch_hz(level_dbfs: f32) -> f32 {
+    (TONE_LOWEST_HZ + (level_dbfs - TONE_QUIETEST_DBFS) * TONE_HZ_PER_DB).clamp(TONE_LOWEST_HZ, TONE_HIGHEST_HZ)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::test_signals::{ONE_SECOND, tone};
+    use crate::libs::dsp::tune_assist::{tone_pitch_hz, TUNE_ASSIST_CENTRE_HZ, TuneAssist, TuneAssistSettings};
+
+    fn level_of_tone(frequency_hz: f32) -> f32 {
+        let mut tune_assist = TuneAssist::new(TuneAssistSettings { enabled: true, tone: false });
+        for n in 0..2 * ONE_SECOND {
+            assert_eq!(tune_assist.process(tone(frequency_hz, 0.01, n), 0.5), 0.5);
+        }
+        tune_assist.level_dbfs()
+    }
+
+    #[test]
+    fn only_the_window_is_measured() {
+        // A sine's mean square is half its amplitude squared.
+        let in_window = level_of_tone(TUNE_ASSIST_CENTRE_HZ);
+        assert!((in_window + 43.0).abs() < 0.5, "{}", in_window);
+        let outside = level_of_tone(TUNE_ASSIST_CENTRE_HZ + 500.0);
+        assert!(outside < in_window - 40.0, "{}", outside);
+    }
+
+    #[test]
+    fn the_tone_rises_with_the_noise() {
+        assert_eq!(tone_pitch_hz(-150.0), 300.0);
+        assert_eq!(tone_pitch_hz(-100.0), 900.0);
+        assert_eq!(tone_pitch_hz(-99.0), 920.0);
+        assert_eq!(tone_pitch_hz(0.0), 2000.0);
+        let mut tune_assist = TuneAssist::new(TuneAssistSettings { enabled: true, tone: true });
+        let peak = (0..4800).map(|_| tune_assist.process(0.0, 0.5).abs()).fold(0.0, f32::max);
+        assert!((peak - 0.3).abs() < 0.01, "{}", peak);
+    }
+}
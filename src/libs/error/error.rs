@@ -0,0 +1,116 @@
+// -------------------------------------------------------------------------------------------------
+// ERRORS FROM THE CAT, AUDIO AND SERIAL LAYERS
+// -------------------------------------------------------------------------------------------------
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum QdxError {
+    // The QDX, or some other device, isn't connected (or can't be found with the name given).
+    DeviceNotFound(String),
+    // More than one device matches, and the user has to choose.
+    DeviceAmbiguous(String),
+    // The device was found, but couldn't be opened, e.g. it's in use by another program.
+    DeviceOpenFailed(String),
+    // The QDX didn't respond in time; it may be busy, or gone.
+    Timeout(String),
+    // The connection to the QDX has failed, e.g. the USB cable has been unplugged.
+    Disconnected(String),
+    // A request that we should never have tried to send.
+    InvalidRequest(String),
+    // The QDX responded with something that doesn't parse as the expected response.
+    MalformedResponse(String),
+    // The QDX responded, but to a different request.
+    MismatchedResponse { request: String, response: String },
+    // The audio device can't work at our sample rate / channel count.
+    AudioFormatUnsupported(String),
+    Audio(portaudio::Error),
+    Io(io::Error),
+}
+
+impl QdxError {
+    // Failures worth trying again on the same connection.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, QdxError::Timeout(_) | QdxError::MalformedResponse(_) | QdxError::MismatchedResponse { .. })
+    }
+
+    // Failures that mean the radio has to be found and reconnected.
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(self, QdxError::Disconnected(_) | QdxError::Io(_))
+    }
+
+    // What the user could do about it.
+    pub fn advice(&self) -> Option<&'static str> {
+        match self {
+            QdxError::DeviceNotFound(_) => Some("Check that the QDX is plugged in and powered on, or choose the device on the command line."),
+            QdxError::DeviceAmbiguous(_) => Some("Choose one of the devices on the command line."),
+            QdxError::DeviceOpenFailed(_) => Some("Check that no other program (e.g. WSJT-X) is using the QDX's serial port."),
+            QdxError::Timeout(_) => Some("Check that the QDX is powered on, and that its CAT port is the one being used."),
+            QdxError::Disconnected(_) | QdxError::Io(_) => Some("Check the QDX's USB cable and power."),
+            QdxError::MalformedResponse(_) | QdxError::MismatchedResponse { .. } => Some("Check that the device on the CAT port is a QDX, and that its firmware is up to date."),
+            QdxError::AudioFormatUnsupported(_) => Some("Choose another audio device that can play stereo at 48kHz."),
+            QdxError::InvalidRequest(_) | QdxError::Audio(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for QdxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QdxError::DeviceNotFound(message) => write!(f, "{}", message),
+            QdxError::DeviceAmbiguous(message) => write!(f, "{}", message),
+            QdxError::DeviceOpenFailed(message) => write!(f, "{}", message),
+            QdxError::Timeout(message) => write!(f, "{}", message),
+            QdxError::Disconnected(message) => write!(f, "{}", message),
+            QdxError::InvalidRequest(message) => write!(f, "{}", message),
+            QdxError::MalformedResponse(message) => write!(f, "{}", message),
+            QdxError::MismatchedResponse { request, response } => write!(f, "QDX response did not match request '{}': '{}'", request, response),
+            QdxError::AudioFormatUnsupported(message) => write!(f, "{}", message),
+            QdxError::Audio(err) => write!(f, "Audio error: {}", err),
+            QdxError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl Error for QdxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QdxError::Audio(err) => Some(err),
+            QdxError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_timeout(err: &io::Error) -> bool {
+    // Serial ports report TimedOut; sockets with a read timeout report WouldBlock on Unix.
+    matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+}
+
+impl From<io::Error> for QdxError {
+    fn from(err: io::Error) -> Self {
+        if is_timeout(&err) {
+            QdxError::Timeout(format!("No response from QDX: {}", err))
+        } else {
+            QdxError::Io(err)
+        }
+    }
+}
+
+impl From<portaudio::Error> for QdxError {
+    fn from(err: portaudio::Error) -> Self {
+        QdxError::Audio(err)
+    }
+}
+
+impl From<serialport::Error> for QdxError {
+    fn from(err: serialport::Error) -> Self {
+        match err.kind {
+            serialport::ErrorKind::NoDevice => QdxError::DeviceNotFound(err.description),
+            serialport::ErrorKind::Io(kind) => QdxError::Io(io::Error::new(kind, err.description)),
+            _ => QdxError::DeviceOpenFailed(err.description),
+        }
+    }
+}
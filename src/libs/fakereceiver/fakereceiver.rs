@@ -3,26 +3,68 @@
 // -------------------------------------------------------------------------------------------------
 
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::SyncSender;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
 use log::{debug, info};
+use crate::libs::dsp::agc::{AgcPreset, AgcSettings};
+use crate::libs::dsp::bandpass::BandpassSettings;
+use crate::libs::dsp::noise_blanker::NoiseBlankerSettings;
+use crate::libs::dsp::noise_reduction::NoiseReductionSettings;
+use crate::libs::dsp::notch::NotchSettings;
+use crate::libs::dsp::tune_assist::TuneAssistSettings;
 use crate::libs::gui_api::gui_api::{GUIInput, GUIInputMessage, GUIOutput};
+use crate::libs::random::random::XorShift;
+use crate::libs::smeter::ballistics::{Ballistics, MeterBallistics};
+use crate::libs::smeter::smeter::{DEFAULT_CALIBRATION_DB, SignalLevel};
+use crate::libs::spectrum::spectrum::{BIN_WIDTH_HZ, SPECTRUM_BINS};
+
+// Fake carriers, as offsets from the initial frequency, so that retuning moves them across the
+// spectrum.
+const FAKE_CARRIER_OFFSETS_HZ: [i64; 3] = [700, 1240, 1850];
+const FAKE_CARRIER_LEVEL_DB: f32 = -40.0;
+const FAKE_NOISE_LEVEL_DB: f32 = -100.0;
+// The fake signal level sweeps up and down between these.
+const FAKE_WEAKEST_DBFS: f32 = -100.0;
+const FAKE_STRONGEST_DBFS: f32 = -20.0;
+// The audio that would have been received between updates (250ms).
+const FAKE_UPDATE_SAMPLES: usize = 12000;
+// The band noise rises and falls between these, as if an ATU were being adjusted.
+const FAKE_WORST_NOISE_DBFS: f32 = -115.0;
+const FAKE_BEST_NOISE_DBFS: f32 = -95.0;
+// The gain reduction at the strongest fake signal, when the AGC is on.
+const FAKE_MAX_GAIN_REDUCTION_DB: f32 = 40.0;
 
 pub struct FakeReceiver {
     gui_input: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>,
     read_thread_handle: Mutex<Option<JoinHandle<()>>>,
+    frequency: Arc<AtomicU32>,
+    meter_calibration_db: Arc<Mutex<f32>>,
+    meter: Arc<Mutex<Ballistics>>,
+    agc_on: Arc<AtomicBool>,
+    tune_assist_on: Arc<AtomicBool>,
 }
 
 impl FakeReceiver {
-    pub fn new(terminate: Arc<AtomicBool>) -> Self {
+    pub fn new(terminate: Arc<AtomicBool>, frequency: u32) -> Self {
         let gui_input_holder: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>> = Arc::new(Mutex::new(None));
         let thread_gui_input_holder = gui_input_holder.clone();
+        let arc_meter_calibration_db = Arc::new(Mutex::new(DEFAULT_CALIBRATION_DB));
+        let thread_meter_calibration_db = arc_meter_calibration_db.clone();
+        let arc_meter = Arc::new(Mutex::new(Ballistics::new(MeterBallistics::Fast)));
+        let thread_meter = arc_meter.clone();
+        let arc_frequency = Arc::new(AtomicU32::new(frequency));
+        let thread_frequency = arc_frequency.clone();
+        let arc_agc_on = Arc::new(AtomicBool::new(false));
+        let thread_agc_on = arc_agc_on.clone();
+        let arc_tune_assist_on = Arc::new(AtomicBool::new(false));
+        let thread_tune_assist_on = arc_tune_assist_on.clone();
         let read_thread_handle = thread::spawn(move || {
             let mut strength: f32 = 0.0;
             let mut strength_sign = 1.0;
+            let mut random = XorShift::new(1);
             loop {
                 if terminate.load(Ordering::SeqCst) {
                     info!("Terminating FakeReceiver thread");
@@ -34,7 +76,22 @@ impl FakeReceiver {
                     None => {
                     }
                     Some(gui_input) => {
-                        gui_input.send(GUIInputMessage::SignalStrength(strength)).unwrap();
+                        let dbfs = FAKE_WEAKEST_DBFS + strength * (FAKE_STRONGEST_DBFS - FAKE_WEAKEST_DBFS);
+                        let calibration_db = *thread_meter_calibration_db.lock().unwrap();
+                        let mut meter = thread_meter.lock().unwrap();
+                        meter.process(10.0_f32.powf(dbfs / 10.0), FAKE_UPDATE_SAMPLES);
+                        let signal_level = SignalLevel::from_mean_square(meter.level(), calibration_db);
+                        let peak_level = SignalLevel::from_mean_square(meter.peak(), calibration_db);
+                        drop(meter);
+                        gui_input.send(GUIInputMessage::SignalLevel(signal_level, peak_level)).unwrap();
+                        let gain_reduction_db = if thread_agc_on.load(Ordering::SeqCst) { strength * FAKE_MAX_GAIN_REDUCTION_DB } else { 0.0 };
+                        gui_input.send(GUIInputMessage::GainReduction(gain_reduction_db)).unwrap();
+                        if thread_tune_assist_on.load(Ordering::SeqCst) {
+                            let noise_dbfs = FAKE_WORST_NOISE_DBFS + strength * (FAKE_BEST_NOISE_DBFS - FAKE_WORST_NOISE_DBFS);
+                            gui_input.send(GUIInputMessage::TuneAssistLevel(noise_dbfs)).unwrap();
+                        }
+                        let tuned = thread_frequency.load(Ordering::SeqCst);
+                        gui_input.send(GUIInputMessage::Spectrum(Self::fake_spectrum(frequency, tuned, &mut random))).unwrap();
                         strength += 0.05 * strength_sign;
                         if strength < 0.0 {
                             strength = 0.0;
@@ -51,8 +108,34 @@ impl FakeReceiver {
         Self {
             gui_input: gui_input_holder,
             read_thread_handle: Mutex::new(Some(read_thread_handle)),
+            frequency: arc_frequency,
+            meter_calibration_db: arc_meter_calibration_db,
+            meter: arc_meter,
+            agc_on: arc_agc_on,
+            tune_assist_on: arc_tune_assist_on,
         }
     }
+
+    // The frequency the fake rig is 'tuned' to, as the real receiver would obtain from the QDX.
+    pub fn get_frequency(&self) -> u32 {
+        self.frequency.load(Ordering::SeqCst)
+    }
+
+    fn fake_spectrum(initial_frequency: u32, tuned_frequency: u32, random: &mut XorShift) -> Vec<f32> {
+        let mut spectrum: Vec<f32> = (0..SPECTRUM_BINS)
+            .map(|_| FAKE_NOISE_LEVEL_DB + 6.0 * random.next_f64() as f32)
+            .collect();
+        for offset in FAKE_CARRIER_OFFSETS_HZ {
+            let audio_hz = initial_frequency as i64 + offset - tuned_frequency as i64;
+            if audio_hz >= 0 {
+                let bin = (audio_hz as f32 / BIN_WIDTH_HZ).round() as usize;
+                if bin < SPECTRUM_BINS {
+                    spectrum[bin] = FAKE_CARRIER_LEVEL_DB;
+                }
+            }
+        }
+        spectrum
+    }
 }
 
 impl GUIInput for FakeReceiver {
@@ -62,11 +145,56 @@ impl GUIInput for FakeReceiver {
 }
 
 impl GUIOutput for FakeReceiver {
-    fn set_frequency(&mut self, _frequency_hz: u32) {
+    fn set_frequency(&mut self, frequency_hz: u32) {
+        info!("FakeReceiver tuned to {}", frequency_hz);
+        self.frequency.store(frequency_hz, Ordering::SeqCst);
     }
 
     fn set_amplitude(&mut self, _amplitude: f32) {
     }
+
+    fn set_spectrum_averaging(&mut self, _averaging: u32) {
+    }
+
+    fn set_bandpass(&mut self, settings: BandpassSettings) {
+        info!("FakeReceiver bandpass {:?}", settings);
+    }
+
+    fn set_notch(&mut self, settings: NotchSettings) {
+        info!("FakeReceiver notch {:?}", settings);
+    }
+
+    fn set_auto_notch(&mut self, enabled: bool) {
+        info!("FakeReceiver auto notch {}", enabled);
+    }
+
+    fn set_noise_reduction(&mut self, settings: NoiseReductionSettings) {
+        info!("FakeReceiver noise reduction {:?}", settings);
+    }
+
+    fn set_noise_blanker(&mut self, settings: NoiseBlankerSettings) {
+        info!("FakeReceiver noise blanker {:?}", settings);
+    }
+
+    fn set_agc(&mut self, settings: AgcSettings) {
+        info!("FakeReceiver AGC {:?}", settings);
+        self.agc_on.store(settings.preset != AgcPreset::Off, Ordering::SeqCst);
+    }
+
+    fn set_meter_calibration(&mut self, calibration_db: f32) {
+        info!("FakeReceiver meter calibration {}", calibration_db);
+        *self.meter_calibration_db.lock().unwrap() = calibration_db;
+    }
+
+    fn set_meter_ballistics(&mut self, ballistics: MeterBallistics) {
+        info!("FakeReceiver meter ballistics {:?}", ballistics);
+        self.meter.lock().unwrap().set_ballistics(ballistics);
+    }
+
+    fn set_tune_assist(&mut self, settings: TuneAssistSettings) {
+        info!("FakeReceiver tune assist {:?}", settings);
+        self.tune_assist_on.store(settings.enabled, Ordering::SeqCst);
+    }
 }
 
 impl Drop for FakeReceiver {
@@ -0,0 +1,304 @@
+// -------------------------------------------------------------------------------------------------
+// FLRIG - XML-RPC CONTROL SERVER
+// -------------------------------------------------------------------------------------------------
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use log::{debug, warn};
+use regex::Regex;
+use crate::libs::cat::cat::{Cat, RigMode, Vfo};
+use crate::libs::cat_worker::cat_worker::RememberedFrequency;
+use crate::libs::error::error::{is_timeout, QdxError};
+use crate::libs::tcp_server::tcp_server::{POLL_INTERVAL, TcpServer};
+
+pub const DEFAULT_FLRIG_PORT: u16 = 12345;
+
+// Once the headers have arrived, the body should follow promptly.
+const BODY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Clients check the flrig version to decide which methods they can use; these are all present in
+// this one.
+const FLRIG_VERSION: &str = "1.4.7";
+
+// The QDX doesn't report its filter width; this is what's reported to clients.
+const PASSBAND_HZ: u32 = 3000;
+
+const TRANSCEIVER_NAME: &str = "QDX";
+
+// Requests larger than this are refused; real ones are a few hundred bytes.
+const MAX_CONTENT_LENGTH: usize = 65536;
+
+const MODES: [RigMode; 8] = [RigMode::Lsb, RigMode::Usb, RigMode::Cw, RigMode::Fm, RigMode::Am, RigMode::Fsk, RigMode::CwReverse, RigMode::FskReverse];
+
+const METHODS: [&str; 16] = [
+    "main.get_version",
+    "rig.get_AB",
+    "rig.get_bw",
+    "rig.get_modes",
+    "rig.get_mode",
+    "rig.get_ptt",
+    "rig.get_vfo",
+    "rig.get_vfoA",
+    "rig.get_vfoB",
+    "rig.get_xcvr",
+    "rig.set_mode",
+    "rig.set_vfo",
+    "rig.set_vfoA",
+    "rig.set_vfoB",
+    "system.listMethods",
+    "system.methodHelp",
+];
+
+// Serves a subset of flrig's XML-RPC API to other programs (e.g. fldigi, JS8Call, or WSJT-X set
+// to "FLRig"), so they can share the QDX's CAT port with qdx-receiver.
+pub struct FlrigServer {
+    _server: TcpServer,
+}
+
+impl FlrigServer {
+    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: RememberedFrequency, port: u16) -> Result<Self, QdxError> {
+        let server = TcpServer::new(terminate, "flrig", port, move |terminate, stream| {
+            Self::serve_client(terminate, cat.clone(), frequency.clone(), stream)
+        })?;
+        Ok(Self {
+            _server: server,
+        })
+    }
+
+    // Handles HTTP POSTs on a connection until the client closes it. Clients usually keep the
+    // connection alive between calls.
+    fn serve_client(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: RememberedFrequency, stream: TcpStream) -> Result<(), QdxError> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        let mut content_length: Option<usize> = None;
+        let mut keep_alive = true;
+        let mut in_headers = false;
+        loop {
+            if terminate.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            // A timeout may leave part of a line read; it's completed on the next attempt.
+            match reader.read_line(&mut line) {
+                Ok(0) => {
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(e) if is_timeout(&e) => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            }
+            let header = line.trim_end().to_string();
+            line.clear();
+            if !in_headers {
+                // The request line; the method and path don't matter.
+                if !header.is_empty() {
+                    in_headers = true;
+                    content_length = None;
+                    keep_alive = !header.ends_with("HTTP/1.0");
+                }
+                continue;
+            }
+            if !header.is_empty() {
+                if let Some((name, value)) = header.split_once(':') {
+                    let value = value.trim();
+                    match name.trim().to_ascii_lowercase().as_str() {
+                        "content-length" => content_length = value.parse().ok(),
+                        "connection" => keep_alive = !value.eq_ignore_ascii_case("close"),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            // End of headers; the body follows.
+            in_headers = false;
+            let length = match content_length {
+                Some(length) if length <= MAX_CONTENT_LENGTH => length,
+                _ => {
+                    writer.write_all(b"HTTP/1.1 411 Length Required\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+                    return Ok(());
+                }
+            };
+            let mut body = vec![0u8; length];
+            reader.get_mut().set_read_timeout(Some(BODY_TIMEOUT))?;
+            reader.read_exact(&mut body)?;
+            reader.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+
+            let response = handle_request(&mut cat.lock().unwrap(), &frequency, &String::from_utf8_lossy(&body));
+            let headers = format!("HTTP/1.1 200 OK\r\nServer: qdx-receiver\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+                response.len(), if keep_alive { "keep-alive" } else { "close" });
+            writer.write_all(headers.as_bytes())?;
+            writer.write_all(response.as_bytes())?;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+fn string_value(text: &str) -> String {
+    format!("<value>{}</value>", xml_escape(text))
+}
+
+fn int_value(value: i32) -> String {
+    format!("<value><i4>{}</i4></value>", value)
+}
+
+fn array_value(values: &[String]) -> String {
+    let data: String = values.iter().map(|value| string_value(value)).collect();
+    format!("<value><array><data>{}</data></array></value>", data)
+}
+
+fn method_response(value: &str) -> String {
+    format!("<?xml version=\"1.0\"?>\n<methodResponse><params><param>{}</param></params></methodResponse>\n", value)
+}
+
+fn fault_response(code: i32, message: &str) -> String {
+    format!("<?xml version=\"1.0\"?>\n<methodResponse><fault><value><struct>\
+        <member><name>faultCode</name>{}</member>\
+        <member><name>faultString</name>{}</member>\
+        </struct></value></fault></methodResponse>\n", int_value(code), string_value(message))
+}
+
+// The method name and the text of each parameter, whatever its type.
+fn parse_method_call(body: &str) -> Option<(String, Vec<String>)> {
+    let method_regex = Regex::new(r"<methodName>\s*([\w.]+)\s*</methodName>").unwrap();
+    let param_regex = Regex::new(r"<param>\s*<value>\s*(?:<(?:string|double|int|i4|i8|boolean)>)?([^<]*)").unwrap();
+    let method = method_regex.captures(body)?.get(1)?.as_str().to_string();
+    let params = param_regex.captures_iter(body)
+        .map(|captures| xml_unescape(captures[1].trim()))
+        .collect();
+    Some((method, params))
+}
+
+fn frequency_param(params: &[String]) -> Result<u32, QdxError> {
+    // Clients send frequencies as doubles, e.g. 14074000.000000.
+    match params.first().and_then(|param| param.parse::<f64>().ok()) {
+        Some(frequency_hz) if (0.0..=u32::MAX as f64).contains(&frequency_hz) => Ok(frequency_hz.round() as u32),
+        _ => Err(QdxError::InvalidRequest(format!("Invalid frequency parameter {:?}", params.first()))),
+    }
+}
+
+// Handles one XML-RPC methodCall, returning the methodResponse. Failures are returned as faults.
+// VFO A frequencies set are remembered, to be restored on reconnection.
+pub fn handle_request(cat: &mut Cat, frequency: &RememberedFrequency, body: &str) -> String {
+    let (method, params) = match parse_method_call(body) {
+        Some(call) => call,
+        None => {
+            return fault_response(-1, "Malformed XML-RPC request");
+        }
+    };
+    debug!("flrig request {} {:?}", method, params);
+    let result = match method.as_str() {
+        "main.get_version" => Ok(string_value(FLRIG_VERSION)),
+        "rig.get_xcvr" => Ok(string_value(TRANSCEIVER_NAME)),
+        "rig.get_vfo" => cat.get_frequency().map(|frequency_hz| string_value(&frequency_hz.to_string())),
+        "rig.get_vfoA" => cat.get_vfo_frequency(Vfo::A).map(|frequency_hz| string_value(&frequency_hz.to_string())),
+        "rig.get_vfoB" => cat.get_vfo_frequency(Vfo::B).map(|frequency_hz| string_value(&frequency_hz.to_string())),
+        "rig.set_vfo" | "rig.set_vfoA" => frequency_param(&params).and_then(|frequency_hz| {
+            cat.set_frequency(frequency_hz)?;
+            frequency.set(frequency_hz);
+            Ok(string_value(""))
+        }),
+        "rig.set_vfoB" => frequency_param(&params).and_then(|frequency_hz| cat.set_vfo_frequency(Vfo::B, frequency_hz)).map(|_| string_value("")),
+        "rig.get_mode" => cat.get_mode().map(|mode| string_value(mode.name())),
+        "rig.set_mode" => {
+            match params.first().and_then(|name| MODES.iter().find(|mode| mode.name() == name)) {
+                Some(mode) => cat.set_mode(*mode).map(|_| string_value("")),
+                None => Err(QdxError::InvalidRequest(format!("Unknown mode {:?}", params.first()))),
+            }
+        }
+        "rig.get_modes" => Ok(array_value(&MODES.iter().map(|mode| mode.name().to_string()).collect::<Vec<String>>())),
+        "rig.get_ptt" => cat.is_transmitting().map(|transmitting| int_value(if transmitting { 1 } else { 0 })),
+        "rig.get_AB" => cat.get_receive_vfo().map(|vfo| string_value(if vfo == Vfo::B { "B" } else { "A" })),
+        "rig.get_bw" => Ok(array_value(&[PASSBAND_HZ.to_string(), String::new()])),
+        "system.listMethods" => Ok(array_value(&METHODS.iter().map(|name| name.to_string()).collect::<Vec<String>>())),
+        "system.methodHelp" => Ok(string_value("")),
+        _ => {
+            debug!("Unsupported flrig method {}", method);
+            return fault_response(-1, format!("Unknown method {}", method).as_str());
+        }
+    };
+    match result {
+        Ok(value) => method_response(&value),
+        Err(err) => {
+            warn!("flrig method {} failed: {}", method, err);
+            fault_response(-2, err.to_string().as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::cat::cat::Cat;
+    use crate::libs::cat_worker::cat_worker::RememberedFrequency;
+    use crate::libs::flrig::flrig::handle_request;
+    use crate::libs::transport::transport::MockTransport;
+
+    fn cat_with(transport: &MockTransport) -> Cat {
+        Cat::new(Box::new(transport.clone()))
+    }
+
+    fn call(method: &str, params: &str) -> String {
+        format!("<?xml version=\"1.0\"?><methodCall><methodName>{}</methodName><params>{}</params></methodCall>", method, params)
+    }
+
+    #[test]
+    fn get_and_set_vfo() {
+        let transport = MockTransport::new();
+        transport.expect("FA;", "FA00014074000;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert!(handle_request(&mut cat, &frequency, &call("rig.get_vfo", "")).contains("<param><value>14074000</value></param>"));
+        let response = handle_request(&mut cat, &frequency, &call("rig.set_vfo", "<param><value><double>7074000.000000</double></value></param>"));
+        assert!(!response.contains("<fault>"));
+        assert_eq!(transport.written(), "FA;FA00007074000;");
+        assert_eq!(frequency.get(), 7074000);
+    }
+
+    #[test]
+    fn get_and_set_mode() {
+        let transport = MockTransport::new();
+        transport.expect("MD;", "MD2;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert!(handle_request(&mut cat, &frequency, &call("rig.get_mode", "")).contains("<value>USB</value>"));
+        assert!(!handle_request(&mut cat, &frequency, &call("rig.set_mode", "<param><value>CW</value></param>")).contains("<fault>"));
+        assert!(handle_request(&mut cat, &frequency, &call("rig.set_mode", "<param><value>WFM</value></param>")).contains("<fault>"));
+        assert_eq!(transport.written(), "MD;MD3;");
+    }
+
+    #[test]
+    fn unknown_method_is_a_fault() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        let response = handle_request(&mut cat, &frequency, &call("rig.set_ptt", "<param><value><i4>1</i4></value></param>"));
+        assert!(response.contains("<fault>"));
+        assert!(response.contains("Unknown method rig.set_ptt"));
+        assert_eq!(transport.written(), "");
+    }
+
+    #[test]
+    fn failures_are_faults() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert!(handle_request(&mut cat, &frequency, &call("rig.get_vfo", "")).contains("<fault>"));
+    }
+}
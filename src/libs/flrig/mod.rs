@@ -0,0 +1 @@
+pub mod flrig;
\ No newline at end of file
@@ -0,0 +1,290 @@
+// -------------------------------------------------------------------------------------------------
+// DSP CONTROLS
+// -------------------------------------------------------------------------------------------------
+
+use fltk::app::Sender;
+use fltk::button::CheckButton;
+use fltk::enums::Align;
+use fltk::menu::Choice;
+use fltk::prelude::*;
+use fltk::valuator::SliderType::Horizontal;
+use fltk::valuator::ValueSlider;
+use crate::libs::dsp::agc::{AGC_PRESETS, AgcSettings, MAX_MAX_GAIN_DB, MIN_MAX_GAIN_DB};
+use crate::libs::dsp::bandpass::{BANDPASS_PRESETS, BandpassSettings, MAX_CENTRE_HZ, MIN_CENTRE_HZ};
+use crate::libs::dsp::noise_blanker::{MAX_BLANKER_THRESHOLD, MIN_BLANKER_THRESHOLD, NoiseBlankerSettings};
+use crate::libs::dsp::noise_reduction::NoiseReductionSettings;
+use crate::libs::dsp::notch::{MAX_NOTCH_HZ, MAX_NOTCH_Q, MIN_NOTCH_HZ, MIN_NOTCH_Q, NotchSettings};
+use crate::libs::gui::gui::WIDGET_PADDING;
+use crate::libs::gui_api::gui_api::Message;
+
+const DSP_ROW_HEIGHT: i32 = 25;
+const DSP_ROWS: i32 = 4;
+pub const DSP_CONTROLS_HEIGHT: i32 = DSP_ROWS * DSP_ROW_HEIGHT + (DSP_ROWS - 1) * WIDGET_PADDING;
+
+const CONTROL_LABEL_WIDTH: i32 = 50;
+const CONTROL_LABEL_SIZE: i32 = 12;
+
+// The settings of the controls, as saved in the configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct DspSettings {
+    pub bandpass: BandpassSettings,
+    pub notch: NotchSettings,
+    pub auto_notch: bool,
+    pub noise_reduction: NoiseReductionSettings,
+    pub noise_blanker: NoiseBlankerSettings,
+    pub agc: AgcSettings,
+}
+
+// The controls for the receive audio processing, in rows across the bottom of the window.
+pub struct DspControls {
+    bandpass_choice: Choice,
+    bandpass_centre_slider: ValueSlider,
+    agc_choice: Choice,
+    agc_max_gain_slider: ValueSlider,
+    notch_button: CheckButton,
+    notch_frequency_slider: ValueSlider,
+    notch_q_slider: ValueSlider,
+    auto_notch_button: CheckButton,
+    noise_reduction_button: CheckButton,
+    noise_reduction_strength_slider: ValueSlider,
+    noise_reduction_bypass_button: CheckButton,
+    noise_blanker_button: CheckButton,
+    noise_blanker_threshold_slider: ValueSlider,
+    tune_assist_button: CheckButton,
+}
+
+impl DspControls {
+    pub fn new(x: i32, y: i32, sender: Sender<Message>, settings: DspSettings) -> Self {
+        let bandpass_choice_width = 90;
+        let centre_x = x + CONTROL_LABEL_WIDTH + bandpass_choice_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let centre_width = 200;
+        let agc_x = centre_x + centre_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let agc_choice_width = 90;
+        let max_gain_x = agc_x + agc_choice_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let notch_y = y + DSP_ROW_HEIGHT + WIDGET_PADDING;
+        let notch_button_width = 70;
+        let notch_frequency_x = x + notch_button_width + CONTROL_LABEL_WIDTH;
+        let notch_frequency_width = 200;
+        let notch_q_x = notch_frequency_x + notch_frequency_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let notch_q_width = 150;
+        let auto_notch_x = notch_q_x + notch_q_width + WIDGET_PADDING;
+        let noise_reduction_y = notch_y + DSP_ROW_HEIGHT + WIDGET_PADDING;
+        let noise_reduction_button_width = 70;
+        let strength_x = x + noise_reduction_button_width + CONTROL_LABEL_WIDTH;
+        let strength_width = 200;
+        let bypass_x = strength_x + strength_width + WIDGET_PADDING;
+        let noise_blanker_y = noise_reduction_y + DSP_ROW_HEIGHT + WIDGET_PADDING;
+        let noise_blanker_button_width = 70;
+        let threshold_x = x + noise_blanker_button_width + CONTROL_LABEL_WIDTH;
+        let threshold_width = 200;
+        let tune_assist_x = threshold_x + threshold_width + WIDGET_PADDING;
+        let mut controls = Self {
+            bandpass_choice: Choice::default()
+                .with_size(bandpass_choice_width, DSP_ROW_HEIGHT)
+                .with_pos(x + CONTROL_LABEL_WIDTH, y)
+                .with_label("Filter"),
+            bandpass_centre_slider: ValueSlider::default()
+                .with_size(centre_width, DSP_ROW_HEIGHT)
+                .with_pos(centre_x, y)
+                .with_label("Centre"),
+            agc_choice: Choice::default()
+                .with_size(agc_choice_width, DSP_ROW_HEIGHT)
+                .with_pos(agc_x, y)
+                .with_label("AGC"),
+            agc_max_gain_slider: ValueSlider::default()
+                .with_size(150, DSP_ROW_HEIGHT)
+                .with_pos(max_gain_x, y)
+                .with_label("Max gain"),
+            notch_button: CheckButton::default()
+                .with_size(notch_button_width, DSP_ROW_HEIGHT)
+                .with_pos(x, notch_y)
+                .with_label("Notch"),
+            notch_frequency_slider: ValueSlider::default()
+                .with_size(notch_frequency_width, DSP_ROW_HEIGHT)
+                .with_pos(notch_frequency_x, notch_y)
+                .with_label("Freq"),
+            notch_q_slider: ValueSlider::default()
+                .with_size(notch_q_width, DSP_ROW_HEIGHT)
+                .with_pos(notch_q_x, notch_y)
+                .with_label("Q"),
+            auto_notch_button: CheckButton::default()
+                .with_size(100, DSP_ROW_HEIGHT)
+                .with_pos(auto_notch_x, notch_y)
+                .with_label("Auto notch"),
+            noise_reduction_button: CheckButton::default()
+                .with_size(noise_reduction_button_width, DSP_ROW_HEIGHT)
+                .with_pos(x, noise_reduction_y)
+                .with_label("NR"),
+            noise_reduction_strength_slider: ValueSlider::default()
+                .with_size(strength_width, DSP_ROW_HEIGHT)
+                .with_pos(strength_x, noise_reduction_y)
+                .with_label("Strength"),
+            noise_reduction_bypass_button: CheckButton::default()
+                .with_size(100, DSP_ROW_HEIGHT)
+                .with_pos(bypass_x, noise_reduction_y)
+                .with_label("Bypass"),
+            noise_blanker_button: CheckButton::default()
+                .with_size(noise_blanker_button_width, DSP_ROW_HEIGHT)
+                .with_pos(x, noise_blanker_y)
+                .with_label("NB"),
+            noise_blanker_threshold_slider: ValueSlider::default()
+                .with_size(threshold_width, DSP_ROW_HEIGHT)
+                .with_pos(threshold_x, noise_blanker_y)
+                .with_label("Threshold"),
+            tune_assist_button: CheckButton::default()
+                .with_size(100, DSP_ROW_HEIGHT)
+                .with_pos(tune_assist_x, noise_blanker_y)
+                .with_label("Tune assist"),
+        };
+
+        controls.bandpass_choice.set_align(Align::Left);
+        controls.bandpass_choice.set_label_size(CONTROL_LABEL_SIZE);
+        for preset in BANDPASS_PRESETS {
+            controls.bandpass_choice.add_choice(preset.label());
+        }
+        let preset_sender = sender.clone();
+        controls.bandpass_choice.set_callback(move |wid| {
+            if let Some(preset) = BANDPASS_PRESETS.get(wid.value() as usize) {
+                preset_sender.send(Message::SetBandpassPreset(*preset));
+            }
+        });
+
+        controls.bandpass_centre_slider.set_align(Align::Left);
+        controls.bandpass_centre_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.bandpass_centre_slider.set_type(Horizontal);
+        controls.bandpass_centre_slider.set_bounds(MIN_CENTRE_HZ as f64, MAX_CENTRE_HZ as f64);
+        controls.bandpass_centre_slider.set_step(10.0, 1);
+        let centre_sender = sender.clone();
+        controls.bandpass_centre_slider.set_callback(move |wid| {
+            centre_sender.send(Message::SetBandpassCentre(wid.value() as f32));
+        });
+
+        controls.agc_choice.set_align(Align::Left);
+        controls.agc_choice.set_label_size(CONTROL_LABEL_SIZE);
+        for preset in AGC_PRESETS {
+            controls.agc_choice.add_choice(preset.label());
+        }
+        controls.agc_choice.set_value(AGC_PRESETS.iter().position(|preset| *preset == settings.agc.preset).unwrap_or(0) as i32);
+        let agc_sender = sender.clone();
+        controls.agc_choice.set_callback(move |wid| {
+            if let Some(preset) = AGC_PRESETS.get(wid.value() as usize) {
+                agc_sender.send(Message::SetAgcPreset(*preset));
+            }
+        });
+
+        // The ceiling on the AGC's gain, in dB; lower keeps the noise down between signals.
+        controls.agc_max_gain_slider.set_align(Align::Left);
+        controls.agc_max_gain_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.agc_max_gain_slider.set_type(Horizontal);
+        controls.agc_max_gain_slider.set_bounds(MIN_MAX_GAIN_DB as f64, MAX_MAX_GAIN_DB as f64);
+        controls.agc_max_gain_slider.set_step(1.0, 1);
+        controls.agc_max_gain_slider.set_value(settings.agc.max_gain_db as f64);
+        let max_gain_sender = sender.clone();
+        controls.agc_max_gain_slider.set_callback(move |wid| {
+            max_gain_sender.send(Message::SetAgcMaxGain(wid.value() as f32));
+        });
+
+        controls.notch_button.set_label_size(CONTROL_LABEL_SIZE);
+        let notch_sender = sender.clone();
+        controls.notch_button.set_callback(move |wid| {
+            notch_sender.send(Message::SetNotchEnabled(wid.is_checked()));
+        });
+
+        controls.notch_frequency_slider.set_align(Align::Left);
+        controls.notch_frequency_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.notch_frequency_slider.set_type(Horizontal);
+        controls.notch_frequency_slider.set_bounds(MIN_NOTCH_HZ as f64, MAX_NOTCH_HZ as f64);
+        controls.notch_frequency_slider.set_step(10.0, 1);
+        let notch_frequency_sender = sender.clone();
+        controls.notch_frequency_slider.set_callback(move |wid| {
+            notch_frequency_sender.send(Message::SetNotchFrequency(wid.value() as f32));
+        });
+
+        controls.notch_q_slider.set_align(Align::Left);
+        controls.notch_q_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.notch_q_slider.set_type(Horizontal);
+        controls.notch_q_slider.set_bounds(MIN_NOTCH_Q as f64, MAX_NOTCH_Q as f64);
+        controls.notch_q_slider.set_step(1.0, 1);
+        let notch_q_sender = sender.clone();
+        controls.notch_q_slider.set_callback(move |wid| {
+            notch_q_sender.send(Message::SetNotchQ(wid.value() as f32));
+        });
+
+        controls.auto_notch_button.set_label_size(CONTROL_LABEL_SIZE);
+        controls.auto_notch_button.set_checked(settings.auto_notch);
+        let auto_notch_sender = sender.clone();
+        controls.auto_notch_button.set_callback(move |wid| {
+            auto_notch_sender.send(Message::SetAutoNotch(wid.is_checked()));
+        });
+
+        controls.noise_reduction_button.set_label_size(CONTROL_LABEL_SIZE);
+        controls.noise_reduction_button.set_checked(settings.noise_reduction.enabled);
+        let noise_reduction_sender = sender.clone();
+        controls.noise_reduction_button.set_callback(move |wid| {
+            noise_reduction_sender.send(Message::SetNoiseReductionEnabled(wid.is_checked()));
+        });
+
+        controls.noise_reduction_strength_slider.set_align(Align::Left);
+        controls.noise_reduction_strength_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.noise_reduction_strength_slider.set_type(Horizontal);
+        controls.noise_reduction_strength_slider.set_bounds(0.0, 1.0);
+        controls.noise_reduction_strength_slider.set_value(settings.noise_reduction.strength as f64);
+        let strength_sender = sender.clone();
+        controls.noise_reduction_strength_slider.set_callback(move |wid| {
+            strength_sender.send(Message::SetNoiseReductionStrength(wid.value() as f32));
+        });
+
+        // Hears the input without noise reduction, with the same delay, to compare.
+        controls.noise_reduction_bypass_button.set_label_size(CONTROL_LABEL_SIZE);
+        let bypass_sender = sender.clone();
+        controls.noise_reduction_bypass_button.set_callback(move |wid| {
+            bypass_sender.send(Message::SetNoiseReductionBypass(wid.is_checked()));
+        });
+
+        controls.noise_blanker_button.set_label_size(CONTROL_LABEL_SIZE);
+        controls.noise_blanker_button.set_checked(settings.noise_blanker.enabled);
+        let noise_blanker_sender = sender.clone();
+        controls.noise_blanker_button.set_callback(move |wid| {
+            noise_blanker_sender.send(Message::SetNoiseBlankerEnabled(wid.is_checked()));
+        });
+
+        // In multiples of the running average; lower blanks more.
+        controls.noise_blanker_threshold_slider.set_align(Align::Left);
+        controls.noise_blanker_threshold_slider.set_label_size(CONTROL_LABEL_SIZE);
+        controls.noise_blanker_threshold_slider.set_type(Horizontal);
+        controls.noise_blanker_threshold_slider.set_bounds(MIN_BLANKER_THRESHOLD as f64, MAX_BLANKER_THRESHOLD as f64);
+        controls.noise_blanker_threshold_slider.set_step(0.5, 1);
+        controls.noise_blanker_threshold_slider.set_value(settings.noise_blanker.threshold as f64);
+        let threshold_sender = sender.clone();
+        controls.noise_blanker_threshold_slider.set_callback(move |wid| {
+            threshold_sender.send(Message::SetNoiseBlankerThreshold(wid.value() as f32));
+        });
+
+        // Opens the tune assist window; always off at startup.
+        controls.tune_assist_button.set_label_size(CONTROL_LABEL_SIZE);
+        controls.tune_assist_button.set_callback(move |wid| {
+            sender.send(Message::SetTuneAssistEnabled(wid.is_checked()));
+        });
+
+        controls.show_bandpass(settings.bandpass);
+        controls.show_notch(settings.notch);
+        controls
+    }
+
+    // Shows settings that have changed other than through these controls, e.g. on a band change.
+    pub fn show_bandpass(&mut self, bandpass: BandpassSettings) {
+        let preset_index = BANDPASS_PRESETS.iter().position(|preset| *preset == bandpass.preset).unwrap_or(0);
+        self.bandpass_choice.set_value(preset_index as i32);
+        self.bandpass_centre_slider.set_value(bandpass.centre_hz as f64);
+    }
+
+    pub fn show_notch(&mut self, notch: NotchSettings) {
+        self.notch_button.set_checked(notch.enabled);
+        self.notch_frequency_slider.set_value(notch.frequency_hz as f64);
+        self.notch_q_slider.set_value(notch.q as f64);
+    }
+
+    pub fn show_tune_assist(&mut self, enabled: bool) {
+        self.tune_assist_button.set_checked(enabled);
+    }
+}
@@ -10,12 +10,28 @@ use std::thread::JoinHandle;
 use std::time::Duration;
 use fltk::image::PngImage;
 use fltk::{app::*, app, button::*, draw::*, enums::*, prelude::*, widget::*, window::*};
+use fltk::frame::Frame;
+use fltk::menu::{MenuFlag, MenuItem};
 use fltk::output::Output;
 use fltk::valuator::SliderType::Horizontal;
 use fltk::valuator::ValueSlider;
 use log::{debug, error, info};
 use rust_embed::RustEmbed;
-use crate::libs::gui_api::gui_api::{GUIInputMessage, GUIOutput, Message};
+use crate::libs::bands::bands::{band_for_frequency, default_band_frequency};
+use crate::libs::config::config::ConfigurationStore;
+use crate::libs::dsp::agc::AgcSettings;
+use crate::libs::dsp::bandpass::BandpassSettings;
+use crate::libs::dsp::noise_blanker::NoiseBlankerSettings;
+use crate::libs::dsp::noise_reduction::NoiseReductionSettings;
+use crate::libs::dsp::notch::NotchSettings;
+use crate::libs::dsp::tune_assist::TuneAssistSettings;
+use crate::libs::gui::dsp_controls::{DSP_CONTROLS_HEIGHT, DspControls, DspSettings};
+use crate::libs::gui::spectrum_widget::{SPECTRUM_CONTROLS_HEIGHT, SpectrumSettings, SpectrumWidget};
+use crate::libs::gui::tune_assist_window::TuneAssistWindow;
+use crate::libs::gui::waterfall_widget::{retune_for_pitch, WATERFALL_CONTROLS_HEIGHT, WaterfallSettings, WaterfallWidget};
+use crate::libs::gui_api::gui_api::{GUIInputMessage, GUIOutput, Message, MeterMode};
+use crate::libs::smeter::ballistics::{METER_BALLISTICS, MeterBallistics};
+use crate::libs::smeter::smeter::{calibration_for, DEFAULT_CALIBRATION_DB, SignalLevel};
 
 pub const WIDGET_PADDING: i32 = 10;
 
@@ -30,6 +46,28 @@ const BAND_BUTTON_DIM: i32 = (DIGIT_HEIGHT / 2) + 10;
 
 const MUTE_BUTTON_DIM: i32 = (DIGIT_HEIGHT / 2) + 12;
 
+const STATUS_HEIGHT: i32 = 20;
+
+// The last entry on the meter's menu, after its ballistics.
+const METER_CALIBRATE_LABEL: &str = "Calibrate...";
+
+// The gain reduction at full scale, when the meter is showing the AGC's.
+const METER_MAX_GAIN_REDUCTION_DB: f32 = 60.0;
+
+// The spectrum is in a column to the right of the meter and controls, with the waterfall below it
+// taking the rest of the column.
+const SPECTRUM_WIDTH: i32 = 480;
+const SPECTRUM_HEIGHT: i32 = METER_HEIGHT;
+
+// What the meter shows, shared with its drawing callback.
+struct MeterState {
+    mode: MeterMode,
+    signal_level: SignalLevel,
+    peak_level: SignalLevel, // At the peak-hold marker.
+    ballistics: MeterBallistics,
+    gain_reduction_db: f32,
+}
+
 #[derive(RustEmbed)]
 #[folder = "assets/"]
 struct Asset;
@@ -37,9 +75,11 @@ struct Asset;
 pub struct Gui {
     gui_input_tx: Arc<mpsc::SyncSender<GUIInputMessage>>,
     gui_output: Arc<Mutex<dyn GUIOutput>>,
+    config: Arc<Mutex<ConfigurationStore>>,
     sender: fltk::app::Sender<Message>,
     receiver: fltk::app::Receiver<Message>,
     thread_handle: Mutex<Option<JoinHandle<()>>>,
+    window: Window,
     window_width: i32,
     window_height: i32,
 
@@ -79,13 +119,31 @@ pub struct Gui {
     volume_slider: ValueSlider,
     muted: bool,
     mute_button: Button,
-    signal_strength: Arc<Mutex<f32>>,
+    status_frame: Frame,
+    connected: bool,
+    meter: Arc<Mutex<MeterState>>,
+    spectrum_widget: SpectrumWidget,
+    waterfall_widget: WaterfallWidget,
+    tuning_pitch_hz: u32,
+    dsp_controls: DspControls,
+    band: Option<u8>, // The band the frequency is in; its DSP settings are in use.
+    bandpass: BandpassSettings,
+    notch: NotchSettings,
+    noise_reduction: NoiseReductionSettings,
+    noise_blanker: NoiseBlankerSettings,
+    agc: AgcSettings,
+    tune_assist: TuneAssistSettings,
+    tune_assist_window: TuneAssistWindow,
     wheel_digit: Option<u32>,
 }
 
 impl Gui {
-    pub fn new(version: &str, gui_output: Arc<Mutex<dyn GUIOutput>>, terminate: Arc<AtomicBool>, frequency: u32, amplitude: f32) -> Self {
+    pub fn new(version: &str, gui_output: Arc<Mutex<dyn GUIOutput>>, terminate: Arc<AtomicBool>, config: Arc<Mutex<ConfigurationStore>>, frequency: u32) -> Self {
         debug!("Initialising Window");
+        // The waterfall is drawn as an RGB image.
+        if let Err(err) = app::set_visual(Mode::Rgb8) {
+            error!("Could not set RGB visual: {}", err);
+        }
         let mut wind = Window::default().with_label(format!("qdx-receiver v{} de M0CUV", version).as_str());
         let window_background = Color::from_hex_str("#dfe2ff").unwrap();
         let meter_png_file = Asset::get("s-meter.png").unwrap().data;
@@ -114,17 +172,63 @@ impl Gui {
         let updn_button_x = WIDGET_PADDING + DIGIT_BUTTON_OFFSET;
         let band_button_y = WIDGET_PADDING + METER_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING + DIGIT_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING;
         let volume_row_y = WIDGET_PADDING + METER_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING + DIGIT_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING + BAND_BUTTON_DIM + WIDGET_PADDING;
-
-        let arc_mutex_signal_strength = Arc::new(Mutex::new(0.0));
-        let meter_arc_mutex_signal_strength = arc_mutex_signal_strength.clone();
+        let status_row_y = volume_row_y + MUTE_BUTTON_DIM + WIDGET_PADDING;
+        let columns_height = WIDGET_PADDING + METER_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM  + WIDGET_PADDING + DIGIT_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING + BAND_BUTTON_DIM + WIDGET_PADDING + MUTE_BUTTON_DIM + WIDGET_PADDING + STATUS_HEIGHT + WIDGET_PADDING;
+        let column_x = WIDGET_PADDING + METER_WIDTH + WIDGET_PADDING;
+        // The DSP controls are across the bottom, below both columns.
+        let window_height = columns_height + DSP_CONTROLS_HEIGHT + WIDGET_PADDING;
+        let waterfall_y = WIDGET_PADDING + SPECTRUM_HEIGHT + WIDGET_PADDING + SPECTRUM_CONTROLS_HEIGHT + WIDGET_PADDING;
+        let waterfall_height = columns_height - waterfall_y - WIDGET_PADDING - WATERFALL_CONTROLS_HEIGHT - WIDGET_PADDING;
+
+        let locked_config = config.lock().unwrap();
+        let arc_mutex_meter = Arc::new(Mutex::new(MeterState {
+            mode: locked_config.get_meter_mode(),
+            signal_level: SignalLevel::from_mean_square(0.0, DEFAULT_CALIBRATION_DB),
+            peak_level: SignalLevel::from_mean_square(0.0, DEFAULT_CALIBRATION_DB),
+            ballistics: locked_config.get_meter_ballistics(),
+            gain_reduction_db: 0.0,
+        }));
+        let meter_arc_mutex_meter = arc_mutex_meter.clone();
+        let amplitude = locked_config.get_amplitude();
+        let muted = locked_config.get_muted();
+        let window_position = locked_config.get_window_position();
+        let band = band_for_frequency(frequency);
+        let meter_calibration_db = Self::meter_calibration(&locked_config, band);
+        let dsp_settings = DspSettings {
+            bandpass: band.and_then(|metres| locked_config.get_band_bandpass(metres)).unwrap_or_default(),
+            notch: locked_config.get_notch(),
+            auto_notch: locked_config.get_auto_notch(),
+            noise_reduction: locked_config.get_noise_reduction(),
+            noise_blanker: locked_config.get_noise_blanker(),
+            agc: locked_config.get_agc(),
+        };
+        let spectrum_settings = SpectrumSettings {
+            reference_level: locked_config.get_spectrum_reference_level(),
+            span_hz: locked_config.get_spectrum_span_hz(),
+            averaging: locked_config.get_spectrum_averaging(),
+        };
+        let tune_assist = locked_config.get_tune_assist();
+        let waterfall_settings = WaterfallSettings {
+            palette: locked_config.get_waterfall_palette(),
+            speed: locked_config.get_waterfall_speed(),
+            contrast: locked_config.get_waterfall_contrast(),
+            tuning_pitch_hz: locked_config.get_tuning_pitch_hz(),
+        };
+        drop(locked_config);
+        let spectrum_sender = sender.clone();
+        let waterfall_sender = sender.clone();
+        let dsp_sender = sender.clone();
+        let tune_assist_sender = sender.clone();
         let mut gui = Gui {
             gui_input_tx: Arc::new(gui_input_tx),
             gui_output,
+            config,
             sender,
             receiver,
             thread_handle: Mutex::new(None),
-            window_width: WIDGET_PADDING + METER_WIDTH + WIDGET_PADDING,
-            window_height: WIDGET_PADDING + METER_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM  + WIDGET_PADDING + DIGIT_HEIGHT + WIDGET_PADDING + DIGIT_BUTTON_DIM + WIDGET_PADDING + BAND_BUTTON_DIM + WIDGET_PADDING + MUTE_BUTTON_DIM + WIDGET_PADDING,
+            window: wind.clone(),
+            window_width: WIDGET_PADDING + METER_WIDTH + WIDGET_PADDING + SPECTRUM_WIDTH + WIDGET_PADDING,
+            window_height,
 
             meter_canvas: Widget::new(WIDGET_PADDING, WIDGET_PADDING, METER_WIDTH, METER_HEIGHT, ""),
             frequency,
@@ -243,19 +347,71 @@ impl Gui {
             volume_slider: ValueSlider::default()
                 .with_size(METER_WIDTH - WIDGET_PADDING - MUTE_BUTTON_DIM - WIDGET_PADDING, MUTE_BUTTON_DIM)
                 .with_pos(WIDGET_PADDING, volume_row_y),
-            muted: false,
+            muted,
             mute_button: Button::default()
                 .with_size(MUTE_BUTTON_DIM, MUTE_BUTTON_DIM)
                 .with_pos(WIDGET_PADDING + METER_WIDTH - MUTE_BUTTON_DIM, volume_row_y)
                 .with_label("🔇"),
-            signal_strength: arc_mutex_signal_strength,
+            status_frame: Frame::default()
+                .with_size(METER_WIDTH, STATUS_HEIGHT)
+                .with_pos(WIDGET_PADDING, status_row_y),
+            connected: true,
+            meter: arc_mutex_meter,
+            spectrum_widget: SpectrumWidget::new(column_x, WIDGET_PADDING, SPECTRUM_WIDTH, SPECTRUM_HEIGHT, spectrum_sender, spectrum_settings),
+            waterfall_widget: WaterfallWidget::new(column_x, waterfall_y, SPECTRUM_WIDTH, waterfall_height, waterfall_sender, waterfall_settings, spectrum_settings),
+            tuning_pitch_hz: waterfall_settings.tuning_pitch_hz,
+            dsp_controls: DspControls::new(WIDGET_PADDING, columns_height, dsp_sender, dsp_settings),
+            band,
+            bandpass: dsp_settings.bandpass,
+            notch: dsp_settings.notch,
+            noise_reduction: dsp_settings.noise_reduction,
+            noise_blanker: dsp_settings.noise_blanker,
+            agc: dsp_settings.agc,
+            tune_assist,
+            tune_assist_window: TuneAssistWindow::new(tune_assist_sender, tune_assist),
             wheel_digit: None,
         };
 
         gui.meter_canvas.set_trigger(CallbackTrigger::Release);
         gui.meter_canvas.draw(move |wid| {
-            let signal_strength = *meter_arc_mutex_signal_strength.lock().unwrap();
-            Self::draw_meter(wid, signal_strength, &mut meter_png);
+            let meter = meter_arc_mutex_meter.lock().unwrap();
+            Self::draw_meter(wid, &meter, &mut meter_png);
+        });
+        // Clicking on the meter switches what it shows; right-clicking offers its ballistics, and
+        // calibration.
+        let mut meter_menu_labels: Vec<&'static str> = METER_BALLISTICS.iter().map(|ballistics| ballistics.label()).collect();
+        meter_menu_labels.push(METER_CALIBRATE_LABEL);
+        let meter_menu = MenuItem::new(&meter_menu_labels);
+        for index in 0..METER_BALLISTICS.len() {
+            if let Some(mut item) = meter_menu.at(index as i32) {
+                item.set_flag(if index == METER_BALLISTICS.len() - 1 { MenuFlag::Radio | MenuFlag::MenuDivider } else { MenuFlag::Radio });
+            }
+        }
+        let menu_arc_mutex_meter = gui.meter.clone();
+        let meter_sender = gui.sender.clone();
+        gui.meter_canvas.handle(move |_, ev| {
+            if ev == Event::Push {
+                if app::event_mouse_button() != app::MouseButton::Right {
+                    meter_sender.send(Message::ToggleMeterMode);
+                    return true;
+                }
+                let current = menu_arc_mutex_meter.lock().unwrap().ballistics;
+                for (index, ballistics) in METER_BALLISTICS.iter().enumerate() {
+                    if let Some(mut item) = meter_menu.at(index as i32) {
+                        if *ballistics == current { item.set() } else { item.clear() }
+                    }
+                }
+                if let Some(label) = meter_menu.popup(app::event_x(), app::event_y()).and_then(|item| item.label()) {
+                    if label == METER_CALIBRATE_LABEL {
+                        meter_sender.send(Message::CalibrateMeter);
+                    } else if let Some(ballistics) = METER_BALLISTICS.iter().find(|ballistics| ballistics.label() == label) {
+                        meter_sender.send(Message::SetMeterBallistics(*ballistics));
+                    }
+                }
+                true
+            } else {
+                false
+            }
         });
 
         gui.frequency_output.set_color(window_background);
@@ -302,12 +458,33 @@ impl Gui {
         });
         // weird init needed..
         gui.sender.clone().send(Message::SetAmplitude(gui.amplitude));
+        gui.sender.clone().send(Message::SetSpectrumAveraging(spectrum_settings.averaging));
+        let mut gui_output = gui.gui_output.lock().unwrap();
+        gui_output.set_bandpass(dsp_settings.bandpass);
+        gui_output.set_notch(dsp_settings.notch);
+        gui_output.set_auto_notch(dsp_settings.auto_notch);
+        gui_output.set_noise_reduction(dsp_settings.noise_reduction);
+        gui_output.set_noise_blanker(dsp_settings.noise_blanker);
+        gui_output.set_agc(dsp_settings.agc);
+        gui_output.set_meter_calibration(meter_calibration_db);
+        gui_output.set_meter_ballistics(gui.meter.lock().unwrap().ballistics);
+        gui_output.set_tune_assist(tune_assist);
+        drop(gui_output);
+        gui.spectrum_widget.set_notch_marker(if dsp_settings.notch.enabled { Some(dsp_settings.notch.frequency_hz) } else { None });
 
         gui.mute_button.emit(gui.sender.clone(), Message::ToggleMute);
-        gui.mute_button.set_color(Color::Light2);
+        gui.mute_button.set_color(if gui.muted { Color::Red } else { Color::Light2 });
+
+        gui.status_frame.set_align(Align::Left | Align::Inside | Align::Clip);
+        gui.status_frame.set_label_size(12);
+        gui.status_frame.set_label_color(Color::DarkRed);
 
         wind.set_size(gui.window_width, gui.window_height);
+        if let Some((x, y)) = window_position {
+            wind.set_pos(x, y);
+        }
         wind.set_color(window_background);
+        gui.tune_assist_window.close_with(&mut wind);
 
         // Functions called on the GUI by the rest of the system...
         let thread_gui_sender = gui.sender.clone();
@@ -320,9 +497,37 @@ impl Gui {
 
                 if let Ok(gui_input_message) = gui_input_rx.recv_timeout(Duration::from_millis(250)) {
                     match gui_input_message {
-                        GUIInputMessage::SignalStrength(amplitude) => {
-                            //info!("Signal strength is {:1.3}", amplitude);
-                            thread_gui_sender.send(Message::SignalStrength(amplitude));
+                        GUIInputMessage::SignalLevel(signal_level, peak_level) => {
+                            //info!("Signal level is {:?}", signal_level);
+                            thread_gui_sender.send(Message::SignalLevel(signal_level, peak_level));
+                        }
+                        GUIInputMessage::GainReduction(gain_reduction_db) => {
+                            thread_gui_sender.send(Message::GainReduction(gain_reduction_db));
+                        }
+                        GUIInputMessage::Frequency(frequency) => {
+                            thread_gui_sender.send(Message::Frequency(frequency));
+                        }
+                        GUIInputMessage::CatError(error, advice) => {
+                            let status = match advice {
+                                Some(advice) => format!("CAT error: {}. {}", error, advice),
+                                None => format!("CAT error: {}", error),
+                            };
+                            thread_gui_sender.send(Message::CatStatus(status));
+                        }
+                        GUIInputMessage::CatRecovered => {
+                            thread_gui_sender.send(Message::CatStatus(String::new()));
+                        }
+                        GUIInputMessage::Disconnected => {
+                            thread_gui_sender.send(Message::Connected(false));
+                        }
+                        GUIInputMessage::Reconnected => {
+                            thread_gui_sender.send(Message::Connected(true));
+                        }
+                        GUIInputMessage::Spectrum(spectrum) => {
+                            thread_gui_sender.send(Message::Spectrum(spectrum));
+                        }
+                        GUIInputMessage::TuneAssistLevel(level_dbfs) => {
+                            thread_gui_sender.send(Message::TuneAssistLevel(level_dbfs));
                         }
                     }
                 }
@@ -345,8 +550,13 @@ impl Gui {
                   mid_y - (short_r * theta.sin()) as i32);
     }
 
-    fn draw_meter(wid: &mut Widget, signal_strength: f32, meter_png: &mut PngImage) {
+    fn draw_meter(wid: &mut Widget, meter: &MeterState, meter_png: &mut PngImage) {
         push_clip(wid.x(), wid.y(), wid.width(), wid.height());
+        if meter.mode == MeterMode::GainReduction {
+            Self::draw_gain_reduction(wid, meter.gain_reduction_db);
+            pop_clip();
+            return;
+        }
 
         meter_png.draw(wid.x(), wid.y(), wid.width(), wid.height());
         set_draw_color(Color::Black);
@@ -368,13 +578,24 @@ impl Gui {
         set_line_style(LineStyle::Solid, 5);
         //let mut theta = right_theta;
         //loop {
-        let fudged_signal_strength = (signal_strength * 10.0).min(1.0); // to make it like my Yaesu :)
-        //info!("fudged signal strength {}", fudged_signal_strength);
-        let theta = fudged_signal_strength * theta_range + right_theta; // Theta increases from the right
+        // The reading is shown under the scale, too.
+        let position = meter.signal_level.scale_position();
+        fltk::draw::set_font(Font::Helvetica, 14);
+        draw_text(meter.signal_level.reading().as_str(), wid.x() + 8, wid.y() + wid.height() - 8);
+        let theta = position * theta_range + right_theta; // Theta increases from the right
         let long_r = 164.0;
         let short_r = 80.0;
-        debug!("Updating meter to theta {} signal strength is {}", theta, fudged_signal_strength);
+        debug!("Updating meter to theta {} position is {}", theta, position);
         Self::draw_meter_line(theta, long_r, short_r, mid_x, mid_y);
+        // The peak-hold marker is a short red mark at the scale, with its reading above the
+        // needle's, to the nearest tenth of a dB so small changes can be seen.
+        set_draw_color(Color::Red);
+        set_line_style(LineStyle::Solid, 3);
+        let peak_theta = meter.peak_level.scale_position() * theta_range + right_theta;
+        Self::draw_meter_line(peak_theta, long_r, long_r - 25.0, mid_x, mid_y);
+        set_draw_color(Color::Black);
+        fltk::draw::set_font(Font::Helvetica, 12);
+        draw_text(format!("Peak {:.1} dBm ({})", meter.peak_level.dbm, meter.ballistics.label()).as_str(), wid.x() + 8, wid.y() + wid.height() - 28);
         //theta += 0.01;
         //if theta >= left_theta {
         //    break;
@@ -384,18 +605,141 @@ impl Gui {
         pop_clip();
     }
 
+    // The S-meter's face has no meaning for the AGC's gain reduction, so that has a scale of its
+    // own: a bar growing from the left, marked every 10 dB.
+    fn draw_gain_reduction(wid: &Widget, gain_reduction_db: f32) {
+        draw_rect_fill(wid.x(), wid.y(), wid.width(), wid.height(), Color::from_rgb(245, 240, 225));
+        let scale_x = wid.x() + 20;
+        let scale_width = wid.width() - 40;
+        let x_of = |db: f32| scale_x + ((db / METER_MAX_GAIN_REDUCTION_DB).clamp(0.0, 1.0) * scale_width as f32) as i32;
+        let bar_top = wid.y() + 50;
+        let bar_height = 30;
+
+        set_draw_color(Color::Black);
+        fltk::draw::set_font(Font::Helvetica, 14);
+        draw_text("AGC gain reduction, dB", scale_x, wid.y() + 30);
+        draw_rect_fill(scale_x, bar_top, x_of(gain_reduction_db) - scale_x, bar_height, Color::DarkRed);
+        set_draw_color(Color::Black);
+        draw_rect(scale_x, bar_top, scale_width, bar_height);
+        fltk::draw::set_font(Font::Helvetica, 12);
+        let mut grid_db = 0.0;
+        while grid_db <= METER_MAX_GAIN_REDUCTION_DB {
+            let x = x_of(grid_db);
+            draw_line(x, bar_top + bar_height, x, bar_top + bar_height + 6);
+            draw_text(format!("{}", grid_db).as_str(), x - 6, bar_top + bar_height + 20);
+            grid_db += 10.0;
+        }
+
+        fltk::draw::set_font(Font::Helvetica, 14);
+        draw_text(format!("AGC -{:.0} dB", gain_reduction_db).as_str(), wid.x() + 8, wid.y() + wid.height() - 8);
+        draw_rect(wid.x(), wid.y(), wid.width(), wid.height());
+    }
+
     fn show_frequency(&mut self) {
         self.frequency_output.set_value(format!("{:08}",self.frequency).as_str());
     }
 
+    fn set_frequency(&mut self, frequency: u32) {
+        self.frequency = frequency;
+        info!("New frequency {}", self.frequency);
+        self.gui_output.lock().unwrap().set_frequency(self.frequency);
+        self.config.lock().unwrap().set_frequency(self.frequency);
+        self.show_frequency();
+        self.follow_band();
+    }
+
+    // On moving into a different band, uses the DSP settings last used there.
+    fn follow_band(&mut self) {
+        let band = band_for_frequency(self.frequency);
+        if band == self.band {
+            return;
+        }
+        self.band = band;
+        if let Some(metres) = band {
+            let bandpass = self.config.lock().unwrap().get_band_bandpass(metres).unwrap_or_default();
+            info!("Using the {}m bandpass {:?}", metres, bandpass);
+            self.set_bandpass(bandpass);
+            self.dsp_controls.show_bandpass(bandpass);
+        }
+        let meter_calibration_db = Self::meter_calibration(&self.config.lock().unwrap(), band);
+        self.gui_output.lock().unwrap().set_meter_calibration(meter_calibration_db);
+    }
+
+    // The band's, if the frequency is in one and it's been calibrated.
+    fn meter_calibration(config: &ConfigurationStore, band: Option<u8>) -> f32 {
+        band.and_then(|metres| config.get_band_meter_calibration(metres)).unwrap_or(DEFAULT_CALIBRATION_DB)
+    }
+
+    // Makes the meter read the level of a signal generator's carrier, as it's being received.
+    fn calibrate_meter(&mut self) {
+        let metres = match self.band {
+            None => {
+                fltk::dialog::alert_default("The meter is calibrated for each band; tune to one first.");
+                return;
+            }
+            Some(metres) => metres,
+        };
+        let measured = self.meter.lock().unwrap().signal_level;
+        let prompt = format!("Level of the signal generator's carrier on {}m, in dBm:", metres);
+        let generator_dbm = match fltk::dialog::input_default(prompt.as_str(), "-73").map(|text| text.trim().parse::<f32>()) {
+            Some(Ok(generator_dbm)) => generator_dbm,
+            Some(Err(_)) => {
+                fltk::dialog::alert_default("That isn't a level in dBm.");
+                return;
+            }
+            None => return, // Cancelled.
+        };
+        let calibration_db = calibration_for(generator_dbm, measured);
+        info!("Calibrating the {}m meter to {} dB, measuring {} dBFS for {} dBm", metres, calibration_db, measured.dbfs, generator_dbm);
+        self.config.lock().unwrap().set_band_meter_calibration(metres, calibration_db);
+        self.gui_output.lock().unwrap().set_meter_calibration(calibration_db);
+    }
+
+    fn set_notch(&mut self, notch: NotchSettings) {
+        self.notch = notch;
+        self.gui_output.lock().unwrap().set_notch(notch);
+        self.config.lock().unwrap().set_notch(notch);
+        self.spectrum_widget.set_notch_marker(if notch.enabled { Some(notch.frequency_hz) } else { None });
+    }
+
+    fn set_noise_reduction(&mut self, noise_reduction: NoiseReductionSettings) {
+        self.noise_reduction = noise_reduction;
+        self.gui_output.lock().unwrap().set_noise_reduction(noise_reduction);
+        self.config.lock().unwrap().set_noise_reduction(noise_reduction);
+    }
+
+    fn set_noise_blanker(&mut self, noise_blanker: NoiseBlankerSettings) {
+        self.noise_blanker = noise_blanker;
+        self.gui_output.lock().unwrap().set_noise_blanker(noise_blanker);
+        self.config.lock().unwrap().set_noise_blanker(noise_blanker);
+    }
+
+    fn set_agc(&mut self, agc: AgcSettings) {
+        self.agc = agc;
+        self.gui_output.lock().unwrap().set_agc(agc);
+        self.config.lock().unwrap().set_agc(agc);
+    }
+
+    fn set_tune_assist(&mut self, tune_assist: TuneAssistSettings) {
+        self.tune_assist = tune_assist;
+        self.gui_output.lock().unwrap().set_tune_assist(tune_assist);
+        self.config.lock().unwrap().set_tune_assist(tune_assist);
+    }
+
+    // Also remembered for the current band, if the frequency is in one.
+    fn set_bandpass(&mut self, bandpass: BandpassSettings) {
+        self.bandpass = bandpass;
+        self.gui_output.lock().unwrap().set_bandpass(bandpass);
+        if let Some(metres) = self.band {
+            self.config.lock().unwrap().set_band_bandpass(metres, bandpass);
+        }
+    }
+
     fn increment_digit(&mut self, digit: u32) {
         debug!("Previous frequency {}", self.frequency);
         let pow = 10_u32.pow(digit);
         if self.frequency + pow < 99999999 {
-            self.frequency += pow;
-            info!("New frequency {}", self.frequency);
-            self.gui_output.lock().unwrap().set_frequency(self.frequency);
-            self.show_frequency();
+            self.set_frequency(self.frequency + pow);
         } else {
             error!("Out of range!");
         }
@@ -405,10 +749,7 @@ impl Gui {
         debug!("Previous frequency {}", self.frequency);
         let pow = 10_u32.pow(digit);
         if self.frequency as i64 - pow as i64 >= 0 {
-            self.frequency -= pow;
-            info!("New frequency {}", self.frequency);
-            self.gui_output.lock().unwrap().set_frequency(self.frequency);
-            self.show_frequency();
+            self.set_frequency(self.frequency - pow);
         } else {
             error!("Out of range!");
         }
@@ -424,8 +765,11 @@ impl Gui {
                 match message {
                     Message::SetAmplitude(amplitude) => {
                         info!("Setting amplitude to {}", amplitude);
-                        self.gui_output.lock().unwrap().set_amplitude(amplitude);
+                        if !self.muted {
+                            self.gui_output.lock().unwrap().set_amplitude(amplitude);
+                        }
                         self.amplitude = amplitude;
+                        self.config.lock().unwrap().set_amplitude(amplitude);
                     }
                     Message::IncrementFrequencyWheel => {
                         if let Some(digit) = self.wheel_digit {
@@ -447,22 +791,9 @@ impl Gui {
                     }
                     Message::SetBandMetres(m) => {
                         info!("Setting band to {}m", m);
-                        self.frequency = match m {
-                            80 =>  3_573_000,
-                            60 =>  5_357_000,
-                            40 =>  7_074_000,
-                            30 => 10_136_000,
-                            20 => 14_074_000,
-                            17 => 18_100_000,
-                            15 => 21_074_000,
-                            12 => 24_915_000,
-                            11 => 27_255_000, // Maybe?
-                            10 => 28_180_000,
-                            _ => 14_074_000, // default to 20m
-                        };
-                        info!("New frequency {}", self.frequency);
-                        self.gui_output.lock().unwrap().set_frequency(self.frequency);
-                        self.show_frequency();
+                        // Return to where we last were on this band, if we've been there before.
+                        let band_frequency = self.config.lock().unwrap().get_band_frequency(m);
+                        self.set_frequency(band_frequency.unwrap_or_else(|| default_band_frequency(m)));
                     }
                     Message::ToggleMute => {
                         if self.muted {
@@ -475,16 +806,184 @@ impl Gui {
                             self.mute_button.set_color(Color::Red);
                         }
                         self.muted = !self.muted;
+                        self.config.lock().unwrap().set_muted(self.muted);
                     }
-                    Message::SignalStrength(strength) => {
-                        *self.signal_strength.lock().unwrap() = strength;
+                    Message::SignalLevel(signal_level, peak_level) => {
+                        let mut meter = self.meter.lock().unwrap();
+                        meter.signal_level = signal_level;
+                        meter.peak_level = peak_level;
+                        if meter.mode == MeterMode::SignalLevel {
+                            self.meter_canvas.redraw();
+                        }
+                    }
+                    Message::GainReduction(gain_reduction_db) => {
+                        let mut meter = self.meter.lock().unwrap();
+                        meter.gain_reduction_db = gain_reduction_db;
+                        if meter.mode == MeterMode::GainReduction {
+                            self.meter_canvas.redraw();
+                        }
+                    }
+                    Message::ToggleMeterMode => {
+                        let mut meter = self.meter.lock().unwrap();
+                        meter.mode = match meter.mode {
+                            MeterMode::SignalLevel => MeterMode::GainReduction,
+                            MeterMode::GainReduction => MeterMode::SignalLevel,
+                        };
+                        info!("Meter showing {:?}", meter.mode);
+                        self.config.lock().unwrap().set_meter_mode(meter.mode);
+                        self.meter_canvas.redraw();
+                    }
+                    Message::CalibrateMeter => {
+                        self.calibrate_meter();
+                    }
+                    Message::SetMeterBallistics(ballistics) => {
+                        info!("Meter ballistics {}", ballistics.label());
+                        self.meter.lock().unwrap().ballistics = ballistics;
+                        self.gui_output.lock().unwrap().set_meter_ballistics(ballistics);
+                        self.config.lock().unwrap().set_meter_ballistics(ballistics);
                         self.meter_canvas.redraw();
                     }
+                    Message::Frequency(frequency) => {
+                        // The rig is already there, so it isn't told again.
+                        info!("Frequency changed externally to {}", frequency);
+                        self.frequency = frequency;
+                        self.config.lock().unwrap().set_frequency(frequency);
+                        self.show_frequency();
+                        self.follow_band();
+                    }
+                    Message::CatStatus(status) => {
+                        // Shown until it's cleared when the QDX responds again. While disconnected,
+                        // that's what's shown instead. It may be too long for the frame, so it's
+                        // the tooltip too.
+                        if self.connected {
+                            self.status_frame.set_label(&status);
+                            self.status_frame.set_tooltip(&status);
+                            self.window.redraw();
+                        }
+                    }
+                    Message::Connected(connected) => {
+                        info!("QDX {}", if connected { "reconnected" } else { "disconnected" });
+                        self.connected = connected;
+                        self.status_frame.set_label(if connected { "" } else { "QDX disconnected; waiting for it to return..." });
+                        self.status_frame.set_tooltip("");
+                        self.frequency_output.set_text_color(if connected { Color::Black } else { Color::Inactive });
+                        self.window.redraw();
+                    }
+                    Message::Spectrum(spectrum) => {
+                        self.waterfall_widget.add_spectrum(&spectrum);
+                        self.spectrum_widget.set_spectrum(spectrum);
+                    }
+                    Message::SetSpectrumReferenceLevel(reference_level) => {
+                        self.spectrum_widget.set_reference_level(reference_level);
+                        self.waterfall_widget.set_reference_level(reference_level);
+                        self.config.lock().unwrap().set_spectrum_reference_level(reference_level);
+                    }
+                    Message::SetSpectrumSpan(span_hz) => {
+                        self.spectrum_widget.set_span(span_hz);
+                        self.waterfall_widget.set_span(span_hz);
+                        self.config.lock().unwrap().set_spectrum_span_hz(span_hz);
+                    }
+                    Message::SetSpectrumAveraging(averaging) => {
+                        self.gui_output.lock().unwrap().set_spectrum_averaging(averaging);
+                        self.config.lock().unwrap().set_spectrum_averaging(averaging);
+                    }
+                    Message::SetWaterfallPalette(palette) => {
+                        self.waterfall_widget.set_palette(palette);
+                        self.config.lock().unwrap().set_waterfall_palette(palette);
+                    }
+                    Message::SetWaterfallSpeed(speed) => {
+                        self.waterfall_widget.set_speed(speed);
+                        self.config.lock().unwrap().set_waterfall_speed(speed);
+                    }
+                    Message::SetWaterfallContrast(contrast) => {
+                        self.waterfall_widget.set_contrast(contrast);
+                        self.config.lock().unwrap().set_waterfall_contrast(contrast);
+                    }
+                    Message::SetTuningPitch(pitch_hz) => {
+                        self.tuning_pitch_hz = pitch_hz;
+                        self.config.lock().unwrap().set_tuning_pitch_hz(pitch_hz);
+                    }
+                    Message::TuneToAudioFrequency(audio_hz) => {
+                        info!("Tuning signal at {}Hz to {}Hz", audio_hz, self.tuning_pitch_hz);
+                        self.set_frequency(retune_for_pitch(self.frequency, audio_hz, self.tuning_pitch_hz));
+                    }
+                    Message::SetBandpassPreset(preset) => {
+                        let bandpass = self.bandpass.with_preset(preset);
+                        info!("Setting bandpass to {:?}", bandpass);
+                        self.set_bandpass(bandpass);
+                        // The centre may have changed with the preset.
+                        self.dsp_controls.show_bandpass(bandpass);
+                    }
+                    Message::SetBandpassCentre(centre_hz) => {
+                        self.set_bandpass(BandpassSettings { centre_hz, ..self.bandpass });
+                    }
+                    Message::SetNotchEnabled(enabled) => {
+                        self.set_notch(NotchSettings { enabled, ..self.notch });
+                    }
+                    Message::SetNotchFrequency(frequency_hz) => {
+                        info!("Setting notch to {}Hz", frequency_hz);
+                        self.set_notch(NotchSettings { enabled: true, frequency_hz, ..self.notch });
+                        // It may have been set by clicking the spectrum.
+                        self.dsp_controls.show_notch(self.notch);
+                    }
+                    Message::SetNotchQ(q) => {
+                        self.set_notch(NotchSettings { q, ..self.notch });
+                    }
+                    Message::SetAutoNotch(enabled) => {
+                        info!("Auto notch {}", if enabled { "on" } else { "off" });
+                        self.gui_output.lock().unwrap().set_auto_notch(enabled);
+                        self.config.lock().unwrap().set_auto_notch(enabled);
+                    }
+                    Message::SetNoiseReductionEnabled(enabled) => {
+                        info!("Noise reduction {}", if enabled { "on" } else { "off" });
+                        self.set_noise_reduction(NoiseReductionSettings { enabled, ..self.noise_reduction });
+                    }
+                    Message::SetNoiseReductionStrength(strength) => {
+                        self.set_noise_reduction(NoiseReductionSettings { strength, ..self.noise_reduction });
+                    }
+                    Message::SetNoiseReductionBypass(bypass) => {
+                        self.set_noise_reduction(NoiseReductionSettings { bypass, ..self.noise_reduction });
+                    }
+                    Message::SetNoiseBlankerEnabled(enabled) => {
+                        info!("Noise blanker {}", if enabled { "on" } else { "off" });
+                        self.set_noise_blanker(NoiseBlankerSettings { enabled, ..self.noise_blanker });
+                    }
+                    Message::SetNoiseBlankerThreshold(threshold) => {
+                        self.set_noise_blanker(NoiseBlankerSettings { threshold, ..self.noise_blanker });
+                    }
+                    Message::SetAgcPreset(preset) => {
+                        info!("AGC {}", preset.label());
+                        self.set_agc(AgcSettings { preset, ..self.agc });
+                    }
+                    Message::SetAgcMaxGain(max_gain_db) => {
+                        self.set_agc(AgcSettings { max_gain_db, ..self.agc });
+                    }
+                    Message::SetTuneAssistEnabled(enabled) => {
+                        info!("Tune assist {}", if enabled { "on" } else { "off" });
+                        self.set_tune_assist(TuneAssistSettings { enabled, ..self.tune_assist });
+                        if enabled {
+                            self.tune_assist_window.show();
+                        } else {
+                            self.tune_assist_window.hide();
+                        }
+                        self.dsp_controls.show_tune_assist(enabled);
+                    }
+                    Message::SetTuneAssistTone(tone) => {
+                        self.set_tune_assist(TuneAssistSettings { tone, ..self.tune_assist });
+                    }
+                    Message::TuneAssistLevel(level_dbfs) => {
+                        self.tune_assist_window.set_level(level_dbfs);
+                    }
                 }
             }
         }
     }
 
+    // Called on exit, so the window reappears where it was left.
+    pub fn save_window_position(&self) {
+        self.config.lock().unwrap().set_window_position(self.window.x(), self.window.y());
+    }
+
     // Use this to send update messages to the GUI.
     pub fn gui_input_sender(&self) -> Arc<mpsc::SyncSender<GUIInputMessage>> {
         self.gui_input_tx.clone()
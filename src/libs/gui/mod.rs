@@ -1 +1,5 @@
-pub mod gui;
\ No newline at end of file
+pub mod dsp_controls;
+pub mod gui;
+pub mod spectrum_widget;
+pub mod tune_assist_window;
+pub mod waterfall_widget;
\ No newline at end of file
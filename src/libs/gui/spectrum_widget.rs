@@ -0,0 +1,239 @@
+// -------------------------------------------------------------------------------------------------
+// SPECTRUM WIDGET
+// -------------------------------------------------------------------------------------------------
+
+use std::sync::{Arc, Mutex};
+use fltk::app;
+use fltk::app::Sender;
+use fltk::draw::*;
+use fltk::enums::{Align, Color, Event, Font};
+use fltk::menu::Choice;
+use fltk::prelude::*;
+use fltk::valuator::SliderType::Horizontal;
+use fltk::valuator::ValueSlider;
+use fltk::widget::Widget;
+use crate::libs::dsp::notch::{MAX_NOTCH_HZ, MIN_NOTCH_HZ};
+use crate::libs::gui::gui::WIDGET_PADDING;
+use crate::libs::gui_api::gui_api::Message;
+use crate::libs::spectrum::spectrum::{BIN_WIDTH_HZ, MAX_AVERAGING};
+
+pub const SPECTRUM_CONTROLS_HEIGHT: i32 = 25;
+
+// The spans that can be shown, from 0Hz; the widest is the whole passband.
+pub const SPECTRUM_SPANS_HZ: [u32; 4] = [3000, 6000, 12000, 24000];
+
+// The dB range shown below the reference level.
+const SPECTRUM_RANGE_DB: f32 = 80.0;
+const SPECTRUM_GRID_DB: f32 = 10.0;
+const SPECTRUM_GRID_DIVISIONS: u32 = 6;
+
+const CONTROL_LABEL_WIDTH: i32 = 35;
+
+// The settings of the controls, as saved in the configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct SpectrumSettings {
+    pub reference_level: f32, // dBFS at the top of the display.
+    pub span_hz: u32,
+    pub averaging: u32,
+}
+
+struct SpectrumState {
+    spectrum: Vec<f32>,
+    reference_level: f32, // dBFS at the top of the display.
+    span_hz: u32,
+    notch_hz: Option<f32>, // Marked, if the manual notch is in use.
+}
+
+// A spectrum scope showing the latest spectrum in dBFS, with controls underneath for the
+// reference level, averaging and span. Clicking on it sets the manual notch there.
+pub struct SpectrumWidget {
+    canvas: Widget,
+    reference_slider: ValueSlider,
+    averaging_slider: ValueSlider,
+    span_choice: Choice,
+    state: Arc<Mutex<SpectrumState>>,
+}
+
+impl SpectrumWidget {
+    // The canvas is height high; the controls are below it.
+    pub fn new(x: i32, y: i32, width: i32, height: i32, sender: Sender<Message>, settings: SpectrumSettings) -> Self {
+        let state = Arc::new(Mutex::new(SpectrumState {
+            spectrum: vec![],
+            reference_level: settings.reference_level,
+            span_hz: settings.span_hz,
+            notch_hz: None,
+        }));
+        let controls_y = y + height + WIDGET_PADDING;
+        let reference_slider_width = 140;
+        let averaging_x = x + CONTROL_LABEL_WIDTH + reference_slider_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let averaging_slider_width = 110;
+        let span_x = averaging_x + averaging_slider_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let mut widget = Self {
+            canvas: Widget::new(x, y, width, height, ""),
+            reference_slider: ValueSlider::default()
+                .with_size(reference_slider_width, SPECTRUM_CONTROLS_HEIGHT)
+                .with_pos(x + CONTROL_LABEL_WIDTH, controls_y)
+                .with_label("Ref"),
+            averaging_slider: ValueSlider::default()
+                .with_size(averaging_slider_width, SPECTRUM_CONTROLS_HEIGHT)
+                .with_pos(averaging_x, controls_y)
+                .with_label("Avg"),
+            span_choice: Choice::default()
+                .with_size(x + width - span_x, SPECTRUM_CONTROLS_HEIGHT)
+                .with_pos(span_x, controls_y)
+                .with_label("Span"),
+            state,
+        };
+
+        let draw_state = widget.state.clone();
+        widget.canvas.draw(move |wid| {
+            let state = draw_state.lock().unwrap();
+            Self::draw_spectrum(wid, &state);
+        });
+
+        let click_state = widget.state.clone();
+        let click_sender = sender.clone();
+        widget.canvas.handle(move |wid, ev| {
+            if ev == Event::Push {
+                let span_hz = click_state.lock().unwrap().span_hz;
+                let audio_hz = column_frequency(app::event_x() - wid.x(), wid.width(), span_hz) as f32;
+                // On the wider spans, there's more shown than the notch can reach; clicks there
+                // are ignored, rather than putting the notch somewhere else.
+                if (MIN_NOTCH_HZ..=MAX_NOTCH_HZ).contains(&audio_hz) {
+                    click_sender.send(Message::SetNotchFrequency(audio_hz));
+                    return true;
+                }
+            }
+            false
+        });
+
+        widget.reference_slider.set_align(Align::Left);
+        widget.reference_slider.set_type(Horizontal);
+        widget.reference_slider.set_bounds(-60.0, 0.0);
+        widget.reference_slider.set_step(1.0, 1);
+        widget.reference_slider.set_value(settings.reference_level as f64);
+        let reference_sender = sender.clone();
+        widget.reference_slider.set_callback(move |wid| {
+            reference_sender.send(Message::SetSpectrumReferenceLevel(wid.value() as f32));
+        });
+
+        widget.averaging_slider.set_align(Align::Left);
+        widget.averaging_slider.set_type(Horizontal);
+        widget.averaging_slider.set_bounds(1.0, MAX_AVERAGING as f64);
+        widget.averaging_slider.set_step(1.0, 1);
+        widget.averaging_slider.set_value(settings.averaging as f64);
+        let averaging_sender = sender.clone();
+        widget.averaging_slider.set_callback(move |wid| {
+            averaging_sender.send(Message::SetSpectrumAveraging(wid.value() as u32));
+        });
+
+        widget.span_choice.set_align(Align::Left);
+        for span in SPECTRUM_SPANS_HZ {
+            widget.span_choice.add_choice(format!("{} kHz", span / 1000).as_str());
+        }
+        let span_index = SPECTRUM_SPANS_HZ.iter().position(|span| *span == settings.span_hz).unwrap_or(SPECTRUM_SPANS_HZ.len() - 1);
+        widget.span_choice.set_value(span_index as i32);
+        widget.span_choice.set_callback(move |wid| {
+            if let Some(span) = SPECTRUM_SPANS_HZ.get(wid.value() as usize) {
+                sender.send(Message::SetSpectrumSpan(*span));
+            }
+        });
+
+        widget
+    }
+
+    pub fn set_spectrum(&mut self, spectrum: Vec<f32>) {
+        self.state.lock().unwrap().spectrum = spectrum;
+        self.canvas.redraw();
+    }
+
+    pub fn set_reference_level(&mut self, reference_level: f32) {
+        self.state.lock().unwrap().reference_level = reference_level;
+        self.canvas.redraw();
+    }
+
+    pub fn set_span(&mut self, span_hz: u32) {
+        self.state.lock().unwrap().span_hz = span_hz;
+        self.canvas.redraw();
+    }
+
+    pub fn set_notch_marker(&mut self, notch_hz: Option<f32>) {
+        self.state.lock().unwrap().notch_hz = notch_hz;
+        self.canvas.redraw();
+    }
+
+    fn draw_spectrum(wid: &mut Widget, state: &SpectrumState) {
+        push_clip(wid.x(), wid.y(), wid.width(), wid.height());
+        draw_rect_fill(wid.x(), wid.y(), wid.width(), wid.height(), Color::Black);
+
+        // The grid, labelled in dBFS and kHz.
+        set_font(Font::Helvetica, 10);
+        set_draw_color(Color::from_rgb(64, 64, 64));
+        let pixels_per_db = wid.height() as f32 / SPECTRUM_RANGE_DB;
+        let first_grid_db = (state.reference_level / SPECTRUM_GRID_DB).floor() * SPECTRUM_GRID_DB;
+        let mut grid_db = first_grid_db;
+        while grid_db > state.reference_level - SPECTRUM_RANGE_DB {
+            let y = wid.y() + ((state.reference_level - grid_db) * pixels_per_db) as i32;
+            set_draw_color(Color::from_rgb(64, 64, 64));
+            draw_line(wid.x(), y, wid.x() + wid.width(), y);
+            set_draw_color(Color::from_rgb(160, 160, 160));
+            draw_text(format!("{}", grid_db).as_str(), wid.x() + 2, y + 10);
+            grid_db -= SPECTRUM_GRID_DB;
+        }
+        for division in 1..SPECTRUM_GRID_DIVISIONS {
+            let x = wid.x() + (wid.width() as u32 * division / SPECTRUM_GRID_DIVISIONS) as i32;
+            set_draw_color(Color::from_rgb(64, 64, 64));
+            draw_line(x, wid.y(), x, wid.y() + wid.height());
+            let khz = (state.span_hz * division / SPECTRUM_GRID_DIVISIONS) as f32 / 1000.0;
+            set_draw_color(Color::from_rgb(160, 160, 160));
+            draw_text(format!("{}k", khz).as_str(), x + 2, wid.y() + wid.height() - 2);
+        }
+
+        if !state.spectrum.is_empty() {
+            set_draw_color(Color::Green);
+            set_line_style(LineStyle::Solid, 1);
+            let mut previous: Option<(i32, i32)> = None;
+            for column in 0..wid.width() {
+                let level = match column_level(&state.spectrum, column, wid.width(), state.span_hz) {
+                    None => break,
+                    Some(level) => level,
+                };
+                let y = wid.y() + ((state.reference_level - level) * pixels_per_db).clamp(0.0, wid.height() as f32) as i32;
+                let point = (wid.x() + column, y);
+                if let Some((previous_x, previous_y)) = previous {
+                    draw_line(previous_x, previous_y, point.0, point.1);
+                }
+                previous = Some(point);
+            }
+            set_line_style(LineStyle::Solid, 0);
+        }
+
+        if let Some(notch_hz) = state.notch_hz {
+            let x = wid.x() + (notch_hz / state.span_hz as f32 * wid.width() as f32) as i32;
+            set_draw_color(Color::Red);
+            draw_line(x, wid.y(), x, wid.y() + wid.height());
+        }
+
+        set_draw_color(Color::Black);
+        draw_rect(wid.x(), wid.y(), wid.width(), wid.height());
+        pop_clip();
+    }
+}
+
+// The level shown in a pixel column of a display width wide, showing span_hz from 0Hz. Each
+// column shows the highest bin it covers, so narrow signals aren't lost when the span is wide.
+// None if the column is beyond the spectrum.
+pub fn column_level(spectrum: &[f32], column: i32, width: i32, span_hz: u32) -> Option<f32> {
+    let bins_per_pixel = span_hz as f32 / BIN_WIDTH_HZ / width as f32;
+    let first_bin = (column as f32 * bins_per_pixel) as usize;
+    let last_bin = (((column + 1) as f32 * bins_per_pixel) as usize).max(first_bin + 1).min(spectrum.len());
+    if first_bin >= last_bin {
+        return None;
+    }
+    Some(spectrum[first_bin..last_bin].iter().cloned().fold(f32::MIN, f32::max))
+}
+
+// The audio frequency at a pixel column of a display width wide, showing span_hz from 0Hz.
+pub fn column_frequency(column: i32, width: i32, span_hz: u32) -> u32 {
+    (column.clamp(0, width) as u64 * span_hz as u64 / width as u64) as u32
+}
@@ -0,0 +1,169 @@
+// -------------------------------------------------------------------------------------------------
+// TUNE ASSIST WINDOW
+// -------------------------------------------------------------------------------------------------
+
+use std::sync::{Arc, Mutex};
+use fltk::app;
+use fltk::app::Sender;
+use fltk::button::{Button, CheckButton};
+use fltk::draw::*;
+use fltk::enums::{Color, Event, Font};
+use fltk::group::Group;
+use fltk::prelude::*;
+use fltk::widget::Widget;
+use fltk::window::Window;
+use crate::libs::dsp::tune_assist::TuneAssistSettings;
+use crate::libs::gui::gui::WIDGET_PADDING;
+use crate::libs::gui_api::gui_api::Message;
+
+const BAR_WIDTH: i32 = 620;
+const BAR_HEIGHT: i32 = 120;
+const BUTTON_HEIGHT: i32 = 25;
+
+// The range of the bar.
+const BAR_MIN_DBFS: f32 = -140.0;
+const BAR_MAX_DBFS: f32 = -40.0;
+const BAR_GRID_DB: f32 = 10.0;
+
+struct TuneAssistState {
+    level_dbfs: Option<f32>,
+    best_dbfs: Option<f32>, // The highest level since the window was opened, or reset.
+}
+
+// A separate window showing the band noise level, measured by the tune assist, as a large bar,
+// with a marker at the best so far. Closing it turns the tune assist off.
+pub struct TuneAssistWindow {
+    window: Window,
+    bar: Widget,
+    tone_button: CheckButton,
+    reset_button: Button,
+    state: Arc<Mutex<TuneAssistState>>,
+}
+
+impl TuneAssistWindow {
+    pub fn new(sender: Sender<Message>, settings: TuneAssistSettings) -> Self {
+        // Otherwise it would be created inside the main window, if that's still being built.
+        let parent = Group::try_current();
+        Group::set_current(None::<&Group>);
+
+        let buttons_y = WIDGET_PADDING + BAR_HEIGHT + WIDGET_PADDING;
+        let mut tune_assist_window = Self {
+            window: Window::default()
+                .with_size(WIDGET_PADDING + BAR_WIDTH + WIDGET_PADDING, buttons_y + BUTTON_HEIGHT + WIDGET_PADDING)
+                .with_label("Tune assist"),
+            bar: Widget::new(WIDGET_PADDING, WIDGET_PADDING, BAR_WIDTH, BAR_HEIGHT, ""),
+            tone_button: CheckButton::default()
+                .with_size(80, BUTTON_HEIGHT)
+                .with_pos(WIDGET_PADDING, buttons_y)
+                .with_label("Tone"),
+            reset_button: Button::default()
+                .with_size(100, BUTTON_HEIGHT)
+                .with_pos(WIDGET_PADDING + 80 + WIDGET_PADDING, buttons_y)
+                .with_label("Reset best"),
+            state: Arc::new(Mutex::new(TuneAssistState { level_dbfs: None, best_dbfs: None })),
+        };
+        tune_assist_window.window.end();
+        Group::set_current(parent.as_ref());
+
+        let draw_state = tune_assist_window.state.clone();
+        tune_assist_window.bar.draw(move |wid| {
+            let state = draw_state.lock().unwrap();
+            Self::draw_bar(wid, &state);
+        });
+
+        // Hears the tone rather than the receive audio.
+        tune_assist_window.tone_button.set_checked(settings.tone);
+        let tone_sender = sender.clone();
+        tune_assist_window.tone_button.set_callback(move |wid| {
+            tone_sender.send(Message::SetTuneAssistTone(wid.is_checked()));
+        });
+
+        let reset_state = tune_assist_window.state.clone();
+        let mut reset_bar = tune_assist_window.bar.clone();
+        tune_assist_window.reset_button.set_callback(move |_| {
+            let mut state = reset_state.lock().unwrap();
+            state.best_dbfs = state.level_dbfs;
+            reset_bar.redraw();
+        });
+
+        // Closing the window is the same as unchecking its button in the main window.
+        tune_assist_window.window.set_callback(move |_| {
+            if app::event() == Event::Close {
+                sender.send(Message::SetTuneAssistEnabled(false));
+            }
+        });
+
+        tune_assist_window
+    }
+
+    // Starts afresh, with no best so far.
+    pub fn show(&mut self) {
+        *self.state.lock().unwrap() = TuneAssistState { level_dbfs: None, best_dbfs: None };
+        self.window.show();
+    }
+
+    pub fn hide(&mut self) {
+        self.window.hide();
+    }
+
+    // Closing the main window closes this one too, or the app would carry on without it.
+    pub fn close_with(&self, main_window: &mut Window) {
+        let mut window = self.window.clone();
+        main_window.set_callback(move |main_window| {
+            window.hide();
+            main_window.hide();
+        });
+    }
+
+    pub fn set_level(&mut self, level_dbfs: f32) {
+        let mut state = self.state.lock().unwrap();
+        state.level_dbfs = Some(level_dbfs);
+        state.best_dbfs = Some(state.best_dbfs.map_or(level_dbfs, |best| best.max(level_dbfs)));
+        self.bar.redraw();
+    }
+
+    fn draw_bar(wid: &mut Widget, state: &TuneAssistState) {
+        push_clip(wid.x(), wid.y(), wid.width(), wid.height());
+        draw_rect_fill(wid.x(), wid.y(), wid.width(), wid.height(), Color::Black);
+        let x_of = |dbfs: f32| wid.x() + (bar_fraction(dbfs) * wid.width() as f32) as i32;
+
+        set_font(Font::Helvetica, 10);
+        let mut grid_dbfs = BAR_MIN_DBFS + BAR_GRID_DB;
+        while grid_dbfs < BAR_MAX_DBFS {
+            let x = x_of(grid_dbfs);
+            set_draw_color(Color::from_rgb(64, 64, 64));
+            draw_line(x, wid.y(), x, wid.y() + wid.height());
+            set_draw_color(Color::from_rgb(160, 160, 160));
+            draw_text(format!("{}", grid_dbfs).as_str(), x + 2, wid.y() + wid.height() - 2);
+            grid_dbfs += BAR_GRID_DB;
+        }
+
+        let bar_top = wid.y() + wid.height() / 2;
+        let bar_height = wid.height() / 2 - 15;
+        if let Some(level_dbfs) = state.level_dbfs {
+            draw_rect_fill(wid.x(), bar_top, x_of(level_dbfs) - wid.x(), bar_height, Color::Green);
+            set_draw_color(Color::White);
+            set_font(Font::HelveticaBold, 24);
+            draw_text(format!("{:.1} dBFS", level_dbfs).as_str(), wid.x() + 8, wid.y() + 30);
+        }
+        if let Some(best_dbfs) = state.best_dbfs {
+            set_draw_color(Color::Red);
+            set_line_style(LineStyle::Solid, 3);
+            let x = x_of(best_dbfs);
+            draw_line(x, bar_top - 5, x, bar_top + bar_height + 5);
+            set_line_style(LineStyle::Solid, 0);
+            set_font(Font::Helvetica, 16);
+            let below_best = state.level_dbfs.map_or(0.0, |level_dbfs| level_dbfs - best_dbfs);
+            draw_text(format!("Best {:.1} dBFS ({:+.1} dB)", best_dbfs, below_best).as_str(), wid.x() + 220, wid.y() + 30);
+        }
+
+        set_draw_color(Color::Black);
+        draw_rect(wid.x(), wid.y(), wid.width(), wid.height());
+        pop_clip();
+    }
+}
+
+// How far along the bar a level is, from 0 to 1.
+fn bar_fraction(level_dbfs: f32) -> f32 {
+    ((level_dbfs - BAR_MIN_DBFS) / (BAR_MAX_DBFS - BAR_MIN_DBFS)).clamp(0.0, 1.0)
+}
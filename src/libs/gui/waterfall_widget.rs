@@ -0,0 +1,282 @@
+// -------------------------------------------------------------------------------------------------
+// WATERFALL WIDGET
+// -------------------------------------------------------------------------------------------------
+
+use std::sync::{Arc, Mutex};
+use fltk::app;
+use fltk::app::Sender;
+use fltk::draw::*;
+use fltk::enums::{Align, Color, ColorDepth, Event};
+use fltk::menu::Choice;
+use fltk::prelude::*;
+use fltk::valuator::SliderType::Horizontal;
+use fltk::valuator::Slider;
+use fltk::widget::Widget;
+use log::warn;
+use crate::libs::gui::gui::WIDGET_PADDING;
+use crate::libs::gui::spectrum_widget::{column_frequency, column_level, SpectrumSettings};
+use crate::libs::gui_api::gui_api::{Message, WaterfallPalette};
+
+pub const WATERFALL_CONTROLS_HEIGHT: i32 = 25;
+
+pub const WATERFALL_PALETTES: [WaterfallPalette; 3] = [WaterfallPalette::Classic, WaterfallPalette::Heat, WaterfallPalette::Grey];
+
+// The audio pitches that a clicked signal can be tuned to: CW, and digital modes.
+pub const TUNING_PITCHES_HZ: [u32; 2] = [700, 1500];
+
+// A row is added for every (MAX_WATERFALL_SPEED + 1 - speed) spectra.
+pub const MAX_WATERFALL_SPEED: u32 = 4;
+const MAX_WATERFALL_CONTRAST: f64 = 4.0;
+
+// At a contrast of 1, the colours cover this range below the reference level.
+const WATERFALL_RANGE_DB: f32 = 80.0;
+
+const CONTROL_LABEL_WIDTH: i32 = 45;
+const CONTROL_LABEL_SIZE: i32 = 12;
+
+// The settings of the controls, as saved in the configuration.
+#[derive(Clone, Copy, Debug)]
+pub struct WaterfallSettings {
+    pub palette: WaterfallPalette,
+    pub speed: u32, // 1 to MAX_WATERFALL_SPEED.
+    pub contrast: f32, // 1 to MAX_WATERFALL_CONTRAST.
+    pub tuning_pitch_hz: u32,
+}
+
+struct WaterfallState {
+    image: Vec<u8>, // RGB, the newest row at the top.
+    width: i32,
+    height: i32,
+    spectra_since_row: u32,
+    palette: WaterfallPalette,
+    speed: u32,
+    contrast: f32,
+    reference_level: f32,
+    span_hz: u32,
+}
+
+// A scrolling history of spectra, the newest at the top, coloured by level. Clicking on it asks
+// for the clicked frequency to be tuned to the chosen pitch.
+pub struct WaterfallWidget {
+    canvas: Widget,
+    palette_choice: Choice,
+    speed_slider: Slider,
+    contrast_slider: Slider,
+    pitch_choice: Choice,
+    state: Arc<Mutex<WaterfallState>>,
+}
+
+impl WaterfallWidget {
+    // The canvas is height high; the controls are below it. The reference level and span follow
+    // the spectrum scope's.
+    pub fn new(x: i32, y: i32, width: i32, height: i32, sender: Sender<Message>, settings: WaterfallSettings, spectrum_settings: SpectrumSettings) -> Self {
+        let state = Arc::new(Mutex::new(WaterfallState {
+            image: vec![0; (width * height * 3) as usize],
+            width,
+            height,
+            spectra_since_row: 0,
+            palette: settings.palette,
+            speed: settings.speed,
+            contrast: settings.contrast,
+            reference_level: spectrum_settings.reference_level,
+            span_hz: spectrum_settings.span_hz,
+        }));
+        let controls_y = y + height + WIDGET_PADDING;
+        let palette_choice_width = 75;
+        let slider_width = 60;
+        let speed_x = x + CONTROL_LABEL_WIDTH + palette_choice_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let contrast_x = speed_x + slider_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let pitch_x = contrast_x + slider_width + WIDGET_PADDING + CONTROL_LABEL_WIDTH;
+        let mut widget = Self {
+            canvas: Widget::new(x, y, width, height, ""),
+            palette_choice: Choice::default()
+                .with_size(palette_choice_width, WATERFALL_CONTROLS_HEIGHT)
+                .with_pos(x + CONTROL_LABEL_WIDTH, controls_y)
+                .with_label("Palette"),
+            speed_slider: Slider::default()
+                .with_size(slider_width, WATERFALL_CONTROLS_HEIGHT)
+                .with_pos(speed_x, controls_y)
+                .with_label("Speed"),
+            contrast_slider: Slider::default()
+                .with_size(slider_width, WATERFALL_CONTROLS_HEIGHT)
+                .with_pos(contrast_x, controls_y)
+                .with_label("Contrast"),
+            pitch_choice: Choice::default()
+                .with_size(x + width - pitch_x, WATERFALL_CONTROLS_HEIGHT)
+                .with_pos(pitch_x, controls_y)
+                .with_label("Pitch"),
+            state,
+        };
+
+        let draw_state = widget.state.clone();
+        widget.canvas.draw(move |wid| {
+            let state = draw_state.lock().unwrap();
+            if let Err(err) = draw_image(&state.image, wid.x(), wid.y(), state.width, state.height, ColorDepth::Rgb8) {
+                warn!("Could not draw waterfall: {}", err);
+            }
+            set_draw_color(Color::Black);
+            draw_rect(wid.x(), wid.y(), wid.width(), wid.height());
+        });
+
+        let click_state = widget.state.clone();
+        let click_sender = sender.clone();
+        widget.canvas.handle(move |wid, ev| {
+            if ev == Event::Push {
+                let span_hz = click_state.lock().unwrap().span_hz;
+                let audio_hz = column_frequency(app::event_x() - wid.x(), wid.width(), span_hz);
+                click_sender.send(Message::TuneToAudioFrequency(audio_hz));
+                true
+            } else {
+                false
+            }
+        });
+
+        widget.palette_choice.set_align(Align::Left);
+        widget.palette_choice.set_label_size(CONTROL_LABEL_SIZE);
+        for palette in WATERFALL_PALETTES {
+            widget.palette_choice.add_choice(format!("{:?}", palette).as_str());
+        }
+        let palette_index = WATERFALL_PALETTES.iter().position(|palette| *palette == settings.palette).unwrap_or(0);
+        widget.palette_choice.set_value(palette_index as i32);
+        let palette_sender = sender.clone();
+        widget.palette_choice.set_callback(move |wid| {
+            if let Some(palette) = WATERFALL_PALETTES.get(wid.value() as usize) {
+                palette_sender.send(Message::SetWaterfallPalette(*palette));
+            }
+        });
+
+        widget.speed_slider.set_align(Align::Left);
+        widget.speed_slider.set_label_size(CONTROL_LABEL_SIZE);
+        widget.speed_slider.set_type(Horizontal);
+        widget.speed_slider.set_bounds(1.0, MAX_WATERFALL_SPEED as f64);
+        widget.speed_slider.set_step(1.0, 1);
+        widget.speed_slider.set_value(settings.speed as f64);
+        let speed_sender = sender.clone();
+        widget.speed_slider.set_callback(move |wid| {
+            speed_sender.send(Message::SetWaterfallSpeed(wid.value() as u32));
+        });
+
+        widget.contrast_slider.set_align(Align::Left);
+        widget.contrast_slider.set_label_size(CONTROL_LABEL_SIZE);
+        widget.contrast_slider.set_type(Horizontal);
+        widget.contrast_slider.set_bounds(1.0, MAX_WATERFALL_CONTRAST);
+        widget.contrast_slider.set_step(0.25, 1);
+        widget.contrast_slider.set_value(settings.contrast as f64);
+        let contrast_sender = sender.clone();
+        widget.contrast_slider.set_callback(move |wid| {
+            contrast_sender.send(Message::SetWaterfallContrast(wid.value() as f32));
+        });
+
+        widget.pitch_choice.set_align(Align::Left);
+        widget.pitch_choice.set_label_size(CONTROL_LABEL_SIZE);
+        for pitch in TUNING_PITCHES_HZ {
+            widget.pitch_choice.add_choice(format!("{} Hz", pitch).as_str());
+        }
+        let pitch_index = TUNING_PITCHES_HZ.iter().position(|pitch| *pitch == settings.tuning_pitch_hz).unwrap_or(0);
+        widget.pitch_choice.set_value(pitch_index as i32);
+        widget.pitch_choice.set_callback(move |wid| {
+            if let Some(pitch) = TUNING_PITCHES_HZ.get(wid.value() as usize) {
+                sender.send(Message::SetTuningPitch(*pitch));
+            }
+        });
+
+        widget
+    }
+
+    // Scrolls the waterfall down and adds the spectrum at the top, if it's time for a new row.
+    pub fn add_spectrum(&mut self, spectrum: &[f32]) {
+        let mut state = self.state.lock().unwrap();
+        state.spectra_since_row += 1;
+        if state.spectra_since_row < MAX_WATERFALL_SPEED + 1 - state.speed.clamp(1, MAX_WATERFALL_SPEED) {
+            return;
+        }
+        state.spectra_since_row = 0;
+
+        let row_length = (state.width * 3) as usize;
+        let image_length = state.image.len();
+        state.image.copy_within(0..image_length - row_length, row_length);
+        let range_db = WATERFALL_RANGE_DB / state.contrast.max(1.0);
+        let bottom_db = state.reference_level - range_db;
+        for column in 0..state.width {
+            let colour = match column_level(spectrum, column, state.width, state.span_hz) {
+                None => [0, 0, 0],
+                Some(level) => palette_colour(state.palette, (level - bottom_db) / range_db),
+            };
+            let offset = (column * 3) as usize;
+            state.image[offset..offset + 3].copy_from_slice(&colour);
+        }
+        drop(state);
+        self.canvas.redraw();
+    }
+
+    pub fn set_palette(&mut self, palette: WaterfallPalette) {
+        self.state.lock().unwrap().palette = palette;
+    }
+
+    pub fn set_speed(&mut self, speed: u32) {
+        self.state.lock().unwrap().speed = speed;
+    }
+
+    pub fn set_contrast(&mut self, contrast: f32) {
+        self.state.lock().unwrap().contrast = contrast;
+    }
+
+    pub fn set_reference_level(&mut self, reference_level: f32) {
+        self.state.lock().unwrap().reference_level = reference_level;
+    }
+
+    // The existing rows were at the old span, so they're cleared.
+    pub fn set_span(&mut self, span_hz: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.span_hz = span_hz;
+        state.image.fill(0);
+        drop(state);
+        self.canvas.redraw();
+    }
+}
+
+// The colour of a level, from 0.0 (at the bottom of the range) to 1.0 (at the reference level).
+// Levels outside that take the end colours.
+pub fn palette_colour(palette: WaterfallPalette, value: f32) -> [u8; 3] {
+    let stops: &[[u8; 3]] = match palette {
+        WaterfallPalette::Classic => &[[0, 0, 0], [0, 0, 192], [0, 192, 255], [255, 255, 0], [255, 0, 0]],
+        WaterfallPalette::Heat => &[[0, 0, 0], [192, 0, 0], [255, 192, 0], [255, 255, 255]],
+        WaterfallPalette::Grey => &[[0, 0, 0], [255, 255, 255]],
+    };
+    let position = value.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let index = (position as usize).min(stops.len() - 2);
+    let fraction = position - index as f32;
+    let mut colour = [0u8; 3];
+    for (channel, value) in colour.iter_mut().enumerate() {
+        let from = stops[index][channel] as f32;
+        let to = stops[index + 1][channel] as f32;
+        *value = (from + (to - from) * fraction).round() as u8;
+    }
+    colour
+}
+
+// The frequency to tune to so that a signal heard at audio_hz is heard at pitch_hz instead. The
+// QDX receives upper sideband, so audio frequencies are above the dial frequency.
+pub fn retune_for_pitch(dial_hz: u32, audio_hz: u32, pitch_hz: u32) -> u32 {
+    (dial_hz as i64 + audio_hz as i64 - pitch_hz as i64).max(0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::gui::waterfall_widget::{palette_colour, retune_for_pitch};
+    use crate::libs::gui_api::gui_api::WaterfallPalette;
+
+    #[test]
+    fn palette_ends_and_middle() {
+        assert_eq!(palette_colour(WaterfallPalette::Grey, -1.0), [0, 0, 0]);
+        assert_eq!(palette_colour(WaterfallPalette::Grey, 0.5), [128, 128, 128]);
+        assert_eq!(palette_colour(WaterfallPalette::Classic, 1.0), [255, 0, 0]);
+        assert_eq!(palette_colour(WaterfallPalette::Heat, 2.0), [255, 255, 255]);
+    }
+
+    #[test]
+    fn clicked_signal_is_tuned_to_pitch() {
+        assert_eq!(retune_for_pitch(14074000, 2200, 1500), 14074700);
+        assert_eq!(retune_for_pitch(7030000, 300, 700), 7029600);
+    }
+}
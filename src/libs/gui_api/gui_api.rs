@@ -4,12 +4,29 @@
 
 use std::sync::Arc;
 use std::sync::mpsc::SyncSender;
+use serde::{Deserialize, Serialize};
+use crate::libs::dsp::agc::{AgcPreset, AgcSettings};
+use crate::libs::dsp::bandpass::{BandpassPreset, BandpassSettings};
+use crate::libs::dsp::noise_blanker::NoiseBlankerSettings;
+use crate::libs::dsp::noise_reduction::NoiseReductionSettings;
+use crate::libs::dsp::notch::NotchSettings;
+use crate::libs::dsp::tune_assist::TuneAssistSettings;
+use crate::libs::smeter::ballistics::MeterBallistics;
+use crate::libs::smeter::smeter::SignalLevel;
 
 // The Receiver can effect changes in parts of the GUI by sending messages of this type
 // to the GUIInput channel (sender), obtained from the GUI.
-#[derive(Clone, PartialEq, Copy)]
+#[derive(Clone, PartialEq)]
 pub enum GUIInputMessage {
-    SignalStrength(f32)
+    SignalLevel(SignalLevel, SignalLevel), // At the needle, and at the peak-hold marker.
+    GainReduction(f32), // The AGC's, in dB.
+    Frequency(u32), // The rig has been retuned by something other than this GUI.
+    CatError(String, Option<&'static str>), // Communication with the rig has failed, and any advice.
+    CatRecovered, // ... and has since succeeded.
+    Disconnected, // The rig has gone away...
+    Reconnected, // ... and has come back.
+    Spectrum(Vec<f32>), // Levels in dBFS, from 0Hz in steps of BIN_WIDTH_HZ.
+    TuneAssistLevel(f32), // The band noise, in dBFS; only sent while the tune assist is on.
 }
 
 // The Receiver can connect to the GUI by implementing this, and sending these messages.
@@ -17,11 +34,58 @@ pub trait GUIInput {
     fn set_gui_input(&mut self, gui_input: Arc<SyncSender<GUIInputMessage>>);
 }
 
+// The colour schemes the waterfall can be shown in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WaterfallPalette {
+    Classic, // Black, blue, cyan, yellow, red.
+    Heat, // Black, red, yellow, white.
+    Grey,
+}
+
+// What the meter shows; clicking on it switches between them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MeterMode {
+    SignalLevel,
+    GainReduction, // The AGC's.
+}
+
 // Internal GUI messaging
 #[derive(Clone, Debug)]
 pub enum Message {
     SetAmplitude(f32),
-    SignalStrength(f32),
+    SignalLevel(SignalLevel, SignalLevel),
+    GainReduction(f32),
+    ToggleMeterMode,
+    SetMeterBallistics(MeterBallistics),
+    CalibrateMeter, // Against a signal generator, on the current band.
+    Frequency(u32),
+    CatStatus(String),
+    Connected(bool),
+    Spectrum(Vec<f32>),
+    SetSpectrumReferenceLevel(f32),
+    SetSpectrumSpan(u32),
+    SetSpectrumAveraging(u32),
+    SetWaterfallPalette(WaterfallPalette),
+    SetWaterfallSpeed(u32),
+    SetWaterfallContrast(f32),
+    SetTuningPitch(u32),
+    TuneToAudioFrequency(u32), // Retune so that this audio frequency is heard at the tuning pitch.
+    SetBandpassPreset(BandpassPreset),
+    SetBandpassCentre(f32),
+    SetNotchEnabled(bool),
+    SetNotchFrequency(f32), // Also enables it, as when the spectrum is clicked.
+    SetNotchQ(f32),
+    SetAutoNotch(bool),
+    SetNoiseReductionEnabled(bool),
+    SetNoiseReductionStrength(f32),
+    SetNoiseReductionBypass(bool),
+    SetNoiseBlankerEnabled(bool),
+    SetNoiseBlankerThreshold(f32),
+    SetAgcPreset(AgcPreset),
+    SetAgcMaxGain(f32),
+    SetTuneAssistEnabled(bool),
+    SetTuneAssistTone(bool),
+    TuneAssistLevel(f32),
     IncrementFrequencyWheel,
     IncrementFrequencyDigit(u32),
     DecrementFrequencyWheel,
@@ -35,4 +99,14 @@ pub enum Message {
 pub trait GUIOutput {
     fn set_frequency(&mut self, frequency_hz: u32);
     fn set_amplitude(&mut self, amplitude: f32); // 0.0 -> 1.0
+    fn set_spectrum_averaging(&mut self, averaging: u32); // The number of spectra averaged; 1 is none.
+    fn set_bandpass(&mut self, settings: BandpassSettings);
+    fn set_notch(&mut self, settings: NotchSettings);
+    fn set_auto_notch(&mut self, enabled: bool);
+    fn set_noise_reduction(&mut self, settings: NoiseReductionSettings);
+    fn set_noise_blanker(&mut self, settings: NoiseBlankerSettings);
+    fn set_agc(&mut self, settings: AgcSettings);
+    fn set_meter_calibration(&mut self, calibration_db: f32); // dBm = dBFS + this, on the current band.
+    fn set_meter_ballistics(&mut self, ballistics: MeterBallistics);
+    fn set_tune_assist(&mut self, settings: TuneAssistSettings);
 }
@@ -1,7 +1,24 @@
 pub mod audio;
+pub mod bands;
 pub mod cat;
+pub mod cat_worker;
+pub mod config;
+pub mod config_dir;
+pub mod dsp;
+pub mod error;
 pub mod fakereceiver;
+pub mod flrig;
 pub mod gui;
 pub mod gui_api;
+#[cfg(target_os = "linux")]
+pub mod pty;
+pub mod random;
 pub mod receiver;
-pub mod serial;
\ No newline at end of file
+pub mod rigctld;
+pub mod serial;
+pub mod simulator;
+pub mod smeter;
+pub mod spectrum;
+pub mod supervisor;
+pub mod tcp_server;
+pub mod transport;
\ No newline at end of file
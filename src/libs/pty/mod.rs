@@ -0,0 +1 @@
+pub mod pty;
\ No newline at end of file
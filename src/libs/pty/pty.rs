@@ -0,0 +1,256 @@
+// -------------------------------------------------------------------------------------------------
+// PSEUDO-TERMINAL CAT PASS-THROUGH
+// -------------------------------------------------------------------------------------------------
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::libs::cat::cat::Cat;
+use crate::libs::cat_worker::cat_worker::RememberedFrequency;
+use crate::libs::error::error::QdxError;
+use crate::libs::gui_api::gui_api::{GUIInput, GUIInputMessage};
+
+// How often a blocked read gives up, to check for termination.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// The reply to a request that couldn't be handled, as the TS-480 does.
+const ERROR_REPLY: &str = "?;";
+
+// Requests longer than this without a ; aren't CAT, and are discarded.
+const MAX_REQUEST_LENGTH: usize = 64;
+
+fn last_os_error(what: &str) -> QdxError {
+    QdxError::DeviceOpenFailed(format!("Could not create pseudo-terminal: {} failed: {}", what, io::Error::last_os_error()))
+}
+
+// A pseudo-terminal, in raw mode. Other programs open the slave side (e.g. /dev/pts/3) as if it
+// were a serial port; this reads and writes the master side.
+pub struct Pty {
+    master: File,
+    // Held open so the master doesn't see a hangup each time a program closes the slave.
+    _slave: File,
+    slave_path: String,
+}
+
+impl Pty {
+    pub fn open() -> Result<Pty, QdxError> {
+        // SAFETY: plain libc calls on a file descriptor that's owned by the File from here on.
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(last_os_error("posix_openpt"));
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 {
+                return Err(last_os_error("grantpt"));
+            }
+            if libc::unlockpt(fd) != 0 {
+                return Err(last_os_error("unlockpt"));
+            }
+            let mut name = [0 as libc::c_char; 128];
+            if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+                return Err(last_os_error("ptsname_r"));
+            }
+            let slave_path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+            // No echo, line editing or translation: CAT is binary-clean, ;-terminated.
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(last_os_error("tcgetattr"));
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(last_os_error("tcsetattr"));
+            }
+
+            let slave = match OpenOptions::new().read(true).write(true).open(&slave_path) {
+                Ok(slave) => slave,
+                Err(e) => {
+                    return Err(QdxError::DeviceOpenFailed(format!("Could not open pseudo-terminal {}: {}", slave_path, e)));
+                }
+            };
+            Ok(Pty { master, _slave: slave, slave_path })
+        }
+    }
+
+    // The device that other programs should open.
+    pub fn get_slave_path(&self) -> &str {
+        &self.slave_path
+    }
+
+    // Returns whether there's something to read, waiting at most the timeout.
+    pub fn wait_readable(&self, timeout: Duration) -> Result<bool, QdxError> {
+        let mut poll_fd = libc::pollfd { fd: self.master.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        // SAFETY: poll_fd is valid for the duration of the call.
+        let result = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err.into());
+        }
+        Ok(result > 0 && (poll_fd.revents & libc::POLLIN) != 0)
+    }
+}
+
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.master.read(buf)
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+// Collects ;-terminated requests from bytes as they arrive, ignoring the whitespace some programs
+// put between them.
+#[derive(Default)]
+pub struct RequestAssembler {
+    partial: String,
+}
+
+impl RequestAssembler {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut requests = vec![];
+        for byte in bytes {
+            let c = *byte as char;
+            if c.is_ascii_whitespace() || !c.is_ascii() {
+                continue;
+            }
+            self.partial.push(c);
+            if c == ';' {
+                requests.push(std::mem::take(&mut self.partial));
+            } else if self.partial.len() > MAX_REQUEST_LENGTH {
+                debug!("Discarding unterminated request '{}'", self.partial);
+                self.partial.clear();
+            }
+        }
+        requests
+    }
+}
+
+// Forwards TS-480 CAT requests from programs that can only use a serial port, via a pty, to the
+// QDX through the shared Cat. Frequency changes they make are shown in the GUI straight away, and
+// remembered, to be restored on reconnection.
+pub struct PtyServer {
+    gui_input: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>,
+    thread_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PtyServer {
+    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: RememberedFrequency) -> Result<Self, QdxError> {
+        let mut pty = Pty::open()?;
+        info!("CAT pass-through available on {}", pty.get_slave_path());
+
+        let gui_input_holder: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>> = Arc::new(Mutex::new(None));
+        let thread_gui_input_holder = gui_input_holder.clone();
+        let thread_handle = thread::spawn(move || {
+            let mut assembler = RequestAssembler::default();
+            let mut buffer = [0u8; 256];
+            loop {
+                if terminate.load(Ordering::SeqCst) {
+                    info!("Terminating pty server thread");
+                    break;
+                }
+                match pty.wait_readable(POLL_INTERVAL) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(err) => {
+                        warn!("Could not wait for pty: {}", err);
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                }
+                let count = match pty.read(&mut buffer) {
+                    Ok(count) => count,
+                    Err(err) => {
+                        warn!("Could not read from pty: {}", err);
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                };
+                for request in assembler.push(&buffer[..count]) {
+                    let reply = Self::forward(&cat, &frequency, &thread_gui_input_holder, &request);
+                    if let Some(reply) = reply {
+                        if let Err(err) = pty.write_all(reply.as_bytes()) {
+                            warn!("Could not write to pty: {}", err);
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            gui_input: gui_input_holder,
+            thread_handle: Mutex::new(Some(thread_handle)),
+        })
+    }
+
+    fn forward(cat: &Arc<Mutex<Cat>>, frequency: &RememberedFrequency, gui_input: &Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>, request: &str) -> Option<String> {
+        debug!("pty request '{}'", request);
+        let mut cat = cat.lock().unwrap();
+        match cat.pass_through(request) {
+            Ok(reply) => {
+                // A VFO A frequency set is what the GUI shows. It's remembered while the Cat is
+                // still locked, so a poll can't see it first.
+                if let Some(frequency_hz) = request.strip_prefix("FA").and_then(|rest| rest.strip_suffix(';')).and_then(|digits| digits.parse::<u32>().ok()) {
+                    frequency.set(frequency_hz);
+                    drop(cat);
+                    if let Some(gui_input) = gui_input.lock().unwrap().as_deref() {
+                        let _ = gui_input.send(GUIInputMessage::Frequency(frequency_hz));
+                    }
+                }
+                reply
+            }
+            Err(err) => {
+                warn!("pty request '{}' failed: {}", request, err);
+                Some(ERROR_REPLY.to_string())
+            }
+        }
+    }
+}
+
+impl GUIInput for PtyServer {
+    fn set_gui_input(&mut self, gui_input: Arc<SyncSender<GUIInputMessage>>) {
+        *self.gui_input.lock().unwrap() = Some(gui_input);
+    }
+}
+
+impl Drop for PtyServer {
+    fn drop(&mut self) {
+        debug!("PtyServer joining thread handle...");
+        let mut thread_handle = self.thread_handle.lock().unwrap();
+        thread_handle.take().map(JoinHandle::join);
+        debug!("...PtyServer joined thread handle");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::pty::pty::RequestAssembler;
+
+    #[test]
+    fn requests_are_assembled_across_reads() {
+        let mut assembler = RequestAssembler::default();
+        assert_eq!(assembler.push(b"FA;I"), vec!["FA;"]);
+        assert_eq!(assembler.push(b"F;\r\nMD2;"), vec!["IF;", "MD2;"]);
+        assert!(assembler.push(b"ID").is_empty());
+        assert_eq!(assembler.push(b";"), vec!["ID;"]);
+    }
+}
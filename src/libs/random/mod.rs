@@ -0,0 +1 @@
+pub mod random;
\ No newline at end of file
@@ -0,0 +1,30 @@
+// -------------------------------------------------------------------------------------------------
+// PSEUDO-RANDOM NUMBERS
+// -------------------------------------------------------------------------------------------------
+
+// A small, seedable pseudo-random number generator, so that simulated faults, fake signals and
+// tests are repeatable.
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // Zero would stay zero forever.
+        Self { state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed } }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    // In [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -2,52 +2,207 @@
 // RECEIVER
 // -------------------------------------------------------------------------------------------------
 
-use std::error::Error;
 use std::sync::{Arc, Mutex, RwLock};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::SyncSender;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
+use clap::arg_enum;
 use log::{debug, info, warn};
 use portaudio::{Duplex, DuplexStreamSettings, NonBlocking, PortAudio, Stream};
 use portaudio as pa;
+use ringbuf::{HeapConsumer, HeapRb};
 use crate::libs::cat::cat::Cat;
+use crate::libs::cat_worker::cat_worker::{CatWorker, RememberedFrequency};
+use crate::libs::dsp::agc::{Agc, AgcSettings};
+use crate::libs::dsp::auto_notch::AutoNotch;
+use crate::libs::dsp::bandpass::{Bandpass, BandpassSettings};
+use crate::libs::dsp::noise_blanker::{NoiseBlanker, NoiseBlankerSettings};
+use crate::libs::dsp::noise_reduction::{NoiseReduction, NoiseReductionSettings};
+use crate::libs::dsp::notch::{Notch, NotchSettings};
+use crate::libs::dsp::tune_assist::{TuneAssist, TuneAssistSettings};
+use crate::libs::error::error::QdxError;
 use crate::libs::gui_api::gui_api::{GUIInput, GUIInputMessage, GUIOutput};
+use crate::libs::smeter::ballistics::{Ballistics, MeterBallistics};
+use crate::libs::smeter::smeter::{DEFAULT_CALIBRATION_DB, SignalLevel};
+use crate::libs::spectrum::spectrum::{FFT_SIZE, SpectrumAnalyser};
+
+arg_enum! {
+    // Where the volume control takes effect: scaling the audio here, setting the QDX's own USB
+    // audio gain over CAT, or both.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum VolumeControl {
+        Software,
+        Rig,
+        Both,
+    }
+}
 
 #[derive(Clone)]
 pub struct CallbackData {
     amplitude: f32,
-    avg_waveform_amplitude: f32,
+    meter: Ballistics, // Of the power within the bandpass filter's passband.
     min_waveform_amplitude: f32,
     max_waveform_amplitude: f32,
+    callback_count: u64, // Stops increasing if the audio device disappears.
+    // The callback owns the DSP stages; these are their settings, which it picks up as they change.
+    bandpass: BandpassSettings,
+    notch: NotchSettings,
+    auto_notch: bool,
+    noise_reduction: NoiseReductionSettings,
+    noise_blanker: NoiseBlankerSettings,
+    agc: AgcSettings,
+    gain_reduction_db: f32,
+    meter_calibration_db: f32,
+    tune_assist: TuneAssistSettings,
+    tune_assist_level_dbfs: f32,
 }
 
 pub struct Receiver {
     gui_input: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>,
     read_thread_handle: Mutex<Option<JoinHandle<()>>>,
+    spectrum_thread_handle: Mutex<Option<JoinHandle<()>>>,
+    // The spectrum thread reads the input samples that the callback writes into the other end of
+    // this; a new one is made each time the callback is started.
+    spectrum_samples: Arc<Mutex<Option<HeapConsumer<f32>>>>,
+    spectrum_averaging: Arc<AtomicU32>,
     duplex_stream: Option<Stream<NonBlocking, Duplex<f32, f32>>>,
     callback_data: Arc<RwLock<CallbackData>>,
-    cat: Arc<Mutex<Cat>>,
+    cat_worker: CatWorker,
+    volume_control: VolumeControl,
 }
 
-// TODO replace this with obtaining the audio gain from the QDX, and setting it directly.
-const AMPLITUDE_GAIN: f32 = 90.0;
+// Half a second of input for the spectrum thread to catch up with; if it falls further behind,
+// the callback drops samples rather than waiting.
+const SPECTRUM_RING_BUFFER_SIZE: usize = 24000;
+const SPECTRUM_INTERVAL: Duration = Duration::from_millis(100);
 
-impl Receiver {
-    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>) -> Self {
-        let callback_data = CallbackData {
+// The volume control's 0.0 -> 1.0, as the QDX's 0 -> 255 audio gain.
+fn audio_gain(amplitude: f32) -> u8 {
+    (amplitude.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl CallbackData {
+    fn new() -> Self {
+        Self {
             amplitude: 0.0,
-            avg_waveform_amplitude: 0.0,
+            meter: Ballistics::new(MeterBallistics::Fast),
             min_waveform_amplitude: 100.0,
             max_waveform_amplitude: 0.0,
-        };
+            callback_count: 0,
+            bandpass: BandpassSettings::default(),
+            notch: NotchSettings::default(),
+            auto_notch: false,
+            noise_reduction: NoiseReductionSettings::default(),
+            noise_blanker: NoiseBlankerSettings::default(),
+            agc: AgcSettings::default(),
+            gain_reduction_db: 0.0,
+            meter_calibration_db: DEFAULT_CALIBRATION_DB,
+            tune_assist: TuneAssistSettings::default(),
+            tune_assist_level_dbfs: 0.0,
+        }
+    }
+}
+
+// The DSP stages the callback passes each input sample through.
+struct SignalChain {
+    noise_blanker: NoiseBlanker,
+    bandpass: Bandpass,
+    notch: Notch,
+    auto_notch: AutoNotch,
+    noise_reduction: NoiseReduction,
+    agc: Agc,
+    tune_assist: TuneAssist,
+}
+
+impl SignalChain {
+    fn new(callback_data: &CallbackData) -> Self {
+        let mut auto_notch = AutoNotch::default();
+        auto_notch.set_enabled(callback_data.auto_notch);
+        Self {
+            noise_blanker: NoiseBlanker::new(callback_data.noise_blanker),
+            bandpass: Bandpass::new(callback_data.bandpass),
+            notch: Notch::new(callback_data.notch),
+            auto_notch,
+            noise_reduction: NoiseReduction::new(callback_data.noise_reduction),
+            agc: Agc::new(callback_data.agc),
+            tune_assist: TuneAssist::new(callback_data.tune_assist),
+        }
+    }
+
+    fn set_settings(&mut self, callback_data: &CallbackData) {
+        self.noise_blanker.set_settings(callback_data.noise_blanker);
+        self.bandpass.set_settings(callback_data.bandpass);
+        self.notch.set_settings(callback_data.notch);
+        self.auto_notch.set_enabled(callback_data.auto_notch);
+        self.noise_reduction.set_settings(callback_data.noise_reduction);
+        self.agc.set_settings(callback_data.agc);
+        self.tune_assist.set_settings(callback_data.tune_assist);
+    }
+
+    // Returns the sample to be heard, before the volume control, and the power for the meter.
+    fn process(&mut self, input: f32) -> (f32, f32) {
+        // Impulses are blanked before any filter can spread them out.
+        let filtered = self.bandpass.process(self.noise_blanker.process(input));
+        // The meter measures what's in the passband, before the notches, noise reduction and AGC
+        // change its level, so its calibration holds whatever else is on the band.
+        let power = filtered * filtered;
+        let notched = self.auto_notch.process(self.notch.process(filtered));
+        // The tune assist measures the input, and may replace what's heard with its tone.
+        let sample = self.tune_assist.process(input, self.agc.process(self.noise_reduction.process(notched)));
+        (sample, power)
+    }
+}
+
+impl Receiver {
+    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: u32, volume_control: VolumeControl) -> Self {
+        let callback_data = CallbackData::new();
 
         let arc_lock_callback_data = Arc::new(RwLock::new(callback_data));
         let gui_input_holder: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>> = Arc::new(Mutex::new(None));
         let thread_gui_input_holder = gui_input_holder.clone();
+        let cat_worker = CatWorker::new(terminate.clone(), cat, frequency, gui_input_holder.clone());
 
-        // This thread periodically sends the avg_waveform_amplitude to the gui_input.
+        // This thread periodically analyses the latest input samples and sends the spectrum to
+        // the gui_input.
+        let spectrum_samples: Arc<Mutex<Option<HeapConsumer<f32>>>> = Arc::new(Mutex::new(None));
+        let spectrum_averaging = Arc::new(AtomicU32::new(1));
+        let thread_spectrum_samples = spectrum_samples.clone();
+        let thread_spectrum_averaging = spectrum_averaging.clone();
+        let spectrum_gui_input_holder = gui_input_holder.clone();
+        let spectrum_terminate = terminate.clone();
+        let spectrum_thread_handle = thread::spawn(move || {
+            let mut analyser = SpectrumAnalyser::new();
+            let mut history: Vec<f32> = Vec::with_capacity(SPECTRUM_RING_BUFFER_SIZE + FFT_SIZE);
+            let mut chunk = vec![0.0; SPECTRUM_RING_BUFFER_SIZE];
+            loop {
+                if spectrum_terminate.load(Ordering::SeqCst) {
+                    info!("Terminating Receiver spectrum thread");
+                    break;
+                }
+                thread::sleep(SPECTRUM_INTERVAL);
+                let count = match thread_spectrum_samples.lock().unwrap().as_mut() {
+                    None => continue,
+                    Some(consumer) => consumer.pop_slice(&mut chunk),
+                };
+                history.extend_from_slice(&chunk[..count]);
+                if history.len() > FFT_SIZE {
+                    history.drain(..history.len() - FFT_SIZE);
+                }
+                if history.len() < FFT_SIZE {
+                    continue;
+                }
+                analyser.set_averaging(thread_spectrum_averaging.load(Ordering::SeqCst));
+                let spectrum = analyser.process(&history);
+                if let Some(gui_input) = spectrum_gui_input_holder.lock().unwrap().as_deref() {
+                    let _ = gui_input.send(GUIInputMessage::Spectrum(spectrum));
+                }
+            }
+        });
+
+        // This thread periodically sends the calibrated signal level, the AGC's gain reduction and
+        // the tune assist's noise level to the gui_input.
         let thread_callback_data = arc_lock_callback_data.clone();
         let read_thread_handle = thread::spawn(move || {
             loop {
@@ -62,59 +217,84 @@ impl Receiver {
                     }
                     Some(gui_input) => {
                         let callback_data = thread_callback_data.read().unwrap();
-                        let strength = callback_data.avg_waveform_amplitude;
+                        let signal_level = SignalLevel::from_mean_square(callback_data.meter.level(), callback_data.meter_calibration_db);
+                        let peak_level = SignalLevel::from_mean_square(callback_data.meter.peak(), callback_data.meter_calibration_db);
+                        let tune_assist_level_dbfs = if callback_data.tune_assist.enabled { Some(callback_data.tune_assist_level_dbfs) } else { None };
+                        let gain_reduction_db = callback_data.gain_reduction_db;
                         // info!("min {} max {}", callback_data.min_waveform_amplitude, callback_data.max_waveform_amplitude);
                         drop(callback_data);
 
-                        let _ = gui_input.send(GUIInputMessage::SignalStrength(strength));
+                        let _ = gui_input.send(GUIInputMessage::SignalLevel(signal_level, peak_level));
+                        let _ = gui_input.send(GUIInputMessage::GainReduction(gain_reduction_db));
+                        if let Some(level_dbfs) = tune_assist_level_dbfs {
+                            let _ = gui_input.send(GUIInputMessage::TuneAssistLevel(level_dbfs));
+                        }
                     }
                 }
             }
         });
+
         Self {
             gui_input: gui_input_holder,
             read_thread_handle: Mutex::new(Some(read_thread_handle)),
+            spectrum_thread_handle: Mutex::new(Some(spectrum_thread_handle)),
+            spectrum_samples,
+            spectrum_averaging,
             duplex_stream: None,
             callback_data: arc_lock_callback_data,
-            cat,
+            cat_worker,
+            volume_control,
         }
     }
 
     // The odd form of this callback setup (pass in the PortAudio and settings) rather than just
     // returning the callback to the caller to do stuff with... is because I can't work out what
     // the correct type signature of a callback-returning function should be.
-    pub fn start_duplex_callback(&mut self, pa: &PortAudio, duplex_settings: DuplexStreamSettings<f32, f32>) -> Result<(), Box<dyn Error>> {
+    pub fn start_duplex_callback(&mut self, pa: &PortAudio, duplex_settings: DuplexStreamSettings<f32, f32>) -> Result<(), QdxError> {
 
         let move_clone_callback_data = self.callback_data.clone();
+        let (mut spectrum_producer, spectrum_consumer) = HeapRb::<f32>::new(SPECTRUM_RING_BUFFER_SIZE).split();
+        *self.spectrum_samples.lock().unwrap() = Some(spectrum_consumer);
+
+        let mut chain = SignalChain::new(&self.callback_data.read().unwrap());
 
         let callback = move |pa::DuplexStreamCallbackArgs::<f32, f32> { in_buffer, out_buffer, frames, .. }| {
             //info!("input buffer length is {}, output buffer length is {}, frames is {}", in_buffer.len(), out_buffer.len(), frames);
             // input buffer length is 128, output buffer length is 128, frames is 64
             let callback_data = move_clone_callback_data.read().unwrap();
-            let amplitude = callback_data.amplitude * AMPLITUDE_GAIN;
+            let amplitude = callback_data.amplitude;
+            chain.set_settings(&callback_data);
             drop(callback_data);
 
-            let mut avg_waveform_amplitude = 0.0;
+            // The spectrum is of the left channel, before the volume control. If the ring buffer
+            // is full, the rest are lost.
+            spectrum_producer.push_iter(&mut in_buffer.iter().step_by(2).copied());
+
+            let mut passband_power = 0.0;
             let mut min_amp = 100.0;
             let mut max_amp = 0.0;
-            for idx in 0..frames * 2 {
-                // TODO MONO - if opening the stream with a single channel causes the same values to
-                // be written to both left and right outputs, this could be optimised..
-                let sample = in_buffer[idx] * amplitude;
+            // The QDX's audio is mono, so only the left channel is processed, and the result is
+            // written to both outputs.
+            for frame in 0..frames {
+                let (output, power) = chain.process(in_buffer[frame * 2]);
+                passband_power += power;
+                let sample = output * amplitude;
                 if sample < min_amp {
                     min_amp = sample;
                 }
                 if sample > max_amp {
                     max_amp = sample;
                 }
-                out_buffer[idx] = sample ; // why a scaling factor? why is input so quiet? don't know!
-                avg_waveform_amplitude += sample.abs(); // Should be in range [0..1]
+                out_buffer[frame * 2] = sample;
+                out_buffer[frame * 2 + 1] = sample;
             }
 
-            avg_waveform_amplitude /= 128.0; // should be in range [0..1]
+            passband_power /= frames as f32;
             let mut callback_data = move_clone_callback_data.write().unwrap();
-            callback_data.avg_waveform_amplitude -= callback_data.avg_waveform_amplitude / 40.0;
-            callback_data.avg_waveform_amplitude += avg_waveform_amplitude / 40.0;
+            callback_data.callback_count += 1;
+            callback_data.gain_reduction_db = chain.agc.gain_reduction_db();
+            callback_data.meter.process(passband_power, frames);
+            callback_data.tune_assist_level_dbfs = chain.tune_assist.level_dbfs();
             if min_amp < callback_data.min_waveform_amplitude {
                 callback_data.min_waveform_amplitude = min_amp;
             }
@@ -122,7 +302,8 @@ impl Receiver {
                 callback_data.max_waveform_amplitude = max_amp;
             }
 
-            // With AMPLITUDE set as above, the min/max are around -1 .. +1 on very strong signals.
+            // The QDX's quiet input is made up by the AGC (or its fixed gain, when off), so at full
+            // volume the min/max are within -1 .. +1 on strong signals.
             pa::Continue
         };
 
@@ -135,11 +316,43 @@ impl Receiver {
             }
             Err(e) => {
                 warn!("Error opening duplex stream: {}", e);
+                return Err(e.into());
             }
         }
         Ok(())
         // Now it's playing...
     }
+
+    // Closing the stream releases the audio devices, so they can be found again if the QDX
+    // reappears.
+    pub fn stop_duplex_callback(&mut self) {
+        if let Some(mut stream) = self.duplex_stream.take() {
+            info!("Stopping duplex stream: {:?}", stream.stop());
+            // Dropping it closes it.
+        }
+    }
+
+    // The Supervisor watches this to detect loss of the QDX's audio.
+    pub fn get_callback_count(&self) -> u64 {
+        self.callback_data.read().unwrap().callback_count
+    }
+
+    pub fn is_cat_connection_lost(&self) -> bool {
+        self.cat_worker.is_connection_lost()
+    }
+
+    pub fn disconnect_cat(&self) {
+        self.cat_worker.disconnect();
+    }
+
+    pub fn reconnect_cat(&self, cat: Cat) {
+        self.cat_worker.reconnect(cat);
+    }
+
+    // For the servers that share the Cat, to record the frequencies they set.
+    pub fn remembered_frequency(&self) -> RememberedFrequency {
+        self.cat_worker.remembered_frequency()
+    }
 }
 
 impl GUIInput for Receiver {
@@ -150,21 +363,90 @@ impl GUIInput for Receiver {
 
 impl GUIOutput for Receiver {
     fn set_frequency(&mut self, frequency_hz: u32) {
-        self.cat.lock().unwrap().set_frequency(frequency_hz).unwrap();
+        self.cat_worker.set_frequency(frequency_hz);
     }
 
     fn set_amplitude(&mut self, amplitude: f32) {
+        if self.volume_control != VolumeControl::Software {
+            self.cat_worker.set_audio_gain(audio_gain(amplitude));
+        }
         let mut callback_data = self.callback_data.write().unwrap();
-        callback_data.amplitude = amplitude;
+        // With only the QDX's gain to turn down, the audio passes through unscaled.
+        callback_data.amplitude = if self.volume_control == VolumeControl::Rig { 1.0 } else { amplitude };
+    }
+
+    fn set_spectrum_averaging(&mut self, averaging: u32) {
+        self.spectrum_averaging.store(averaging, Ordering::SeqCst);
+    }
+
+    fn set_bandpass(&mut self, settings: BandpassSettings) {
+        self.callback_data.write().unwrap().bandpass = settings;
+    }
+
+    fn set_notch(&mut self, settings: NotchSettings) {
+        self.callback_data.write().unwrap().notch = settings;
+    }
+
+    fn set_auto_notch(&mut self, enabled: bool) {
+        self.callback_data.write().unwrap().auto_notch = enabled;
+    }
+
+    fn set_noise_reduction(&mut self, settings: NoiseReductionSettings) {
+        self.callback_data.write().unwrap().noise_reduction = settings;
+    }
+
+    fn set_noise_blanker(&mut self, settings: NoiseBlankerSettings) {
+        self.callback_data.write().unwrap().noise_blanker = settings;
+    }
+
+    fn set_agc(&mut self, settings: AgcSettings) {
+        self.callback_data.write().unwrap().agc = settings;
+    }
+
+    fn set_meter_calibration(&mut self, calibration_db: f32) {
+        self.callback_data.write().unwrap().meter_calibration_db = calibration_db;
+    }
+
+    fn set_meter_ballistics(&mut self, ballistics: MeterBallistics) {
+        self.callback_data.write().unwrap().meter.set_ballistics(ballistics);
+    }
+
+    fn set_tune_assist(&mut self, settings: TuneAssistSettings) {
+        self.callback_data.write().unwrap().tune_assist = settings;
     }
 }
 
 impl Drop for Receiver {
     fn drop(&mut self) {
-        info!("Stopping duplex stream: {:?}", self.duplex_stream.as_mut().unwrap().stop());
+        self.stop_duplex_callback();
         debug!("Receiver joining thread handle...");
         let mut read_thread_handle = self.read_thread_handle.lock().unwrap();
         read_thread_handle.take().map(JoinHandle::join);
-        debug!("...FakeReceiver joined thread handle");
+        let mut spectrum_thread_handle = self.spectrum_thread_handle.lock().unwrap();
+        spectrum_thread_handle.take().map(JoinHandle::join);
+        debug!("...Receiver joined thread handles");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::test_signals::mean_output;
+    use crate::libs::receiver::receiver::{CallbackData, SignalChain};
+
+    // The mean power the meter is given for a tone, once the filters have settled.
+    fn metered_power(frequency_hz: f32) -> f32 {
+        let mut chain = SignalChain::new(&CallbackData::new());
+        mean_output(frequency_hz, 0.5, |sample| chain.process(sample).1)
+    }
+
+    #[test]
+    fn the_meter_measures_the_passband() {
+        // The default is the SSB filter, from 300Hz to 2.7kHz.
+        let in_passband = metered_power(1500.0);
+        assert!((in_passband - 0.125).abs() < 0.01, "{}", in_passband);
+        for frequency_hz in [100.0, 8000.0, 12000.0] {
+            let off_passband = metered_power(frequency_hz);
+            assert!(off_passband < in_passband / 1000.0, "{} Hz: {}", frequency_hz, off_passband);
+        }
     }
 }
@@ -0,0 +1 @@
+pub mod rigctld;
\ No newline at end of file
@@ -0,0 +1,324 @@
+// -------------------------------------------------------------------------------------------------
+// RIGCTLD - HAMLIB NET RIGCTL SERVER
+// -------------------------------------------------------------------------------------------------
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::{debug, warn};
+use crate::libs::cat::cat::{Cat, RigMode, Vfo};
+use crate::libs::cat_worker::cat_worker::RememberedFrequency;
+use crate::libs::error::error::{is_timeout, QdxError};
+use crate::libs::tcp_server::tcp_server::TcpServer;
+
+pub const DEFAULT_RIGCTLD_PORT: u16 = 4532;
+
+// The QDX doesn't report its filter width; this is what's reported to clients.
+const PASSBAND_HZ: u32 = 3000;
+
+// Hamlib error codes, returned negated in RPRT replies.
+const RIG_OK: i32 = 0;
+const RIG_EINVAL: i32 = 1;
+const RIG_ENIMPL: i32 = 4;
+const RIG_ETIMEOUT: i32 = 5;
+const RIG_EIO: i32 = 6;
+const RIG_EPROTO: i32 = 8;
+
+// The reply to \dump_state, in protocol version 0 format: the rig's capabilities, in the order the
+// Hamlib NET rigctl backend reads them. Receive range covers the QDX's bands, in all the modes the
+// TS-480 command set can select; transmit isn't offered, as qdx-receiver is receive-only.
+const DUMP_STATE: &str = "0
+2
+2
+3000000.000000 30000000.000000 0x1bf -1 -1 0x3 0x0
+0 0 0 0 0 0 0
+0 0 0 0 0 0 0
+0x1bf 1
+0 0
+0x1bf 3000
+0 0
+0
+0
+0
+0
+0
+0
+0x0
+0x0
+0x0
+0x0
+0x0
+0x0
+";
+
+// Serves the rigctld protocol to other programs (e.g. WSJT-X or fldigi set to "Hamlib NET rigctl"),
+// so they can share the QDX's CAT port with qdx-receiver.
+pub struct RigctldServer {
+    _server: TcpServer,
+}
+
+impl RigctldServer {
+    pub fn new(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: RememberedFrequency, port: u16) -> Result<Self, QdxError> {
+        let server = TcpServer::new(terminate, "rigctld", port, move |terminate, stream| {
+            Self::serve_client(terminate, cat.clone(), frequency.clone(), stream)
+        })?;
+        Ok(Self {
+            _server: server,
+        })
+    }
+
+    fn serve_client(terminate: Arc<AtomicBool>, cat: Arc<Mutex<Cat>>, frequency: RememberedFrequency, stream: TcpStream) -> Result<(), QdxError> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            if terminate.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            // A timeout may leave part of a line read; it's completed on the next attempt.
+            match reader.read_line(&mut line) {
+                Ok(0) => {
+                    return Ok(());
+                }
+                Ok(_) => {
+                    debug!("rigctld request {:?}", line.trim_end());
+                    let reply = handle_command(&mut cat.lock().unwrap(), &frequency, &line);
+                    line.clear();
+                    match reply {
+                        Some(reply) => {
+                            writer.write_all(reply.as_bytes())?;
+                        }
+                        None => {
+                            return Ok(());
+                        }
+                    }
+                }
+                Err(e) if is_timeout(&e) => {}
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    line.clear();
+                    writer.write_all(rprt(RIG_EINVAL).as_bytes())?;
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
+fn rprt(code: i32) -> String {
+    format!("RPRT {}\n", -code)
+}
+
+fn error_code(err: &QdxError) -> i32 {
+    match err {
+        QdxError::InvalidRequest(_) => RIG_EINVAL,
+        QdxError::Timeout(_) => RIG_ETIMEOUT,
+        QdxError::MalformedResponse(_) | QdxError::MismatchedResponse { .. } => RIG_EPROTO,
+        _ => RIG_EIO,
+    }
+}
+
+fn hamlib_mode_name(mode: RigMode) -> &'static str {
+    match mode {
+        RigMode::Lsb => "LSB",
+        RigMode::Usb => "USB",
+        RigMode::Cw => "CW",
+        RigMode::Fm => "FM",
+        RigMode::Am => "AM",
+        RigMode::Fsk => "RTTY",
+        RigMode::CwReverse => "CWR",
+        RigMode::FskReverse => "RTTYR",
+    }
+}
+
+fn hamlib_mode(name: &str) -> Option<RigMode> {
+    match name {
+        "LSB" | "PKTLSB" => Some(RigMode::Lsb),
+        "USB" | "PKTUSB" => Some(RigMode::Usb),
+        "CW" => Some(RigMode::Cw),
+        "FM" | "PKTFM" => Some(RigMode::Fm),
+        "AM" => Some(RigMode::Am),
+        "RTTY" => Some(RigMode::Fsk),
+        "CWR" => Some(RigMode::CwReverse),
+        "RTTYR" => Some(RigMode::FskReverse),
+        _ => None,
+    }
+}
+
+fn hamlib_vfo_name(vfo: Vfo) -> &'static str {
+    match vfo {
+        Vfo::A => "VFOA",
+        Vfo::B => "VFOB",
+        Vfo::Memory => "MEM",
+    }
+}
+
+// The VFO named; for currVFO, that's whichever the rig is receiving on.
+fn hamlib_vfo(cat: &mut Cat, name: &str) -> Result<Option<Vfo>, QdxError> {
+    match name {
+        "VFOA" | "Main" => Ok(Some(Vfo::A)),
+        "VFOB" | "Sub" => Ok(Some(Vfo::B)),
+        "MEM" => Ok(Some(Vfo::Memory)),
+        "currVFO" => cat.get_receive_vfo().map(Some),
+        _ => Ok(None),
+    }
+}
+
+// Handles one line of the rigctld protocol, returning the reply, or None if the client has asked
+// to close the connection. Gets reply with values, one per line; sets reply with RPRT 0 or a
+// negative Hamlib error code. Frequencies set are remembered, to be restored on reconnection.
+pub fn handle_command(cat: &mut Cat, frequency: &RememberedFrequency, line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return Some(String::new()),
+    };
+    let args: Vec<&str> = words.collect();
+    let reply = match command {
+        "f" | "\\get_freq" => {
+            cat.get_frequency().map(|frequency_hz| format!("{}\n", frequency_hz))
+        }
+        "F" | "\\set_freq" => {
+            // Clients may send fractional Hz.
+            match args.first().and_then(|arg| arg.parse::<f64>().ok()) {
+                Some(frequency_hz) if (0.0..=u32::MAX as f64).contains(&frequency_hz) => {
+                    let frequency_hz = frequency_hz.round() as u32;
+                    cat.set_frequency(frequency_hz).map(|_| {
+                        frequency.set(frequency_hz);
+                        rprt(RIG_OK)
+                    })
+                }
+                _ => Ok(rprt(RIG_EINVAL)),
+            }
+        }
+        "m" | "\\get_mode" => {
+            cat.get_mode().map(|mode| format!("{}\n{}\n", hamlib_mode_name(mode), PASSBAND_HZ))
+        }
+        "M" | "\\set_mode" => {
+            // The passband can't be changed, so is ignored.
+            match args.first().and_then(|arg| hamlib_mode(arg)) {
+                Some(mode) => cat.set_mode(mode).map(|_| rprt(RIG_OK)),
+                None => Ok(rprt(RIG_EINVAL)),
+            }
+        }
+        "t" | "\\get_ptt" => {
+            cat.is_transmitting().map(|transmitting| format!("{}\n", if transmitting { 1 } else { 0 }))
+        }
+        "v" | "\\get_vfo" => {
+            cat.get_receive_vfo().map(|vfo| format!("{}\n", hamlib_vfo_name(vfo)))
+        }
+        "V" | "\\set_vfo" => {
+            hamlib_vfo(cat, args.first().copied().unwrap_or_default()).and_then(|vfo| match vfo {
+                Some(vfo) => cat.set_receive_vfo(vfo).map(|_| rprt(RIG_OK)),
+                None => Ok(rprt(RIG_EINVAL)),
+            })
+        }
+        "s" | "\\get_split_vfo" => {
+            cat.get_if_status().and_then(|status| {
+                let transmit_vfo = cat.get_transmit_vfo()?;
+                Ok(format!("{}\n{}\n", if status.split { 1 } else { 0 }, hamlib_vfo_name(transmit_vfo)))
+            })
+        }
+        "\\dump_state" => {
+            Ok(DUMP_STATE.to_string())
+        }
+        "\\chk_vfo" => {
+            // VFO arguments aren't expected on each command.
+            Ok("0\n".to_string())
+        }
+        "\\get_powerstat" => {
+            Ok("1\n".to_string())
+        }
+        "q" | "Q" | "\\quit" => {
+            return None;
+        }
+        _ => {
+            debug!("Unsupported rigctld command {:?}", command);
+            Ok(rprt(RIG_ENIMPL))
+        }
+    };
+    Some(reply.unwrap_or_else(|err| {
+        warn!("rigctld command {:?} failed: {}", command, err);
+        rprt(error_code(&err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::cat::cat::Cat;
+    use crate::libs::cat_worker::cat_worker::RememberedFrequency;
+    use crate::libs::rigctld::rigctld::handle_command;
+    use crate::libs::transport::transport::MockTransport;
+
+    fn cat_with(transport: &MockTransport) -> Cat {
+        Cat::new(Box::new(transport.clone()))
+    }
+
+    #[test]
+    fn get_and_set_frequency() {
+        let transport = MockTransport::new();
+        transport.expect("FA;", "FA00014074000;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "f\n").unwrap(), "14074000\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "\\set_freq 7074000.000000\n").unwrap(), "RPRT 0\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "F banana\n").unwrap(), "RPRT -1\n");
+        assert_eq!(transport.written(), "FA;FA00007074000;");
+        assert_eq!(frequency.get(), 7074000);
+    }
+
+    #[test]
+    fn get_and_set_mode() {
+        let transport = MockTransport::new();
+        transport.expect("MD;", "MD2;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "m\n").unwrap(), "USB\n3000\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "M CW 500\n").unwrap(), "RPRT 0\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "M WFM 0\n").unwrap(), "RPRT -1\n");
+        assert_eq!(transport.written(), "MD;MD3;");
+    }
+
+    #[test]
+    fn get_vfo() {
+        let transport = MockTransport::new();
+        transport.expect("FR;", "FR1;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "v\n").unwrap(), "VFOB\n");
+    }
+
+    #[test]
+    fn set_vfo() {
+        let transport = MockTransport::new();
+        transport.expect("FR1;", "");
+        transport.expect("FR;", "FR1;");
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "V VFOB\n").unwrap(), "RPRT 0\n");
+        // The current VFO is the one the rig has selected, not necessarily A.
+        assert_eq!(handle_command(&mut cat, &frequency, "V currVFO\n").unwrap(), "RPRT 0\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "V VFOC\n").unwrap(), "RPRT -1\n");
+        assert_eq!(transport.written(), "FR1;FR;FR1;");
+    }
+
+    #[test]
+    fn failures_are_reported_as_error_codes() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "f\n").unwrap(), "RPRT -5\n");
+        assert_eq!(handle_command(&mut cat, &frequency, "\\set_level AF 0.5\n").unwrap(), "RPRT -4\n");
+    }
+
+    #[test]
+    fn quit_closes_connection() {
+        let transport = MockTransport::new();
+        let mut cat = cat_with(&transport);
+        let frequency = RememberedFrequency::new(14074000);
+        assert_eq!(handle_command(&mut cat, &frequency, "\\chk_vfo\n").unwrap(), "0\n");
+        assert!(handle_command(&mut cat, &frequency, "q\n").is_none());
+    }
+}
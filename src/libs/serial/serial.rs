@@ -2,30 +2,188 @@
 // SERIAL PORT
 // -------------------------------------------------------------------------------------------------
 
-use std::error::Error;
 use log::{debug, info};
-use serialport::{SerialPortInfo, SerialPortType};
+use regex::Regex;
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+use crate::libs::error::error::QdxError;
 
-pub fn find_qdx_serial_port() -> Result<SerialPortInfo, Box<dyn Error>> {
+fn describe_port(port: &SerialPortInfo) -> String {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            format!("{} (USB {:04x}:{:04x}, serial number {}, product {})", port.port_name, usb.vid, usb.pid,
+                usb.serial_number.as_deref().unwrap_or("unknown"),
+                usb.product.as_deref().unwrap_or("unknown"))
+        }
+        SerialPortType::PciPort => format!("{} (PCI)", port.port_name),
+        SerialPortType::BluetoothPort => format!("{} (Bluetooth)", port.port_name),
+        SerialPortType::Unknown => port.port_name.clone(),
+    }
+}
+
+fn describe_ports(ports: &[SerialPortInfo]) -> String {
+    if ports.is_empty() {
+        return "  (none)".to_string();
+    }
+    let descriptions: Vec<String> = ports.iter().map(|p| format!("  {}", describe_port(p))).collect();
+    descriptions.join("\n")
+}
+
+fn usb_info(port: &SerialPortInfo) -> Option<&UsbPortInfo> {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb) => Some(usb),
+        _ => None,
+    }
+}
+
+fn is_qdx_product(usb: &UsbPortInfo) -> bool {
+    match &usb.product {
+        Some(product) => product.to_uppercase().contains("QDX"),
+        None => false,
+    }
+}
+
+// Which of the ports match the port_spec given with --catport? This may be a port name, a USB
+// serial number, or a USB vendor:product ID pair in hex, e.g. 0483:a34c.
+fn matching_ports(ports: &[SerialPortInfo], port_spec: &str) -> Vec<SerialPortInfo> {
+    let by_name: Vec<SerialPortInfo> = ports.iter().filter(|p| p.port_name == port_spec).cloned().collect();
+    if !by_name.is_empty() {
+        return by_name;
+    }
+
+    let by_serial_number: Vec<SerialPortInfo> = ports.iter()
+        .filter(|p| usb_info(p).and_then(|usb| usb.serial_number.as_deref()) == Some(port_spec))
+        .cloned()
+        .collect();
+    if !by_serial_number.is_empty() {
+        return by_serial_number;
+    }
+
+    let vid_pid_regex = Regex::new(r"^([0-9a-fA-F]{4}):([0-9a-fA-F]{4})$").unwrap();
+    if let Some(captures) = vid_pid_regex.captures(port_spec) {
+        let vid = u16::from_str_radix(captures.get(1).unwrap().as_str(), 16).unwrap();
+        let pid = u16::from_str_radix(captures.get(2).unwrap().as_str(), 16).unwrap();
+        return ports.iter()
+            .filter(|p| usb_info(p).map(|usb| usb.vid == vid && usb.pid == pid).unwrap_or(false))
+            .cloned()
+            .collect();
+    }
+    vec![]
+}
+
+// With no port_spec, finds the QDX by its USB product name. Otherwise the port_spec (from
+// --catport) chooses the port: see matching_ports. A port_spec containing a path separator that
+// doesn't match any enumerated port is used as-is, so that ports the OS doesn't enumerate (e.g.
+// pseudo-terminals) can be used. It's an error if there's more than one candidate.
+pub fn find_qdx_serial_port(port_spec: Option<&str>) -> Result<SerialPortInfo, QdxError> {
     let ports = serialport::available_ports()?;
     info!("Scanning serial ports...");
-    for p in ports {
+    for p in &ports {
         debug!("Port {:?}", p);
-        let return_p = p.clone();
-        let match_p = p.clone();
-        match match_p.port_type {
-            SerialPortType::UsbPort(usb) => {
-                if usb.product == Some("QDX Transceiver".to_string()) {
-                    let found = return_p.clone();
-                    info!("Found QDX Transceiver as {:?}", found);
-                    return Ok(return_p);
-                }
+    }
+    choose_port(&ports, port_spec)
+}
+
+fn choose_port(ports: &[SerialPortInfo], port_spec: Option<&str>) -> Result<SerialPortInfo, QdxError> {
+    let candidates: Vec<SerialPortInfo> = match port_spec {
+        Some(spec) => {
+            let candidates = matching_ports(ports, spec);
+            if candidates.is_empty() && (spec.contains('/') || spec.contains('\\')) {
+                info!("Using {} as QDX serial device", spec);
+                return Ok(SerialPortInfo { port_name: spec.to_string(), port_type: SerialPortType::Unknown });
             }
-            SerialPortType::PciPort => {}
-            SerialPortType::BluetoothPort => {}
-            SerialPortType::Unknown => {}
+            candidates
+        }
+        None => ports.iter().filter(|p| usb_info(p).map(is_qdx_product).unwrap_or(false)).cloned().collect(),
+    };
+
+    match candidates.len() {
+        1 => {
+            let found = candidates[0].clone();
+            info!("Found QDX Transceiver as {:?}", found);
+            Ok(found)
+        }
+        0 => {
+            let what = match port_spec {
+                Some(spec) => format!("Can't find a serial device matching '{}'", spec),
+                None => "Can't find QDX USB serial device".to_string(),
+            };
+            Err(QdxError::DeviceNotFound(format!("{}; available serial devices are:\n{}\nUse --catport with a port name, USB serial number or vid:pid",
+                what, describe_ports(ports))))
+        }
+        _ => {
+            Err(QdxError::DeviceAmbiguous(format!("More than one possible QDX serial device:\n{}\nUse --catport with a port name or USB serial number to choose one",
+                describe_ports(&candidates))))
         }
     }
-    Err(Box::<dyn Error + Send + Sync>::from("Can't find QDX USB serial device"))
 }
 
+#[cfg(test)]
+mod tests {
+    use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+    use crate::libs::error::error::QdxError;
+    use crate::libs::serial::serial::{choose_port, matching_ports};
+
+    fn usb_port(port_name: &str, vid: u16, pid: u16, serial_number: &str, product: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: port_name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: Some(serial_number.to_string()),
+                manufacturer: Some("QRP Labs".to_string()),
+                product: Some(product.to_string()),
+            }),
+        }
+    }
+
+    // Two QDXs, one behind a hub that has mangled its product string, and something else.
+    fn ports() -> Vec<SerialPortInfo> {
+        vec![
+            usb_port("/dev/ttyACM0", 0x0483, 0xa34c, "3473355B3331", "QDX Transceiver"),
+            usb_port("/dev/ttyACM1", 0x0483, 0xa34c, "205F33A14E30", "USB Serial"),
+            usb_port("/dev/ttyUSB0", 0x0403, 0x6001, "A50285BI", "FT232R USB UART"),
+            SerialPortInfo { port_name: "/dev/ttyS0".to_string(), port_type: SerialPortType::Unknown },
+        ]
+    }
+
+    fn port_names(ports: &[SerialPortInfo]) -> Vec<&str> {
+        ports.iter().map(|p| p.port_name.as_str()).collect()
+    }
+
+    #[test]
+    fn match_by_name() {
+        assert_eq!(port_names(&matching_ports(&ports(), "/dev/ttyACM1")), vec!["/dev/ttyACM1"]);
+        assert_eq!(port_names(&matching_ports(&ports(), "/dev/ttyS0")), vec!["/dev/ttyS0"]);
+        assert!(matching_ports(&ports(), "/dev/ttyACM2").is_empty());
+    }
+
+    #[test]
+    fn match_by_serial_number() {
+        assert_eq!(port_names(&matching_ports(&ports(), "205F33A14E30")), vec!["/dev/ttyACM1"]);
+        assert!(matching_ports(&ports(), "205F33A14E3").is_empty());
+    }
+
+    #[test]
+    fn match_by_vid_pid() {
+        assert_eq!(port_names(&matching_ports(&ports(), "0403:6001")), vec!["/dev/ttyUSB0"]);
+        assert_eq!(port_names(&matching_ports(&ports(), "0483:A34C")), vec!["/dev/ttyACM0", "/dev/ttyACM1"]);
+        assert!(matching_ports(&ports(), "0483:a34d").is_empty());
+    }
+
+    #[test]
+    fn more_than_one_candidate_is_ambiguous() {
+        let err = choose_port(&ports(), Some("0483:a34c")).unwrap_err();
+        assert!(matches!(err, QdxError::DeviceAmbiguous(_)), "{}", err);
+        assert!(err.to_string().contains("/dev/ttyACM1"), "{}", err);
+        // Without --catport, only the product name identifies a QDX.
+        assert_eq!(choose_port(&ports(), None).unwrap().port_name, "/dev/ttyACM0");
+    }
+
+    #[test]
+    fn unmatched_paths_are_used_as_is() {
+        let port = choose_port(&ports(), Some("/dev/pts/7")).unwrap();
+        assert_eq!(port, SerialPortInfo { port_name: "/dev/pts/7".to_string(), port_type: SerialPortType::Unknown });
+        let err = choose_port(&ports(), Some("ttyACM7")).unwrap_err();
+        assert!(matches!(err, QdxError::DeviceNotFound(_)), "{}", err);
+    }
+}
@@ -0,0 +1 @@
+pub mod simulator;
\ No newline at end of file
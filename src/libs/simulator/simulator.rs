@@ -0,0 +1,203 @@
+// -------------------------------------------------------------------------------------------------
+// QDX CAT SIMULATOR
+// -------------------------------------------------------------------------------------------------
+
+use std::time::Duration;
+use log::debug;
+use crate::libs::random::random::XorShift;
+
+// The TS-480's identification, which the QDX reports.
+const IDENTIFICATION: u32 = 20;
+
+// The USB audio gain (0 to 255) the simulated QDX starts with.
+const AUDIO_GAIN: u8 = 128;
+
+// The reply to a request that isn't understood, as the QDX gives.
+const ERROR_REPLY: &str = "?;";
+
+// The state of a QDX, as seen through the subset of the TS-480 CAT commands it implements.
+pub struct SimulatedQdx {
+    vfo_a_hz: u32,
+    vfo_b_hz: u32,
+    mode: char,
+    receive_vfo: char,
+    transmit_vfo: char,
+    rit_on: bool,
+    transmitting: bool,
+    audio_gain: u8,
+}
+
+impl SimulatedQdx {
+    pub fn new(frequency_hz: u32) -> Self {
+        Self {
+            vfo_a_hz: frequency_hz,
+            vfo_b_hz: frequency_hz,
+            mode: '2', // USB
+            receive_vfo: '0',
+            transmit_vfo: '0',
+            rit_on: false,
+            transmitting: false,
+            audio_gain: AUDIO_GAIN,
+        }
+    }
+
+    // Handles one ;-terminated request, returning the reply if it has one. Reads are replied to;
+    // sets aren't, unless they're invalid.
+    pub fn respond(&mut self, request: &str) -> Option<String> {
+        debug!("Simulator request '{}'", request);
+        let body = match request.strip_suffix(';') {
+            Some(body) if body.len() >= 2 && body.is_char_boundary(2) => body,
+            _ => return Some(ERROR_REPLY.to_string()),
+        };
+        let (command, parameter) = body.split_at(2);
+        // None if invalid, otherwise the reply, if any.
+        let reply: Option<Option<String>> = match (command, parameter) {
+            ("FA", "") => Some(Some(format!("FA{:011};", self.vfo_a_hz))),
+            ("FB", "") => Some(Some(format!("FB{:011};", self.vfo_b_hz))),
+            ("FA", digits) => Self::parse_frequency(digits).map(|hz| { self.vfo_a_hz = hz; None }),
+            ("FB", digits) => Self::parse_frequency(digits).map(|hz| { self.vfo_b_hz = hz; None }),
+            ("IF", "") => Some(Some(self.if_status())),
+            ("MD", "") => Some(Some(format!("MD{};", self.mode))),
+            ("MD", code) if code.len() == 1 && "1234579".contains(code) => {
+                self.mode = code.chars().next().unwrap();
+                Some(None)
+            }
+            ("FR", "") => Some(Some(format!("FR{};", self.receive_vfo))),
+            ("FT", "") => Some(Some(format!("FT{};", self.transmit_vfo))),
+            ("FR", code) if code == "0" || code == "1" => {
+                self.receive_vfo = code.chars().next().unwrap();
+                Some(None)
+            }
+            ("FT", code) if code == "0" || code == "1" => {
+                self.transmit_vfo = code.chars().next().unwrap();
+                Some(None)
+            }
+            ("RT", "") => Some(Some(format!("RT{};", if self.rit_on { 1 } else { 0 }))),
+            ("RT", code) if code == "0" || code == "1" => {
+                self.rit_on = code == "1";
+                Some(None)
+            }
+            // The QDX's USB audio gain, as the main receiver's AF gain.
+            ("AG", "0") => Some(Some(format!("AG0{:03};", self.audio_gain))),
+            ("AG", gain) => Self::parse_audio_gain(gain).map(|gain| { self.audio_gain = gain; None }),
+            ("ID", "") => Some(Some(format!("ID{:03};", IDENTIFICATION))),
+            ("TX", "") => {
+                self.transmitting = true;
+                Some(None)
+            }
+            ("RX", "") => {
+                self.transmitting = false;
+                Some(None)
+            }
+            _ => None,
+        };
+        reply.unwrap_or_else(|| Some(ERROR_REPLY.to_string()))
+    }
+
+    fn parse_frequency(digits: &str) -> Option<u32> {
+        if digits.len() == 11 && digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse::<u32>().ok()
+        } else {
+            None
+        }
+    }
+
+    fn parse_audio_gain(parameter: &str) -> Option<u8> {
+        match parameter.strip_prefix('0') {
+            Some(digits) if digits.len() == 3 && digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse::<u8>().ok(),
+            _ => None,
+        }
+    }
+
+    fn if_status(&self) -> String {
+        let frequency_hz = if self.receive_vfo == '1' { self.vfo_b_hz } else { self.vfo_a_hz };
+        format!("IF{:011}     {:+05}{}0000{}{}{}0{}000 ;",
+            frequency_hz,
+            0,
+            if self.rit_on { 1 } else { 0 },
+            if self.transmitting { 1 } else { 0 },
+            self.mode,
+            self.receive_vfo,
+            if self.receive_vfo != self.transmit_vfo { 1 } else { 0 })
+    }
+}
+
+// How badly the simulated QDX's replies are delivered.
+#[derive(Clone, Debug)]
+pub struct Faults {
+    pub latency: Duration,
+    pub drop_rate: f64, // Chance of each reply byte being lost.
+    pub malformed_rate: f64, // Chance of a reply being corrupted.
+}
+
+pub struct FaultInjector {
+    faults: Faults,
+    random: XorShift,
+}
+
+impl FaultInjector {
+    pub fn new(faults: Faults, seed: u64) -> Self {
+        Self { faults, random: XorShift::new(seed) }
+    }
+
+    pub fn get_latency(&self) -> Duration {
+        self.faults.latency
+    }
+
+    // Returns the bytes to send for a reply, after corruption and loss.
+    pub fn apply(&mut self, reply: &str) -> Vec<u8> {
+        let mut bytes = reply.as_bytes().to_vec();
+        if bytes.len() > 1 && self.random.next_f64() < self.faults.malformed_rate {
+            // Anything but the terminator, so it's received but can't be parsed.
+            let position = (self.random.next_u64() % (bytes.len() as u64 - 1)) as usize;
+            bytes[position] = b'#';
+            debug!("Simulator malformed reply '{}'", String::from_utf8_lossy(&bytes));
+        }
+        let drop_rate = self.faults.drop_rate;
+        let before = bytes.len();
+        bytes.retain(|_| self.random.next_f64() >= drop_rate);
+        if bytes.len() != before {
+            debug!("Simulator dropped {} bytes", before - bytes.len());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::libs::simulator::simulator::{FaultInjector, Faults, SimulatedQdx};
+
+    #[test]
+    fn reads_and_sets() {
+        let mut qdx = SimulatedQdx::new(14074000);
+        assert_eq!(qdx.respond("FA;"), Some("FA00014074000;".to_string()));
+        assert_eq!(qdx.respond("FA00007074000;"), None);
+        assert_eq!(qdx.respond("FA;"), Some("FA00007074000;".to_string()));
+        assert_eq!(qdx.respond("MD3;"), None);
+        assert_eq!(qdx.respond("IF;"), Some("IF00007074000     +00000000003000000 ;".to_string()));
+        assert_eq!(qdx.respond("XX;"), Some("?;".to_string()));
+    }
+
+    #[test]
+    fn audio_gain() {
+        let mut qdx = SimulatedQdx::new(14074000);
+        assert_eq!(qdx.respond("AG0;"), Some("AG0128;".to_string()));
+        assert_eq!(qdx.respond("AG0064;"), None);
+        assert_eq!(qdx.respond("AG0;"), Some("AG0064;".to_string()));
+        for invalid in ["AG;", "AG1;", "AG0256;", "AG064;", "AG1064;"] {
+            assert_eq!(qdx.respond(invalid), Some("?;".to_string()), "{}", invalid);
+        }
+        assert_eq!(qdx.respond("AG0;"), Some("AG0064;".to_string()));
+    }
+
+    #[test]
+    fn faults_are_repeatable() {
+        let faults = Faults { latency: Duration::ZERO, drop_rate: 0.2, malformed_rate: 0.5 };
+        let mut first = FaultInjector::new(faults.clone(), 42);
+        let mut second = FaultInjector::new(faults, 42);
+        for _ in 0..10 {
+            assert_eq!(first.apply("FA00014074000;"), second.apply("FA00014074000;"));
+        }
+    }
+}
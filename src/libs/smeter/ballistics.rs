@@ -0,0 +1,189 @@
+// -------------------------------------------------------------------------------------------------
+// S-METER BALLISTICS
+// -------------------------------------------------------------------------------------------------
+
+use std::collections::VecDeque;
+use serde::{Deserialize, Serialize};
+use crate::libs::audio::audio::SAMPLE_RATE;
+
+// The time constants of the averaging needles.
+const FAST_SECONDS: f32 = 0.05;
+const SLOW_SECONDS: f32 = 0.5;
+
+// How quickly the needle falls back from a peak, when holding peaks.
+const PEAK_DECAY_DB_PER_SECOND: f32 = 6.0;
+
+// The marker at the highest reading holds there for a while, then falls back slowly.
+const MARKER_HOLD_SECONDS: f32 = 2.0;
+const MARKER_DECAY_DB_PER_SECOND: f32 = 3.0;
+
+// The RMS is taken over slices of this many samples (0.1s), so that only a few are kept.
+const RMS_SLICE_SAMPLES: usize = 4800;
+const RMS_SLICES_PER_SECOND: usize = 10;
+pub const MAX_RMS_SECONDS: u32 = 10;
+const MAX_RMS_SLICES: usize = MAX_RMS_SECONDS as usize * RMS_SLICES_PER_SECOND;
+
+// How the needle follows the signal.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MeterBallistics {
+    Fast,
+    Slow,
+    PeakHold, // Jumps to peaks, and falls back slowly.
+    Rms(u32), // Over this many seconds.
+}
+
+pub const METER_BALLISTICS: [MeterBallistics; 6] = [
+    MeterBallistics::Fast,
+    MeterBallistics::Slow,
+    MeterBallistics::PeakHold,
+    MeterBallistics::Rms(1),
+    MeterBallistics::Rms(3),
+    MeterBallistics::Rms(MAX_RMS_SECONDS),
+];
+
+impl MeterBallistics {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MeterBallistics::Fast => "Fast",
+            MeterBallistics::Slow => "Slow",
+            MeterBallistics::PeakHold => "Peak hold",
+            MeterBallistics::Rms(1) => "RMS over 1s",
+            MeterBallistics::Rms(3) => "RMS over 3s",
+            MeterBallistics::Rms(10) => "RMS over 10s",
+            MeterBallistics::Rms(_) => "RMS",
+        }
+    }
+}
+
+// Turns the power of successive blocks of samples into what the meter's needle and its peak-hold
+// marker show. Power is the mean square of the samples throughout.
+#[derive(Clone)]
+pub struct Ballistics {
+    ballistics: MeterBallistics,
+    level: f32, // At the needle.
+    peak: f32, // At the marker.
+    peak_hold_seconds: f32, // Until the marker starts to fall.
+    slices: VecDeque<f32>, // The power in the most recent slices, for the RMS.
+    slice_sum: f32,
+    slice_samples: usize,
+}
+
+impl Ballistics {
+    pub fn new(ballistics: MeterBallistics) -> Self {
+        Self {
+            ballistics,
+            level: 0.0,
+            peak: 0.0,
+            peak_hold_seconds: 0.0,
+            slices: VecDeque::with_capacity(MAX_RMS_SLICES),
+            slice_sum: 0.0,
+            slice_samples: 0,
+        }
+    }
+
+    pub fn set_ballistics(&mut self, ballistics: MeterBallistics) {
+        self.ballistics = ballistics;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    // The power of the latest block, of this many samples. The slices are kept up to date
+    // whatever the ballistics, so the RMS is ready as soon as it's chosen.
+    pub fn process(&mut self, power: f32, samples: usize) {
+        let seconds = (samples as f64 / SAMPLE_RATE) as f32;
+        self.slice_sum += power * samples as f32;
+        self.slice_samples += samples;
+        if self.slice_samples >= RMS_SLICE_SAMPLES {
+            if self.slices.len() == MAX_RMS_SLICES {
+                self.slices.pop_front();
+            }
+            self.slices.push_back(self.slice_sum / self.slice_samples as f32);
+            self.slice_sum = 0.0;
+            self.slice_samples = 0;
+        }
+
+        self.level = match self.ballistics {
+            MeterBallistics::Fast => self.level + (power - self.level) * smoothing(FAST_SECONDS, seconds),
+            MeterBallistics::Slow => self.level + (power - self.level) * smoothing(SLOW_SECONDS, seconds),
+            MeterBallistics::PeakHold => power.max(self.level * decay(PEAK_DECAY_DB_PER_SECOND, seconds)),
+            MeterBallistics::Rms(rms_seconds) => {
+                let count = (rms_seconds.clamp(1, MAX_RMS_SECONDS) as usize * RMS_SLICES_PER_SECOND).min(self.slices.len());
+                if count == 0 {
+                    power
+                } else {
+                    self.slices.iter().rev().take(count).sum::<f32>() / count as f32
+                }
+            }
+        };
+
+        if self.level >= self.peak {
+            self.peak = self.level;
+            self.peak_hold_seconds = MARKER_HOLD_SECONDS;
+        } else if self.peak_hold_seconds > 0.0 {
+            self.peak_hold_seconds -= seconds;
+        } else {
+            self.peak = self.level.max(self.peak * decay(MARKER_DECAY_DB_PER_SECOND, seconds));
+        }
+    }
+}
+
+// The smoothing factor, for a time constant, over this long.
+fn smoothing(time_constant_seconds: f32, seconds: f32) -> f32 {
+    1.0 - (-seconds / time_constant_seconds).exp()
+}
+
+// The factor by which power falls at this rate, over this long.
+fn decay(db_per_second: f32, seconds: f32) -> f32 {
+    10.0_f32.powf(-db_per_second * seconds / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::audio::audio::SAMPLE_RATE;
+    use crate::libs::smeter::ballistics::{Ballistics, MeterBallistics};
+
+    // This power, for this long, in callback-sized blocks.
+    fn step(ballistics: &mut Ballistics, power: f32, seconds: f32) {
+        for _ in 0..(seconds * SAMPLE_RATE as f32 / 64.0) as usize {
+            ballistics.process(power, 64);
+        }
+    }
+
+    #[test]
+    fn fast_slow_and_rms_follow_a_step_at_different_speeds() {
+        let mut fast = Ballistics::new(MeterBallistics::Fast);
+        let mut slow = Ballistics::new(MeterBallistics::Slow);
+        let mut rms = Ballistics::new(MeterBallistics::Rms(3));
+        for ballistics in [&mut fast, &mut slow, &mut rms] {
+            step(ballistics, 1.0, 0.2);
+        }
+        assert!(fast.level() > 0.95, "{}", fast.level());
+        assert!(slow.level() > 0.3 && slow.level() < 0.4, "{}", slow.level());
+        assert!((rms.level() - 1.0).abs() < 0.01, "{}", rms.level()); // All it's seen so far.
+        for ballistics in [&mut fast, &mut slow, &mut rms] {
+            step(ballistics, 0.0, 1.0);
+        }
+        assert!(fast.level() < 0.001, "{}", fast.level());
+        assert!((rms.level() - 0.2 / 1.2).abs() < 0.02, "{}", rms.level());
+    }
+
+    #[test]
+    fn peaks_are_held_then_fall_back() {
+        let mut ballistics = Ballistics::new(MeterBallistics::PeakHold);
+        step(&mut ballistics, 1.0, 0.01);
+        step(&mut ballistics, 0.0, 1.0);
+        // The needle has fallen by 6dB...
+        assert!((ballistics.level() - 0.25).abs() < 0.01, "{}", ballistics.level());
+        // ... but the marker is still held...
+        assert_eq!(ballistics.peak(), 1.0);
+        // ... until two seconds have passed, when it falls at 3dB a second.
+        step(&mut ballistics, 0.0, 2.0);
+        assert!((ballistics.peak() - 0.5).abs() < 0.02, "{}", ballistics.peak());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod ballistics;
+pub mod smeter;
\ No newline at end of file
@@ -0,0 +1,113 @@
+// -------------------------------------------------------------------------------------------------
+// S-METER
+// -------------------------------------------------------------------------------------------------
+
+pub const S9_DBM: f32 = -73.0;
+pub const DB_PER_S_UNIT: f32 = 6.0;
+
+// dBm = dBFS + the calibration. The QDX's gain varies by band, so this is only a starting point;
+// it can be calibrated per band against a signal generator.
+pub const DEFAULT_CALIBRATION_DB: f32 = -40.0;
+
+// Reported for silence, rather than -infinity.
+const FLOOR_DBFS: f32 = -150.0;
+
+// The meter image's scale marks, as dB relative to S9, and how far across the needle's travel
+// they are.
+const SCALE_MARKS: [(f32, f32); 8] = [
+    (-8.0 * DB_PER_S_UNIT, 0.0), // S1
+    (-6.0 * DB_PER_S_UNIT, 0.11), // S3
+    (-4.0 * DB_PER_S_UNIT, 0.25), // S5
+    (-2.0 * DB_PER_S_UNIT, 0.40), // S7
+    (0.0, 0.53), // S9
+    (10.0, 0.66),
+    (30.0, 0.85),
+    (60.0, 1.0),
+];
+
+// A signal's power as received, in dBFS (where a full-scale square wave is 0dBFS), and as
+// calibrated, in dBm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SignalLevel {
+    pub dbfs: f32,
+    pub dbm: f32,
+}
+
+impl SignalLevel {
+    pub fn new(dbfs: f32, calibration_db: f32) -> Self {
+        Self { dbfs, dbm: dbfs + calibration_db }
+    }
+
+    // From the mean of the squares of the samples.
+    pub fn from_mean_square(mean_square: f32, calibration_db: f32) -> Self {
+        let dbfs = if mean_square > 0.0 { (10.0 * mean_square.log10()).max(FLOOR_DBFS) } else { FLOOR_DBFS };
+        Self::new(dbfs, calibration_db)
+    }
+
+    // S9 is 9; below S0, 0.
+    pub fn s_units(&self) -> f32 {
+        (9.0 + (self.dbm - S9_DBM) / DB_PER_S_UNIT).max(0.0)
+    }
+
+    // As shown under the meter, e.g. "S7 -85 dBm" or "S9+20 -53 dBm".
+    pub fn reading(&self) -> String {
+        let over_s9 = self.dbm - S9_DBM;
+        if over_s9 >= 1.0 {
+            format!("S9+{:.0} {:.0} dBm", over_s9, self.dbm)
+        } else {
+            format!("S{} {:.0} dBm", self.s_units().min(9.0).round(), self.dbm)
+        }
+    }
+
+    // How far across the meter's scale the needle is, from 0 to 1.
+    pub fn scale_position(&self) -> f32 {
+        let over_s9 = self.dbm - S9_DBM;
+        let (first_db, first_position) = SCALE_MARKS[0];
+        if over_s9 <= first_db {
+            return first_position;
+        }
+        for pair in SCALE_MARKS.windows(2) {
+            let ((low_db, low_position), (high_db, high_position)) = (pair[0], pair[1]);
+            if over_s9 <= high_db {
+                return low_position + (over_s9 - low_db) / (high_db - low_db) * (high_position - low_position);
+            }
+        }
+        1.0
+    }
+}
+
+// The calibration that makes a signal generator's known level read correctly.
+pub fn calibration_for(generator_dbm: f32, measured: SignalLevel) -> f32 {
+    generator_dbm - measured.dbfs
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::smeter::smeter::{calibration_for, DEFAULT_CALIBRATION_DB, SignalLevel};
+
+    #[test]
+    fn readings_in_s_units_and_dbm() {
+        let s9 = SignalLevel::new(-33.0, DEFAULT_CALIBRATION_DB);
+        assert_eq!(s9.dbm, -73.0);
+        assert_eq!(s9.s_units(), 9.0);
+        assert_eq!(s9.reading(), "S9 -73 dBm");
+        assert_eq!(SignalLevel::new(-45.0, DEFAULT_CALIBRATION_DB).reading(), "S7 -85 dBm");
+        assert_eq!(SignalLevel::new(-13.0, DEFAULT_CALIBRATION_DB).reading(), "S9+20 -53 dBm");
+        assert_eq!(SignalLevel::new(-150.0, DEFAULT_CALIBRATION_DB).s_units(), 0.0);
+        // A full-scale sine is -3dBFS.
+        assert!((SignalLevel::from_mean_square(0.5, 0.0).dbfs + 3.01).abs() < 0.01);
+        assert_eq!(SignalLevel::from_mean_square(0.0, 0.0).dbfs, -150.0);
+    }
+
+    #[test]
+    fn calibration_and_scale() {
+        let measured = SignalLevel::new(-60.0, DEFAULT_CALIBRATION_DB);
+        let calibration = calibration_for(-73.0, measured);
+        assert_eq!(SignalLevel::new(-60.0, calibration).s_units(), 9.0);
+        assert_eq!(SignalLevel::new(-60.0, calibration).scale_position(), 0.53);
+        assert_eq!(SignalLevel::new(-200.0, calibration).scale_position(), 0.0);
+        assert_eq!(SignalLevel::new(0.0, calibration).scale_position(), 1.0);
+        let s8 = SignalLevel::new(-66.0, calibration).scale_position();
+        assert!(s8 > 0.40 && s8 < 0.53, "{}", s8);
+    }
+}
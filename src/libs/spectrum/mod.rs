@@ -0,0 +1 @@
+pub mod spectrum;
\ No newline at end of file
@@ -0,0 +1,117 @@
+// -------------------------------------------------------------------------------------------------
+// SPECTRUM ANALYSIS
+// -------------------------------------------------------------------------------------------------
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_complex::Complex;
+use crate::libs::audio::audio::SAMPLE_RATE;
+
+// At 48kHz, this gives bins about 11.7Hz wide, covering 0-24kHz.
+pub const FFT_SIZE: usize = 4096;
+pub const SPECTRUM_BINS: usize = FFT_SIZE / 2;
+pub const BIN_WIDTH_HZ: f32 = SAMPLE_RATE as f32 / FFT_SIZE as f32;
+
+// What an empty bin shows as, rather than -infinity.
+pub const SPECTRUM_FLOOR_DB: f32 = -160.0;
+
+pub const MAX_AVERAGING: u32 = 20;
+
+// Converts blocks of audio samples into power spectra in dBFS, where a full-scale sine wave reads
+// 0dB. Successive spectra can be averaged, to steady the display.
+pub struct SpectrumAnalyser {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    averaged: Vec<f32>,
+    averaging: u32, // The number of spectra averaged; 1 is none.
+}
+
+impl SpectrumAnalyser {
+    pub fn new() -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+        // Hann window.
+        let window = (0..FFT_SIZE)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        Self {
+            fft,
+            window,
+            buffer: vec![Complex::default(); FFT_SIZE],
+            scratch,
+            averaged: vec![SPECTRUM_FLOOR_DB; SPECTRUM_BINS],
+            averaging: 1,
+        }
+    }
+
+    pub fn set_averaging(&mut self, averaging: u32) {
+        self.averaging = averaging.clamp(1, MAX_AVERAGING);
+    }
+
+    // Precondition: samples holds FFT_SIZE samples. Returns SPECTRUM_BINS levels, from 0Hz.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        for ((bin, sample), window) in self.buffer.iter_mut().zip(samples).zip(&self.window) {
+            *bin = Complex::new(sample * window, 0.0);
+        }
+        self.fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        // A full-scale sine's energy is split between the positive and negative frequency bins,
+        // and the window halves it again.
+        let scale = 4.0 / FFT_SIZE as f32;
+        let smoothing = 1.0 / self.averaging as f32;
+        for (average, bin) in self.averaged.iter_mut().zip(&self.buffer) {
+            let magnitude = bin.norm() * scale;
+            let level = if magnitude > 0.0 { (20.0 * magnitude.log10()).max(SPECTRUM_FLOOR_DB) } else { SPECTRUM_FLOOR_DB };
+            *average += (level - *average) * smoothing;
+        }
+        self.averaged.clone()
+    }
+}
+
+impl Default for SpectrumAnalyser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The audio frequency at the centre of a bin.
+pub fn bin_frequency(bin: usize) -> f32 {
+    bin as f32 * BIN_WIDTH_HZ
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::dsp::test_signals::tone;
+    use crate::libs::spectrum::spectrum::{BIN_WIDTH_HZ, FFT_SIZE, SpectrumAnalyser};
+
+    fn tone_block(frequency_hz: f32, amplitude: f32) -> Vec<f32> {
+        (0..FFT_SIZE).map(|n| tone(frequency_hz, amplitude, n)).collect()
+    }
+
+    fn peak(spectrum: &[f32]) -> usize {
+        spectrum.iter().enumerate().max_by(|a, b| a.1.partial_cmp(b.1).unwrap()).unwrap().0
+    }
+
+    #[test]
+    fn tone_appears_in_its_bin_at_its_level() {
+        let mut analyser = SpectrumAnalyser::new();
+        let bin = 128;
+        let spectrum = analyser.process(&tone_block(bin as f32 * BIN_WIDTH_HZ, 0.1));
+        assert_eq!(peak(&spectrum), bin);
+        assert!((spectrum[bin] - -20.0).abs() < 0.5, "{}", spectrum[bin]);
+        assert!(spectrum[bin * 4] < -60.0);
+    }
+
+    #[test]
+    fn averaging_smooths_changes() {
+        let mut analyser = SpectrumAnalyser::new();
+        analyser.set_averaging(4);
+        let bin = 64;
+        let first = analyser.process(&tone_block(bin as f32 * BIN_WIDTH_HZ, 1.0))[bin];
+        let second = analyser.process(&tone_block(bin as f32 * BIN_WIDTH_HZ, 1.0))[bin];
+        assert!(first < second && second < 0.0);
+    }
+}
@@ -0,0 +1,160 @@
+// -------------------------------------------------------------------------------------------------
+// CONNECTION SUPERVISOR
+// -------------------------------------------------------------------------------------------------
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use log::{debug, info, warn};
+use portaudio::PortAudio;
+use crate::libs::audio::audio::get_duplex_stream_settings;
+use crate::libs::cat::cat::Cat;
+use crate::libs::error::error::QdxError;
+use crate::libs::gui_api::gui_api::{GUIInput, GUIInputMessage};
+use crate::libs::receiver::receiver::Receiver;
+use crate::libs::transport::transport::{open_cat_transport, Transport};
+
+// How often the connection is checked, and while disconnected, how often the QDX is looked for.
+// The audio callback runs hundreds of times in this period, so if it hasn't run at all, the audio
+// device has gone.
+const SUPERVISOR_INTERVAL: Duration = Duration::from_millis(1000);
+
+// The devices given on the command line (or from the configuration), used to find the QDX again.
+#[derive(Clone, Debug)]
+pub struct DeviceSpecs {
+    pub cat_port_device: Option<String>,
+    pub rig_in_device: Option<String>,
+    pub audio_out_device: Option<String>,
+}
+
+// Watches the Receiver's CAT and audio connections to the QDX. If either is lost (e.g. the USB
+// cable is knocked, or the QDX is power-cycled), both are closed, and reopened when the QDX
+// reappears.
+pub struct Supervisor {
+    gui_input: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>,
+    thread_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    pub fn new(terminate: Arc<AtomicBool>, receiver: Arc<Mutex<Receiver>>, device_specs: DeviceSpecs) -> Self {
+        let gui_input_holder: Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>> = Arc::new(Mutex::new(None));
+        let thread_gui_input_holder = gui_input_holder.clone();
+        let thread_handle = thread::spawn(move || {
+            let mut connected = true;
+            let mut last_callback_count = receiver.lock().unwrap().get_callback_count();
+            loop {
+                if terminate.load(Ordering::SeqCst) {
+                    info!("Terminating Supervisor thread");
+                    break;
+                }
+                thread::sleep(SUPERVISOR_INTERVAL);
+
+                if connected {
+                    let mut locked_receiver = receiver.lock().unwrap();
+                    let callback_count = locked_receiver.get_callback_count();
+                    let audio_lost = callback_count == last_callback_count;
+                    last_callback_count = callback_count;
+                    let cat_lost = locked_receiver.is_cat_connection_lost();
+                    if audio_lost || cat_lost {
+                        warn!("Lost connection to QDX (audio lost: {}, CAT lost: {})", audio_lost, cat_lost);
+                        locked_receiver.stop_duplex_callback();
+                        // The serial port is opened exclusively, so it must be closed before it
+                        // can be reopened, even if it was only the audio that was lost.
+                        locked_receiver.disconnect_cat();
+                        drop(locked_receiver);
+                        connected = false;
+                        Self::send(&thread_gui_input_holder, GUIInputMessage::Disconnected);
+                    }
+                } else {
+                    match Self::reconnect(&receiver, &device_specs) {
+                        Ok(()) => {
+                            info!("Reconnected to QDX");
+                            connected = true;
+                            last_callback_count = receiver.lock().unwrap().get_callback_count();
+                            Self::send(&thread_gui_input_holder, GUIInputMessage::Reconnected);
+                        }
+                        Err(err) => {
+                            debug!("QDX not available yet: {}", err);
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            gui_input: gui_input_holder,
+            thread_handle: Mutex::new(Some(thread_handle)),
+        }
+    }
+
+    fn send(gui_input: &Arc<Mutex<Option<Arc<SyncSender<GUIInputMessage>>>>>, message: GUIInputMessage) {
+        if let Some(gui_input) = gui_input.lock().unwrap().as_deref() {
+            let _ = gui_input.send(message);
+        }
+    }
+
+    // Reopens the CAT transport and audio stream. A new PortAudio is needed each time, since
+    // devices are only enumerated when it's initialised.
+    fn reconnect(receiver: &Arc<Mutex<Receiver>>, device_specs: &DeviceSpecs) -> Result<(), QdxError> {
+        let cat = Self::connect_cat(open_cat_transport(device_specs.cat_port_device.as_deref())?)?;
+
+        let pa = PortAudio::new()?;
+        let duplex_settings = get_duplex_stream_settings(&pa, device_specs.rig_in_device.as_deref(), device_specs.audio_out_device.as_deref())?;
+        let mut locked_receiver = receiver.lock().unwrap();
+        locked_receiver.start_duplex_callback(&pa, duplex_settings)?;
+        locked_receiver.reconnect_cat(cat);
+        Ok(())
+    }
+
+    // The serial port may reappear before the QDX is ready to answer, so it's only back once it
+    // identifies itself.
+    fn connect_cat(transport: Box<dyn Transport>) -> Result<Cat, QdxError> {
+        let mut cat = Cat::new(transport);
+        let id = cat.get_identification()?;
+        debug!("QDX identifies as {}", id);
+        Ok(cat)
+    }
+}
+
+impl GUIInput for Supervisor {
+    fn set_gui_input(&mut self, gui_input: Arc<SyncSender<GUIInputMessage>>) {
+        *self.gui_input.lock().unwrap() = Some(gui_input);
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        debug!("Supervisor joining thread handle...");
+        let mut thread_handle = self.thread_handle.lock().unwrap();
+        thread_handle.take().map(JoinHandle::join);
+        debug!("...Supervisor joined thread handle");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libs::error::error::QdxError;
+    use crate::libs::supervisor::supervisor::Supervisor;
+    use crate::libs::transport::transport::MockTransport;
+
+    #[test]
+    fn the_qdx_is_back_once_it_identifies_itself() {
+        let transport = MockTransport::new();
+        assert!(matches!(Supervisor::connect_cat(Box::new(transport.clone())), Err(QdxError::Timeout(_))));
+        transport.expect("ID;", "ID020;");
+        let mut cat = Supervisor::connect_cat(Box::new(transport.clone())).unwrap();
+        transport.expect("FA;", "FA00014074000;");
+        assert_eq!(cat.get_frequency().unwrap(), 14074000);
+        assert_eq!(transport.written(), "ID;ID;FA;");
+    }
+
+    #[test]
+    fn a_closed_port_is_still_lost() {
+        let transport = MockTransport::new();
+        transport.close();
+        let err = Supervisor::connect_cat(Box::new(transport)).err().unwrap();
+        assert!(err.is_connection_lost(), "{}", err);
+    }
+}
@@ -0,0 +1,90 @@
+// -------------------------------------------------------------------------------------------------
+// TCP SERVER FOR LOCAL CLIENTS
+// -------------------------------------------------------------------------------------------------
+
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::libs::error::error::{is_timeout, QdxError};
+
+// How often blocked accepts and reads give up, to check for termination.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Accepts connections from programs on this machine, serving each on its own thread until the
+// client disconnects or termination is requested. The protocol is up to serve_client, which is
+// given a blocking stream whose reads time out every POLL_INTERVAL.
+pub struct TcpServer {
+    protocol: &'static str,
+    thread_handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl TcpServer {
+    pub fn new<F>(terminate: Arc<AtomicBool>, protocol: &'static str, port: u16, serve_client: F) -> Result<Self, QdxError>
+    where F: Fn(Arc<AtomicBool>, TcpStream) -> Result<(), QdxError> + Send + Sync + 'static {
+        // Only local programs are served; the protocols have no authentication.
+        let listener = match TcpListener::bind(("127.0.0.1", port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                return Err(QdxError::DeviceOpenFailed(format!("Can't listen for {} clients on port {}: {}", protocol, port, e)));
+            }
+        };
+        listener.set_nonblocking(true)?;
+        info!("Listening for {} clients on port {}", protocol, port);
+
+        let serve_client = Arc::new(serve_client);
+        let thread_handle = thread::spawn(move || {
+            let mut client_thread_handles: Vec<JoinHandle<()>> = vec![];
+            loop {
+                if terminate.load(Ordering::SeqCst) {
+                    info!("Terminating {} server thread", protocol);
+                    break;
+                }
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        debug!("{} client connected from {}", protocol, address);
+                        let client_terminate = terminate.clone();
+                        let client_serve_client = serve_client.clone();
+                        client_thread_handles.push(thread::spawn(move || {
+                            let served = stream.set_nonblocking(false)
+                                .and_then(|_| stream.set_read_timeout(Some(POLL_INTERVAL)))
+                                .map_err(QdxError::from)
+                                .and_then(|_| client_serve_client(client_terminate, stream));
+                            if let Err(err) = served {
+                                warn!("{} client failed: {}", protocol, err);
+                            }
+                            debug!("{} client disconnected from {}", protocol, address);
+                        }));
+                    }
+                    Err(e) if is_timeout(&e) => {
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(e) => {
+                        warn!("Could not accept {} client: {}", protocol, e);
+                        thread::sleep(POLL_INTERVAL);
+                    }
+                }
+                client_thread_handles.retain(|handle| !handle.is_finished());
+            }
+            for handle in client_thread_handles {
+                let _ = handle.join();
+            }
+        });
+        Ok(Self {
+            protocol,
+            thread_handle: Mutex::new(Some(thread_handle)),
+        })
+    }
+}
+
+impl Drop for TcpServer {
+    fn drop(&mut self) {
+        debug!("{} TcpServer joining thread handle...", self.protocol);
+        let mut thread_handle = self.thread_handle.lock().unwrap();
+        thread_handle.take().map(JoinHandle::join);
+        debug!("...{} TcpServer joined thread handle", self.protocol);
+    }
+}
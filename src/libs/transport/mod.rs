@@ -0,0 +1 @@
+pub mod transport;
\ No newline at end of file
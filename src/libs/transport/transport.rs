@@ -0,0 +1,310 @@
+// -------------------------------------------------------------------------------------------------
+// CAT TRANSPORT - HOW CAT COMMANDS REACH THE RADIO
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::info;
+use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortSettings, StopBits};
+use crate::libs::error::error::QdxError;
+use crate::libs::serial::serial::find_qdx_serial_port;
+
+const TIMEOUT: Duration = Duration::from_millis(250);
+
+// A --catport of tcp:host:port connects to a remote rig, e.g. one shared with ser2net.
+const TCP_PREFIX: &str = "tcp:";
+
+// Cat talks to the radio through one of these. Reads must time out (returning an Err) rather than
+// block forever if the radio doesn't respond.
+pub trait Transport: Read + Write + Send {
+    fn timeout(&self) -> Duration;
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), QdxError>;
+}
+
+// Opens the transport described by a --catport value: tcp:host:port, or a serial port as found by
+// find_qdx_serial_port.
+pub fn open_cat_transport(port_spec: Option<&str>) -> Result<Box<dyn Transport>, QdxError> {
+    if let Some(address) = port_spec.and_then(|spec| spec.strip_prefix(TCP_PREFIX)) {
+        return Ok(Box::new(TcpTransport::connect(address)?));
+    }
+    let serial_port = find_qdx_serial_port(port_spec)?;
+    Ok(Box::new(SerialTransport::open(&serial_port.port_name)?))
+}
+
+// -------------------------------------------------------------------------------------------------
+// Serial port, e.g. the QDX's USB virtual COM port.
+pub struct SerialTransport {
+    serial_port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    pub fn open(port_name: &str) -> Result<Self, QdxError> {
+        info!("Opening serial port {}", port_name);
+        let settings = SerialPortSettings {
+            baud_rate: 38400, // it's irrelevant over USB
+            data_bits: DataBits::Eight,
+            flow_control: FlowControl::Hardware,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            timeout: TIMEOUT,
+        };
+        match serialport::open_with_settings(port_name, &settings) {
+            Ok(serial_port) => {
+                info!("Port open");
+                Ok(Self { serial_port })
+            }
+            Err(e) => {
+                Err(QdxError::DeviceOpenFailed(format!("Failed to open serial port {}: {}", port_name, e)))
+            }
+        }
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.serial_port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.serial_port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.serial_port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn timeout(&self) -> Duration {
+        self.serial_port.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), QdxError> {
+        self.serial_port.set_timeout(timeout)?;
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// TCP socket, for a rig whose serial port is served over the network, e.g. by ser2net.
+pub struct TcpTransport {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl TcpTransport {
+    pub fn connect(address: &str) -> Result<Self, QdxError> {
+        info!("Connecting to {}", address);
+        match TcpStream::connect(address) {
+            Ok(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                info!("Connected");
+                Ok(Self { stream, timeout: TIMEOUT })
+            }
+            Err(e) => {
+                Err(QdxError::DeviceNotFound(format!("Failed to connect to {}: {}", address, e)))
+            }
+        }
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), QdxError> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// Stands in for a radio that has been lost, so that its port can be closed while the Cat that used
+// it is still shared. Everything but flushing fails.
+pub struct DisconnectedTransport;
+
+impl Read for DisconnectedTransport {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "QDX disconnected"))
+    }
+}
+
+impl Write for DisconnectedTransport {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "QDX disconnected"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for DisconnectedTransport {
+    fn timeout(&self) -> Duration {
+        TIMEOUT
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> Result<(), QdxError> {
+        Ok(())
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// In-memory scripted radio, for testing. Each expected request is answered with its scripted
+// response when it has been completely written; anything else gets no response, so reads time out
+// immediately. Clones share the same script, so a test can keep one to inspect what was written.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct MockState {
+    script: VecDeque<(Vec<u8>, Vec<u8>)>,
+    request: Vec<u8>,
+    response: VecDeque<u8>,
+    written: Vec<u8>,
+    timeout: Duration,
+    closed: bool,
+    time_out_next_write: bool,
+}
+
+#[cfg(test)]
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // When request has been written, make response available to read.
+    pub fn expect(&self, request: &str, response: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.script.push_back((request.as_bytes().to_vec(), response.as_bytes().to_vec()));
+    }
+
+    // Make response available to read now, as if the radio sent it unprompted.
+    pub fn respond(&self, response: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.response.extend(response.as_bytes());
+    }
+
+    // Behave like a radio that has been unplugged.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+    }
+
+    // Behave like a radio that is too busy to accept the next write.
+    pub fn time_out_next_write(&self) {
+        self.state.lock().unwrap().time_out_next_write = true;
+    }
+
+    // Everything written so far.
+    pub fn written(&self) -> String {
+        let state = self.state.lock().unwrap();
+        String::from_utf8_lossy(&state.written).to_string()
+    }
+
+    // How many clones are open, including this one; a closed port's clone has been dropped.
+    pub fn handle_count(&self) -> usize {
+        Arc::strong_count(&self.state)
+    }
+
+    // Have all the expected requests been made?
+    pub fn is_script_complete(&self) -> bool {
+        self.state.lock().unwrap().script.is_empty()
+    }
+}
+
+#[cfg(test)]
+impl Read for MockTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Ok(0);
+        }
+        if state.response.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            match state.response.pop_front() {
+                Some(byte) => {
+                    buf[count] = byte;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+impl Write for MockTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Mock transport closed"));
+        }
+        if std::mem::take(&mut state.time_out_next_write) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Operation timed out"));
+        }
+        state.written.extend_from_slice(buf);
+        for byte in buf {
+            state.request.push(*byte);
+            if *byte == b';' {
+                let request = std::mem::take(&mut state.request);
+                let expected = state.script.front().map(|(expected, _)| *expected == request).unwrap_or(false);
+                if expected {
+                    let (_, response) = state.script.pop_front().unwrap();
+                    state.response.extend(response);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn timeout(&self) -> Duration {
+        self.state.lock().unwrap().timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), QdxError> {
+        self.state.lock().unwrap().timeout = timeout;
+        Ok(())
+    }
+}
@@ -16,15 +16,23 @@ use clap::{App, Arg, ArgMatches};
 use clap::arg_enum;
 use fltk::app;
 use fltk::app::Scheme;
-use log::{debug, error, info};
-use portaudio::{DuplexStreamSettings, PortAudio};
-use qdx_receiver::libs::audio::audio::{get_qdx_input_device, get_speaker_output_device, list_audio_devices};
+use log::{debug, error, info, warn};
+use portaudio::PortAudio;
+use qdx_receiver::libs::audio::audio::{get_duplex_stream_settings, list_audio_devices};
 use qdx_receiver::libs::cat::cat::Cat;
+use qdx_receiver::libs::config::config::{config_file_path, ConfigurationStore};
+use qdx_receiver::libs::config_dir::config_dir;
+use qdx_receiver::libs::error::error::QdxError;
 use qdx_receiver::libs::fakereceiver::fakereceiver::FakeReceiver;
+use qdx_receiver::libs::flrig::flrig::{DEFAULT_FLRIG_PORT, FlrigServer};
 use qdx_receiver::libs::gui::gui::Gui;
 use qdx_receiver::libs::gui_api::gui_api::{GUIInput, GUIOutput};
-use qdx_receiver::libs::receiver::receiver::Receiver;
-use qdx_receiver::libs::serial::serial::find_qdx_serial_port;
+#[cfg(target_os = "linux")]
+use qdx_receiver::libs::pty::pty::PtyServer;
+use qdx_receiver::libs::receiver::receiver::{Receiver, VolumeControl};
+use qdx_receiver::libs::rigctld::rigctld::{DEFAULT_RIGCTLD_PORT, RigctldServer};
+use qdx_receiver::libs::supervisor::supervisor::{DeviceSpecs, Supervisor};
+use qdx_receiver::libs::transport::transport::open_cat_transport;
 
 // -------------------------------------------------------------------------------------------------
 // COMMAND LINE HANDLING AND LOGGING
@@ -41,24 +49,32 @@ pub fn initialise_logging() {
 }
 
 #[cfg(windows)]
-const CAT_HELP: &str = "Sets the port that the QDX CAT interface is available on, e.g. COM4:";
+const CAT_HELP: &str = "Sets the port that the QDX CAT interface is available on, e.g. COM4:, or its USB serial number, or USB vid:pid, or tcp:host:port";
 #[cfg(windows)]
 const CAT_VALUE_NAME: &str = "COM port";
 
 #[cfg(not(windows))]
-const CAT_HELP: &str = "Sets the port that the QDX CAT interface is available on, e.g. /dev/cu-usbserial-1410";
+const CAT_HELP: &str = "Sets the port that the QDX CAT interface is available on, e.g. /dev/cu-usbserial-1410, or its USB serial number, or USB vid:pid, or tcp:host:port";
 #[cfg(not(windows))]
 const CAT_VALUE_NAME: &str = "serial character device";
 
 const CAT_PORT_DEVICE: &str = "cat-port-device";
 const AUDIO_OUT_DEVICE: &str = "audio-out-device";
 const RIG_IN_DEVICE: &str = "rig-in-device";
+const FAKE_RECEIVER: &str = "fake-receiver";
+const RIGCTLD_PORT: &str = "rigctld-port";
+const FLRIG_PORT: &str = "flrig-port";
+const PTY: &str = "pty";
+const VOLUME_CONTROL: &str = "volume-control";
+
+// The frequency the fake receiver starts on.
+const FAKE_FREQUENCY: u32 = 14074000;
 
 arg_enum! {
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum Mode {
         GUI,
-        // ConfigFileLocation,
+        ConfigFileLocation,
         ListAudioDevices,
     }
 }
@@ -79,12 +95,30 @@ fn parse_command_line<'a>() -> (ArgMatches<'a>, Mode) {
             .takes_value(true))
 
         .arg(Arg::with_name(AUDIO_OUT_DEVICE)
-            .short("a").long("audioout").help("Sets the audio device name to use for the speaker/headphone output")
-            .value_name("speaker/headphone audio output device name").takes_value(true))
+            .short("a").long("audioout").help("Sets the audio device to use for the speaker/headphone output, as a name, part of a name, or index shown by ListAudioDevices; defaults to the system's default output")
+            .value_name("speaker/headphone audio output device").takes_value(true))
 
         .arg(Arg::with_name(RIG_IN_DEVICE)
-            .short("r").long("rigaudioin").help("Sets the audio device name to use for input from the transceiver")
-            .value_name("transceiver audio input device name").takes_value(true))
+            .short("r").long("rigaudioin").help("Sets the audio device to use for input from the transceiver, as a name, part of a name, or index shown by ListAudioDevices; defaults to the first device named QDX")
+            .value_name("transceiver audio input device").takes_value(true))
+
+        .arg(Arg::with_name(VOLUME_CONTROL)
+            .long("volume").help("Sets what the volume control adjusts: the audio in qdx-receiver (Software), the QDX's USB audio gain (Rig), or both")
+            .value_name("control").possible_values(&VolumeControl::variants()).default_value("Software").case_insensitive(true))
+
+        .arg(Arg::with_name(FAKE_RECEIVER)
+            .short("f").long("fake").help("Uses a fake receiver instead of the QDX, so no serial port or audio devices are needed"))
+
+        .arg(Arg::with_name(RIGCTLD_PORT)
+            .long("rigctld").help("Shares the QDX with other programs by serving the Hamlib rigctld protocol on this TCP port (default 4532)")
+            .value_name("port").takes_value(true).min_values(0).max_values(1))
+
+        .arg(Arg::with_name(FLRIG_PORT)
+            .long("flrig").help("Shares the QDX with other programs by serving flrig's XML-RPC API on this TCP port (default 12345)")
+            .value_name("port").takes_value(true).min_values(0).max_values(1))
+
+        .arg(Arg::with_name(PTY)
+            .long("pty").help("Shares the QDX with other programs through a virtual serial port (pseudo-terminal), whose name is logged; Linux only"))
 
         .get_matches();
 
@@ -93,28 +127,36 @@ fn parse_command_line<'a>() -> (ArgMatches<'a>, Mode) {
     (result, mode)
 }
 
+// For options that take an optional port number.
+fn port_argument(arguments: &ArgMatches, name: &str, default_port: u16) -> Result<u16, Box<dyn Error>> {
+    match arguments.value_of(name) {
+        None => Ok(default_port),
+        Some(port) => match port.parse::<u16>() {
+            Ok(port) => Ok(port),
+            Err(_) => Err(format!("Invalid port number '{}'", port).into()),
+        },
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // MAIN
 // -------------------------------------------------------------------------------------------------
 
-fn run(_arguments: ArgMatches, mode: Mode, app: Option<fltk::app::App>) -> Result<i32, Box<dyn Error>> {
-    // let home_dir = dirs::home_dir();
-    // let config_path = config_dir::configuration_directory(home_dir)?;
-    // let config_path_clone = config_path.clone();
-    // let mut config = ConfigurationStore::new(config_path).unwrap();
-    // let config_file_path = config.get_config_file_path();
-    //
-    // if mode == Mode::ConfigFileLocation {
-    //     info!("Configuration path is [{:?}]", config_path_clone);
-    //     info!("Configuration file is [{:?}]", config_file_path);
-    //     return Ok(0)
-    // }
+fn run(arguments: ArgMatches, mode: Mode, app: Option<fltk::app::App>) -> Result<i32, Box<dyn Error>> {
+    let config_dir = dirs::config_dir();
+    let config_path = config_dir::configuration_directory(config_dir)?;
 
-    let amplitude: f32 = 1.0; // Max; TODO take from config
+    // Without reading the file, which may be what's wrong.
+    if mode == Mode::ConfigFileLocation {
+        info!("Configuration path is [{:?}]", config_path);
+        info!("Configuration file is [{:?}]", config_file_path(&config_path));
+        return Ok(0)
+    }
 
-    let pa = PortAudio::new()?;
+    let mut config = ConfigurationStore::new(config_path)?;
 
     if mode == Mode::ListAudioDevices {
+        let pa = PortAudio::new()?;
         list_audio_devices(&pa)?;
         return Ok(0)
     }
@@ -125,49 +167,112 @@ fn run(_arguments: ArgMatches, mode: Mode, app: Option<fltk::app::App>) -> Resul
     let frequency: u32;
     let receiver_gui_output: Arc<Mutex<dyn GUIOutput>>;
     let receiver_gui_input: Arc<Mutex<dyn GUIInput>>;
-
-    let using_fake_receiver = false;
-    if using_fake_receiver {
-        frequency = 14074000;
+    let mut supervisor: Option<Supervisor> = None;
+    let mut _rigctld_server: Option<RigctldServer> = None;
+    let mut _flrig_server: Option<FlrigServer> = None;
+    #[cfg(target_os = "linux")]
+    let mut pty_server: Option<PtyServer> = None;
+
+    if arguments.is_present(FAKE_RECEIVER) {
+        info!("Using fake receiver");
+        if arguments.is_present(RIGCTLD_PORT) || arguments.is_present(FLRIG_PORT) || arguments.is_present(PTY) {
+            warn!("The rigctld, flrig and pty servers need a QDX, so aren't started with the fake receiver");
+        }
         let fake_receiver_terminate = terminate.clone();
-        let receiver = Arc::new(Mutex::new(FakeReceiver::new(fake_receiver_terminate)));
+        let fake_frequency = config.get_frequency().unwrap_or(FAKE_FREQUENCY);
+        let receiver = Arc::new(Mutex::new(FakeReceiver::new(fake_receiver_terminate, fake_frequency)));
+        frequency = receiver.lock().unwrap().get_frequency();
         receiver_gui_output = receiver.clone() as Arc<Mutex<dyn GUIOutput>>;
         receiver_gui_input = receiver.clone() as Arc<Mutex<dyn GUIInput>>;
     } else {
+        // Devices given on the command line are remembered for next time.
+        let cat_port_device = arguments.value_of(CAT_PORT_DEVICE).map(String::from).or_else(|| config.get_cat_port_device());
+        let rig_in_device = arguments.value_of(RIG_IN_DEVICE).map(String::from).or_else(|| config.get_rig_in_device());
+        let audio_out_device = arguments.value_of(AUDIO_OUT_DEVICE).map(String::from).or_else(|| config.get_audio_out_device());
+
         info!("Initialising serial input device...");
-        let serial_port = find_qdx_serial_port()?;
-        let cat = Cat::new(serial_port.port_name)?;
+        let transport = open_cat_transport(cat_port_device.as_deref())?;
+        let cat = Cat::new(transport);
+        config.set_cat_port_device(cat_port_device.clone());
         let arc_mutex_cat = Arc::new(Mutex::new(cat));
 
-        frequency = arc_mutex_cat.lock().unwrap().get_frequency()?;
-        info!("QDX on frequency at {:?}", frequency);
-
-        info!("Initialising QDX input device...");
-        let (_qdx_input, qdx_params) = get_qdx_input_device(&pa)?;
-        info!("Initialising speaker output device...");
-        let (_speaker_output, speaker_params) = get_speaker_output_device(&pa)?;
+        match config.get_frequency() {
+            Some(last_frequency) => {
+                info!("Restoring QDX frequency to {:?}", last_frequency);
+                arc_mutex_cat.lock().unwrap().set_frequency(last_frequency)?;
+                frequency = last_frequency;
+            }
+            None => {
+                frequency = arc_mutex_cat.lock().unwrap().get_frequency()?;
+                info!("QDX on frequency at {:?}", frequency);
+            }
+        }
 
-        pa.is_duplex_format_supported(qdx_params, speaker_params, 48000_f64)?;
-        let duplex_settings = DuplexStreamSettings::new(qdx_params, speaker_params, 48000_f64, 64);
+        let pa = PortAudio::new()?;
+        let duplex_settings = get_duplex_stream_settings(&pa, rig_in_device.as_deref(), audio_out_device.as_deref())?;
+        config.set_rig_in_device(rig_in_device.clone());
+        config.set_audio_out_device(audio_out_device.clone());
 
         let receiver_terminate = terminate.clone();
-        let receiver = Arc::new(Mutex::new(Receiver::new(receiver_terminate, arc_mutex_cat)));
+        let mut volume_control = value_t!(arguments.value_of(VOLUME_CONTROL), VolumeControl).unwrap_or(VolumeControl::Software);
+        if volume_control != VolumeControl::Software {
+            if let Err(err) = arc_mutex_cat.lock().unwrap().get_audio_gain() {
+                warn!("The QDX's audio gain can't be read over CAT, so the volume control will scale the audio instead: {}", err);
+                volume_control = VolumeControl::Software;
+            }
+        }
+        let receiver = Arc::new(Mutex::new(Receiver::new(receiver_terminate, arc_mutex_cat.clone(), frequency, volume_control)));
         receiver_gui_output = receiver.clone() as Arc<Mutex<dyn GUIOutput>>;
         receiver_gui_input = receiver.clone() as Arc<Mutex<dyn GUIInput>>;
 
         info!("Starting duplex callback...");
         receiver.lock().unwrap().start_duplex_callback(&pa, duplex_settings)?;
+
+        // The servers set the QDX's frequency directly, through the shared Cat.
+        let remembered_frequency = receiver.lock().unwrap().remembered_frequency();
+
+        if arguments.is_present(RIGCTLD_PORT) {
+            let port = port_argument(&arguments, RIGCTLD_PORT, DEFAULT_RIGCTLD_PORT)?;
+            _rigctld_server = Some(RigctldServer::new(terminate.clone(), arc_mutex_cat.clone(), remembered_frequency.clone(), port)?);
+        }
+        if arguments.is_present(FLRIG_PORT) {
+            let port = port_argument(&arguments, FLRIG_PORT, DEFAULT_FLRIG_PORT)?;
+            _flrig_server = Some(FlrigServer::new(terminate.clone(), arc_mutex_cat.clone(), remembered_frequency.clone(), port)?);
+        }
+        if arguments.is_present(PTY) {
+            #[cfg(target_os = "linux")]
+            {
+                pty_server = Some(PtyServer::new(terminate.clone(), arc_mutex_cat.clone(), remembered_frequency.clone())?);
+            }
+            #[cfg(not(target_os = "linux"))]
+            warn!("The pty server is only available on Linux");
+        }
+
+        let device_specs = DeviceSpecs { cat_port_device, rig_in_device, audio_out_device };
+        supervisor = Some(Supervisor::new(terminate.clone(), receiver.clone(), device_specs));
     }
 
-    let mut gui = Gui::new(VERSION, receiver_gui_output, gui_terminate, frequency, amplitude);
+    let arc_mutex_config = Arc::new(Mutex::new(config));
+    let mut gui = Gui::new(VERSION, receiver_gui_output, gui_terminate, arc_mutex_config.clone(), frequency);
     let gui_input = gui.gui_input_sender();
-    receiver_gui_input.lock().unwrap().set_gui_input(gui_input);
+    receiver_gui_input.lock().unwrap().set_gui_input(gui_input.clone());
+    if let Some(supervisor) = supervisor.as_mut() {
+        supervisor.set_gui_input(gui_input.clone());
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(pty_server) = pty_server.as_mut() {
+        pty_server.set_gui_input(gui_input);
+    }
 
     info!("Start of app wait loop");
     while app.unwrap().wait() {
         gui.message_handle();
     }
     info!("End of app wait loop");
+    gui.save_window_position();
+    if let Err(err) = arc_mutex_config.lock().unwrap().save() {
+        error!("Could not save configuration: {}", err);
+    }
     terminate.store(true, Ordering::SeqCst);
     info!("Exiting");
     Ok(0)
@@ -186,12 +291,16 @@ fn main() {
 
     match run(arguments, mode, app) {
         Err(err) => {
+            let message = match err.downcast_ref::<QdxError>().and_then(QdxError::advice) {
+                Some(advice) => format!("{}\n\n{}", err, advice),
+                None => format!("{}", err),
+            };
             match mode {
                 Mode::GUI => {
-                    fltk::dialog::message_default(&format!("{}", err));
+                    fltk::dialog::message_default(&message);
                 }
                 _ => {
-                    error!("{}", err);
+                    error!("{}", message);
                 }
             }
         }
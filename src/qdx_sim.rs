@@ -0,0 +1,130 @@
+// -------------------------------------------------------------------------------------------------
+// qdx-sim
+// (C) 2023 Matt Gumbley M0CUV
+// -------------------------------------------------------------------------------------------------
+
+#[macro_use]
+extern crate clap;
+
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches};
+use log::{error, info};
+use qdx_receiver::libs::simulator::simulator::Faults;
+
+// -------------------------------------------------------------------------------------------------
+// COMMAND LINE HANDLING AND LOGGING
+// -------------------------------------------------------------------------------------------------
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub fn initialise_logging() {
+    let log_var_name = "RUST_LOG";
+    if env::var(log_var_name).is_err() {
+        env::set_var(log_var_name, "info")
+    }
+    env_logger::init();
+}
+
+const FREQUENCY: &str = "frequency";
+const LATENCY: &str = "latency";
+const DROP_RATE: &str = "drop-rate";
+const MALFORMED_RATE: &str = "malformed-rate";
+const SEED: &str = "seed";
+
+fn parse_command_line<'a>() -> ArgMatches<'a> {
+    App::new("qdx-sim")
+        .version(VERSION)
+        .author("Matt Gumbley <matt.gumbley@gmail.com>")
+        .about("Simulates a QDX's CAT interface on a pseudo-terminal, whose name is printed on startup")
+
+        .arg(Arg::with_name(FREQUENCY)
+            .short("f").long("frequency").help("Sets the initial frequency in Hz")
+            .value_name("Hz").default_value("14074000").takes_value(true))
+
+        .arg(Arg::with_name(LATENCY)
+            .short("l").long("latency").help("Sets the delay before each reply, in milliseconds")
+            .value_name("ms").default_value("0").takes_value(true))
+
+        .arg(Arg::with_name(DROP_RATE)
+            .short("d").long("drop").help("Sets the chance of each reply byte being lost, from 0.0 to 1.0")
+            .value_name("rate").default_value("0.0").takes_value(true))
+
+        .arg(Arg::with_name(MALFORMED_RATE)
+            .short("m").long("malformed").help("Sets the chance of each reply being corrupted, from 0.0 to 1.0")
+            .value_name("rate").default_value("0.0").takes_value(true))
+
+        .arg(Arg::with_name(SEED)
+            .short("s").long("seed").help("Seeds the random choice of faults, so a run can be repeated")
+            .value_name("seed").default_value("1").takes_value(true))
+
+        .get_matches()
+}
+
+// -------------------------------------------------------------------------------------------------
+// MAIN
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(target_os = "linux")]
+fn simulate(frequency: u32, faults: Faults, seed: u64) -> Result<(), Box<dyn Error>> {
+    use std::io::{Read, Write};
+    use std::thread;
+    use qdx_receiver::libs::pty::pty::{Pty, RequestAssembler};
+    use qdx_receiver::libs::simulator::simulator::{FaultInjector, SimulatedQdx};
+
+    let mut pty = Pty::open()?;
+    info!("Simulated QDX on {}", pty.get_slave_path());
+    // The only thing on stdout, for scripts and tests to find.
+    println!("{}", pty.get_slave_path());
+    std::io::stdout().flush()?;
+
+    let mut qdx = SimulatedQdx::new(frequency);
+    let mut fault_injector = FaultInjector::new(faults, seed);
+    let mut assembler = RequestAssembler::default();
+    let mut buffer = [0u8; 256];
+    loop {
+        if !pty.wait_readable(Duration::from_millis(250))? {
+            continue;
+        }
+        let count = pty.read(&mut buffer)?;
+        for request in assembler.push(&buffer[..count]) {
+            if let Some(reply) = qdx.respond(&request) {
+                thread::sleep(fault_injector.get_latency());
+                pty.write_all(&fault_injector.apply(&reply))?;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn simulate(_frequency: u32, _faults: Faults, _seed: u64) -> Result<(), Box<dyn Error>> {
+    Err("qdx-sim needs Linux pseudo-terminals".into())
+}
+
+fn run(arguments: ArgMatches) -> Result<(), Box<dyn Error>> {
+    let frequency = value_t!(arguments.value_of(FREQUENCY), u32)?;
+    let faults = Faults {
+        latency: Duration::from_millis(value_t!(arguments.value_of(LATENCY), u64)?),
+        drop_rate: value_t!(arguments.value_of(DROP_RATE), f64)?,
+        malformed_rate: value_t!(arguments.value_of(MALFORMED_RATE), f64)?,
+    };
+    let seed = value_t!(arguments.value_of(SEED), u64)?;
+    info!("Simulating with {:?}, seed {}", faults, seed);
+    simulate(frequency, faults, seed)
+}
+
+fn main() {
+    initialise_logging();
+
+    let arguments = parse_command_line();
+    if let Err(err) = run(arguments) {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FIN
+// -------------------------------------------------------------------------------------------------
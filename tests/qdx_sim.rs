@@ -0,0 +1,100 @@
+// -------------------------------------------------------------------------------------------------
+// INTEGRATION TESTS: CAT AGAINST THE QDX SIMULATOR
+// -------------------------------------------------------------------------------------------------
+
+#![cfg(target_os = "linux")]
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use qdx_receiver::libs::cat::cat::{Cat, RigMode, Vfo};
+use qdx_receiver::libs::error::error::QdxError;
+use qdx_receiver::libs::transport::transport::SerialTransport;
+
+// Runs qdx-sim until dropped.
+struct Simulator {
+    child: Child,
+    pty_path: String,
+}
+
+impl Simulator {
+    fn start(args: &[&str]) -> Simulator {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_qdx-sim"))
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Could not start qdx-sim");
+        let mut pty_path = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut pty_path).expect("Could not read qdx-sim's pty");
+        Simulator { child, pty_path: pty_path.trim().to_string() }
+    }
+
+    // As if given with --catport.
+    fn cat(&self) -> Cat {
+        Cat::new(Box::new(SerialTransport::open(&self.pty_path).unwrap()))
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn frequency_round_trip() {
+    let simulator = Simulator::start(&["--frequency", "7074000"]);
+    let mut cat = simulator.cat();
+    assert_eq!(cat.get_frequency().unwrap(), 7074000);
+    cat.set_frequency(14074000).unwrap();
+    assert_eq!(cat.get_frequency().unwrap(), 14074000);
+    cat.set_vfo_frequency(Vfo::B, 10136000).unwrap();
+    assert_eq!(cat.get_vfo_frequency(Vfo::B).unwrap(), 10136000);
+}
+
+#[test]
+fn gain_round_trip() {
+    let simulator = Simulator::start(&[]);
+    let mut cat = simulator.cat();
+    assert_eq!(cat.get_audio_gain().unwrap(), 128);
+    cat.set_audio_gain(200).unwrap();
+    assert_eq!(cat.get_audio_gain().unwrap(), 200);
+    cat.set_audio_gain(0).unwrap();
+    assert_eq!(cat.get_audio_gain().unwrap(), 0);
+}
+
+#[test]
+fn status_and_mode() {
+    let simulator = Simulator::start(&[]);
+    let mut cat = simulator.cat();
+    assert_eq!(cat.get_identification().unwrap(), 20);
+    cat.set_mode(RigMode::Cw).unwrap();
+    assert_eq!(cat.get_mode().unwrap(), RigMode::Cw);
+    let status = cat.get_if_status().unwrap();
+    assert_eq!(status.frequency_hz, 14074000);
+    assert_eq!(status.mode, RigMode::Cw);
+    assert!(!status.transmitting);
+}
+
+#[test]
+fn latency_within_timeout_is_tolerated() {
+    let simulator = Simulator::start(&["--latency", "100"]);
+    let mut cat = simulator.cat();
+    assert_eq!(cat.get_frequency().unwrap(), 14074000);
+}
+
+#[test]
+fn malformed_replies_are_reported() {
+    let simulator = Simulator::start(&["--malformed", "1.0"]);
+    let mut cat = simulator.cat();
+    let err = cat.get_frequency().unwrap_err();
+    assert!(matches!(err, QdxError::MalformedResponse(_) | QdxError::MismatchedResponse { .. }), "{}", err);
+}
+
+#[test]
+fn dropped_replies_time_out() {
+    let simulator = Simulator::start(&["--drop", "1.0"]);
+    let mut cat = simulator.cat();
+    let err = cat.get_frequency().unwrap_err();
+    assert!(matches!(err, QdxError::Timeout(_)), "{}", err);
+}